@@ -0,0 +1,933 @@
+//! Application launcher index
+//!
+//! Indexes the `.desktop` files in the `applications` directory of every
+//! XDG data dir. As the spec asks, an entry in an earlier dir shadows one
+//! with the same id later on (so `Hidden=true` in `~/.local/share` hides a
+//! system app), and entries not meant for this desktop (`OnlyShowIn` /
+//! `NotShowIn`) are dropped. `NoDisplay` entries stay launchable by id but
+//! never show up in searches. Names, keywords and actions are read in the
+//! current locale.
+//!
+//! Searches rank fuzzy matches on name, generic name, keywords and
+//! executable, boosted by how often and how recently an app was launched.
+//! Launch counts are stored in the data directory. Apps are started with
+//! `hyprctl dispatch exec` so Hyprland places them on the active workspace.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::{interval, Duration};
+use tracing::{debug, info};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::hyprland;
+use crate::icons::{AppIcon, IconResolver};
+use crate::module::{self, current_timestamp, ok, JsonFile, Module};
+
+/// How often the application dirs are checked for changes
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct AppsSettings {
+    /// Desktop names for `OnlyShowIn` / `NotShowIn`, empty to use
+    /// `$XDG_CURRENT_DESKTOP`
+    desktops: Vec<String>,
+    /// Command `Terminal=true` apps are run in, followed by `-e`
+    terminal: String,
+    /// Results returned by `apps search`
+    limit: usize,
+}
+
+impl Default for AppsSettings {
+    fn default() -> Self {
+        Self {
+            desktops: Vec::new(),
+            terminal: "ghostty".to_string(),
+            limit: 20,
+        }
+    }
+}
+
+/// A `[Desktop Action ...]` of an entry
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DesktopAction {
+    pub id: String,
+    pub name: String,
+    pub icon: String,
+    #[serde(skip)]
+    pub exec: String,
+}
+
+/// An application from a `.desktop` file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DesktopEntry {
+    /// Desktop file id, e.g. `org.gnome.Nautilus.desktop`
+    pub id: String,
+    pub name: String,
+    pub generic_name: String,
+    pub comment: String,
+    /// Icon name or absolute path, as written in the file
+    pub icon: String,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    pub actions: Vec<DesktopAction>,
+    /// Window class the app is expected to map with
+    pub startup_wm_class: String,
+    #[serde(skip)]
+    pub exec: String,
+    #[serde(skip)]
+    pub terminal: bool,
+    /// Kept out of searches but launchable by id
+    #[serde(skip)]
+    pub no_display: bool,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+impl DesktopEntry {
+    /// Shell command line for the entry or one of its actions
+    fn command(&self, action: Option<&str>, terminal: &str) -> Result<String, String> {
+        let exec = match action {
+            None => &self.exec,
+            Some(id) => {
+                &self
+                    .actions
+                    .iter()
+                    .find(|a| a.id == id)
+                    .ok_or_else(|| format!("no action {} in {}", id, self.id))?
+                    .exec
+            }
+        };
+        let command = expand_exec(exec, self);
+        if command.is_empty() {
+            return Err(format!("{} has no command", self.id));
+        }
+        Ok(if self.terminal {
+            format!("{} -e {}", terminal, command)
+        } else {
+            command
+        })
+    }
+}
+
+/// All installed applications
+#[derive(Debug, Default)]
+pub struct AppIndex {
+    pub entries: Vec<DesktopEntry>,
+}
+
+impl AppIndex {
+    /// Read every dir, earlier dirs shadowing later ones
+    pub fn scan(dirs: &[PathBuf], locales: &[String], desktops: &[String]) -> Self {
+        let mut seen = HashSet::new();
+        let mut entries = Vec::new();
+        for dir in dirs {
+            for (id, path) in desktop_files(dir) {
+                // Claimed even when hidden, that is how Hidden masks
+                if !seen.insert(id.clone()) {
+                    continue;
+                }
+                let Ok(contents) = fs::read_to_string(&path) else {
+                    continue;
+                };
+                if let Some(mut entry) = parse_desktop(&id, &contents, locales, desktops) {
+                    entry.path = path;
+                    entries.push(entry);
+                }
+            }
+        }
+        entries.sort_by_key(|e| e.name.to_lowercase());
+        Self { entries }
+    }
+
+    /// Look up by id, with or without the `.desktop` suffix
+    pub fn get(&self, id: &str) -> Option<&DesktopEntry> {
+        let id = id.strip_suffix(".desktop").unwrap_or(id);
+        self.entries
+            .iter()
+            .find(|e| e.id.strip_suffix(".desktop") == Some(id))
+    }
+
+    /// Visible entries matching `query`, best first. An empty query lists
+    /// apps by launch frequency.
+    pub fn search(&self, query: &str, usage: &AppUsage, now: u64) -> Vec<(&DesktopEntry, f64)> {
+        let query = query.trim().to_lowercase();
+        let mut results: Vec<(&DesktopEntry, f64)> = self
+            .entries
+            .iter()
+            .filter(|e| !e.no_display)
+            .filter_map(|e| {
+                let score = if query.is_empty() {
+                    0.0
+                } else {
+                    match_score(&query, e)?
+                };
+                Some((e, score + usage.boost(&e.id, now)))
+            })
+            .collect();
+        // Stable, so equal scores keep name order
+        results.sort_by(|a, b| b.1.total_cmp(&a.1));
+        results
+    }
+}
+
+impl From<&DesktopEntry> for AppIcon {
+    fn from(entry: &DesktopEntry) -> Self {
+        Self {
+            id: entry
+                .id
+                .strip_suffix(".desktop")
+                .unwrap_or(&entry.id)
+                .to_string(),
+            wm_class: entry.startup_wm_class.clone(),
+            icon: entry.icon.clone(),
+        }
+    }
+}
+
+/// Launch count and time of one app
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub count: u32,
+    /// Unix timestamp of the last launch
+    pub last: u64,
+}
+
+/// Launch history persisted to disk
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppUsage {
+    pub apps: HashMap<String, Usage>,
+}
+
+impl AppUsage {
+    fn record(&mut self, id: &str, now: u64) {
+        let usage = self.apps.entry(id.to_string()).or_default();
+        usage.count += 1;
+        usage.last = now;
+    }
+
+    /// Ranking bonus: grows with the launch count, plus a recency part
+    /// that halves every week
+    fn boost(&self, id: &str, now: u64) -> f64 {
+        let Some(usage) = self.apps.get(id) else {
+            return 0.0;
+        };
+        let weeks = now.saturating_sub(usage.last) as f64 / (7.0 * 86400.0);
+        8.0 * (1.0 + usage.count as f64).ln() + 10.0 * 0.5f64.powf(weeks)
+    }
+}
+
+/// Launcher index with search and launch commands
+pub struct AppsModule {
+    settings: AppsSettings,
+    events: EventBus,
+    icons: Arc<IconResolver>,
+    locales: Vec<String>,
+    desktops: Vec<String>,
+    index: RwLock<AppIndex>,
+    usage: Mutex<AppUsage>,
+    usage_file: JsonFile,
+    /// Fingerprint of the application dirs at the last scan
+    fingerprint: Mutex<u64>,
+}
+
+impl AppsModule {
+    pub fn new(events: EventBus, icons: Arc<IconResolver>, config: &Config) -> Self {
+        let settings: AppsSettings = config.section("apps");
+        let desktops = if settings.desktops.is_empty() {
+            env::var("XDG_CURRENT_DESKTOP")
+                .unwrap_or_else(|_| "Hyprland".to_string())
+                .split(':')
+                .map(str::to_string)
+                .collect()
+        } else {
+            settings.desktops.clone()
+        };
+        let usage_file = JsonFile::new("apps.json");
+        Self {
+            settings,
+            events,
+            icons,
+            locales: current_locales(),
+            desktops,
+            index: RwLock::new(AppIndex::default()),
+            usage: Mutex::new(usage_file.load()),
+            usage_file,
+            fingerprint: Mutex::new(0),
+        }
+    }
+
+    /// Rescan if anything changed since the last scan, or always with `force`
+    async fn refresh(&self, force: bool) {
+        // Both walk every application directory, so they run off the runtime
+        let Ok((dirs, fingerprint)) = tokio::task::spawn_blocking(|| {
+            let dirs = application_dirs();
+            let fingerprint = fingerprint(&dirs);
+            (dirs, fingerprint)
+        })
+        .await
+        else {
+            return;
+        };
+        {
+            let mut last = self.fingerprint.lock().await;
+            if !force && *last == fingerprint {
+                return;
+            }
+            *last = fingerprint;
+        }
+
+        let (locales, desktops) = (self.locales.clone(), self.desktops.clone());
+        let Ok(index) =
+            tokio::task::spawn_blocking(move || AppIndex::scan(&dirs, &locales, &desktops)).await
+        else {
+            return;
+        };
+        let count = index.entries.len();
+        let changed = {
+            let mut current = self.index.write().await;
+            let changed = current.entries != index.entries;
+            *current = index;
+            changed
+        };
+        if changed {
+            info!("Indexed {} applications", count);
+            let apps = self
+                .index
+                .read()
+                .await
+                .entries
+                .iter()
+                .map(AppIcon::from)
+                .collect();
+            self.icons.set_apps(apps);
+            self.events.emit("apps-changed", json!({"count": count}));
+        }
+    }
+
+    async fn search(&self, query: &str) -> Value {
+        let index = self.index.read().await;
+        let usage = self.usage.lock().await;
+        let results: Vec<Value> = index
+            .search(query, &usage, current_timestamp())
+            .into_iter()
+            .take(self.settings.limit)
+            .map(|(entry, score)| {
+                let mut value = json!(entry);
+                value["icon_path"] = json!(self.icons.lookup(&entry.icon));
+                for (action, json) in entry
+                    .actions
+                    .iter()
+                    .zip(value["actions"].as_array_mut().into_iter().flatten())
+                {
+                    json["icon_path"] = json!(self.icons.lookup(&action.icon));
+                }
+                value["score"] = json!((score * 10.0).round() / 10.0);
+                value
+            })
+            .collect();
+        json!({"type": "apps", "query": query, "results": results})
+    }
+
+    async fn launch(&self, id: &str, action: Option<&str>) -> Result<(), String> {
+        let (id, command) = {
+            let index = self.index.read().await;
+            let entry = index.get(id).ok_or_else(|| format!("no app: {}", id))?;
+            (
+                entry.id.clone(),
+                entry.command(action, &self.settings.terminal)?,
+            )
+        };
+        debug!("Launching {}: {}", id, command);
+        if !hyprland::dispatch("exec", &command).await {
+            return Err("dispatch exec failed".to_string());
+        }
+
+        let mut usage = self.usage.lock().await;
+        usage.record(&id, current_timestamp());
+        self.usage_file.save(&*usage);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Module for AppsModule {
+    fn name(&self) -> &'static str {
+        "apps"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["apps"]
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"apps": {"count": self.index.read().await.entries.len()}})
+    }
+
+    /// `apps`, `apps search <query>`, `apps launch <id> [action]`,
+    /// `apps reload`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        match args {
+            [] => json!({"type": "apps", "count": self.index.read().await.entries.len()}),
+            ["search", query @ ..] => self.search(&query.join(" ")).await,
+            ["launch", id, rest @ ..] if rest.len() <= 1 => {
+                match self.launch(id, rest.first().copied()).await {
+                    Ok(()) => ok(),
+                    Err(e) => module::error(e),
+                }
+            }
+            ["reload"] => {
+                self.refresh(true).await;
+                json!({"type": "apps", "count": self.index.read().await.entries.len()})
+            }
+            _ => module::error("usage: apps [search <query>|launch <id> [action]|reload]"),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut interval = interval(RESCAN_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.refresh(false).await;
+        }
+    }
+}
+
+/// `applications` dirs in XDG priority order
+fn application_dirs() -> Vec<PathBuf> {
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs::data_dir()
+        .into_iter()
+        .chain(
+            data_dirs
+                .split(':')
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from),
+        )
+        .map(|d| d.join("applications"))
+        .collect()
+}
+
+/// Desktop file ids and paths under one dir; subdirectories become
+/// `-` separated prefixes of the id
+fn desktop_files(dir: &Path) -> Vec<(String, PathBuf)> {
+    fn walk(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if path.is_dir() {
+                walk(&path, &format!("{}{}-", prefix, name), files);
+            } else if name.ends_with(".desktop") {
+                files.push((format!("{}{}", prefix, name), path.clone()));
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    walk(dir, "", &mut files);
+    files
+}
+
+/// Hash of every file and dir path with its mtime, to notice changes
+/// without rereading the files
+fn fingerprint(dirs: &[PathBuf]) -> u64 {
+    fn visit(path: &Path, hasher: &mut DefaultHasher) {
+        let Ok(meta) = fs::metadata(path) else {
+            return;
+        };
+        path.hash(hasher);
+        meta.modified().ok().hash(hasher);
+        if meta.is_dir() {
+            let mut paths: Vec<PathBuf> = fs::read_dir(path)
+                .map(|rd| rd.flatten().map(|e| e.path()).collect())
+                .unwrap_or_default();
+            paths.sort();
+            for path in paths {
+                visit(&path, hasher);
+            }
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for dir in dirs {
+        visit(dir, &mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Locale suffixes to try for localized keys, most specific first
+fn current_locales() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    locale_variants(&locale)
+}
+
+/// `lang_COUNTRY.ENCODING@MODIFIER` to the spec's matching order:
+/// `lang_COUNTRY@MODIFIER`, `lang_COUNTRY`, `lang@MODIFIER`, `lang`
+fn locale_variants(locale: &str) -> Vec<String> {
+    if locale.is_empty() || locale == "C" || locale == "POSIX" {
+        return Vec::new();
+    }
+    let (rest, modifier) = match locale.split_once('@') {
+        Some((rest, modifier)) => (rest, Some(modifier)),
+        None => (locale, None),
+    };
+    let rest = rest.split('.').next().unwrap_or(rest);
+    let (lang, country) = match rest.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (rest, None),
+    };
+
+    let mut variants = Vec::new();
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        variants.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        variants.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        variants.push(format!("{}@{}", lang, modifier));
+    }
+    variants.push(lang.to_string());
+    variants
+}
+
+type Group = HashMap<String, String>;
+
+/// Parse a desktop file, `None` when it should not be offered here
+fn parse_desktop(
+    id: &str,
+    contents: &str,
+    locales: &[String],
+    desktops: &[String],
+) -> Option<DesktopEntry> {
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(name.to_string());
+            continue;
+        }
+        let (Some(group), Some((key, value))) = (&current, line.split_once('=')) else {
+            continue;
+        };
+        // First occurrence wins, as for duplicate groups
+        groups
+            .entry(group.clone())
+            .or_default()
+            .entry(key.trim().to_string())
+            .or_insert_with(|| value.trim().to_string());
+    }
+
+    let main = groups.get("Desktop Entry")?;
+    let get = |key: &str| main.get(key).map(|v| unescape(v)).unwrap_or_default();
+    let flag = |key: &str| main.get(key).is_some_and(|v| v == "true");
+    let shown_here = |key: &str| {
+        main.get(key)
+            .map(|v| split_list(v).iter().any(|d| desktops.contains(d)))
+    };
+
+    if get("Type") != "Application" || flag("Hidden") {
+        return None;
+    }
+    if shown_here("OnlyShowIn") == Some(false) || shown_here("NotShowIn") == Some(true) {
+        return None;
+    }
+    let name = localized(main, "Name", locales)?;
+    let exec = get("Exec");
+    if exec.is_empty() {
+        return None;
+    }
+
+    let actions = main
+        .get("Actions")
+        .map(|v| split_list(v))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|action| {
+            let group = groups.get(&format!("Desktop Action {}", action))?;
+            Some(DesktopAction {
+                name: localized(group, "Name", locales)?,
+                icon: group.get("Icon").map(|v| unescape(v)).unwrap_or_default(),
+                exec: group.get("Exec").map(|v| unescape(v)).unwrap_or_default(),
+                id: action,
+            })
+        })
+        .collect();
+
+    Some(DesktopEntry {
+        id: id.to_string(),
+        name,
+        generic_name: localized(main, "GenericName", locales).unwrap_or_default(),
+        comment: localized(main, "Comment", locales).unwrap_or_default(),
+        icon: get("Icon"),
+        keywords: localized_raw(main, "Keywords", locales)
+            .map(split_list)
+            .unwrap_or_default(),
+        categories: main
+            .get("Categories")
+            .map(|v| split_list(v))
+            .unwrap_or_default(),
+        actions,
+        startup_wm_class: get("StartupWMClass"),
+        exec,
+        terminal: flag("Terminal"),
+        no_display: flag("NoDisplay"),
+        path: PathBuf::new(),
+    })
+}
+
+/// Raw value of `key[locale]` for the best matching locale, or of `key`
+fn localized_raw<'a>(group: &'a Group, key: &str, locales: &[String]) -> Option<&'a str> {
+    locales
+        .iter()
+        .find_map(|locale| group.get(&format!("{}[{}]", key, locale)))
+        .or_else(|| group.get(key))
+        .map(String::as_str)
+}
+
+fn localized(group: &Group, key: &str, locales: &[String]) -> Option<String> {
+    localized_raw(group, key, locales).map(unescape)
+}
+
+/// Undo the `\s`, `\n`, `\t`, `\r` and `\\` escapes of string values
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Split a `;` separated list, honouring `\;`
+fn split_list(value: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(';') => item.push(';'),
+                Some(other) => {
+                    item.push('\\');
+                    item.push(other);
+                }
+                None => item.push('\\'),
+            },
+            ';' => items.push(unescape(&std::mem::take(&mut item))),
+            c => item.push(c),
+        }
+    }
+    items.push(unescape(&item));
+    items.retain(|i| !i.is_empty());
+    items
+}
+
+/// Replace the field codes of an Exec value. Launcher starts get no files
+/// or URLs, so `%f` `%u` and friends are dropped.
+fn expand_exec(exec: &str, entry: &DesktopEntry) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "'\\''"));
+    let mut out = String::with_capacity(exec.len());
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => out.push('%'),
+            Some('i') if !entry.icon.is_empty() => {
+                out.push_str(&format!("--icon {}", quote(&entry.icon)));
+            }
+            Some('c') => out.push_str(&quote(&entry.name)),
+            Some('k') => out.push_str(&quote(&entry.path.to_string_lossy())),
+            _ => {}
+        }
+    }
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// How well `query` (lowercase) matches an entry, `None` for no match
+fn match_score(query: &str, entry: &DesktopEntry) -> Option<f64> {
+    let program = entry
+        .exec
+        .split_whitespace()
+        .next()
+        .and_then(|p| p.rsplit('/').next())
+        .unwrap_or_default();
+    let id = entry.id.strip_suffix(".desktop").unwrap_or(&entry.id);
+
+    let fields = [
+        (entry.name.as_str(), 1.0),
+        (entry.generic_name.as_str(), 0.8),
+    ]
+    .into_iter()
+    .chain(entry.keywords.iter().map(|k| (k.as_str(), 0.7)))
+    .chain([(program, 0.6), (id, 0.5)]);
+    fields
+        .filter_map(|(text, weight)| Some(fuzzy_score(query, &text.to_lowercase())? * weight))
+        .max_by(f64::total_cmp)
+}
+
+/// Score of `query` against `text`, both lowercase: whole match, prefix,
+/// word prefix, substring, then characters in order
+fn fuzzy_score(query: &str, text: &str) -> Option<f64> {
+    if text.is_empty() {
+        return None;
+    }
+    if text == query {
+        return Some(100.0);
+    }
+    if text.starts_with(query) {
+        return Some(90.0);
+    }
+    if text
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(query))
+    {
+        return Some(80.0);
+    }
+    if text.contains(query) {
+        return Some(60.0);
+    }
+
+    // Subsequence, scored by how tightly the characters cluster
+    let mut positions = Vec::new();
+    let mut text_chars = text.char_indices();
+    for q in query.chars() {
+        let (i, _) = text_chars.by_ref().find(|(_, t)| *t == q)?;
+        positions.push(i);
+    }
+    let span = (positions.last()? - positions.first()? + 1) as f64;
+    Some(40.0 * query.len() as f64 / span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIREFOX: &str = r"[Desktop Entry]
+Type=Application
+Name=Firefox
+Name[de]=Firefox-Browser
+GenericName=Web Browser
+GenericName[de]=Webbrowser
+Comment=Browse the\sWeb
+Keywords=Internet;WWW;Browser;Web\;Explorer;
+Keywords[de]=Internet;Netz;
+Icon=firefox
+Exec=/usr/lib/firefox/firefox %u
+Actions=new-window;new-private-window;
+StartupWMClass=firefox
+
+[Desktop Action new-window]
+Name=New Window
+Name[de]=Neues Fenster
+Exec=/usr/lib/firefox/firefox --new-window %u
+
+[Desktop Action new-private-window]
+Name=New Private Window
+Exec=/usr/lib/firefox/firefox --private-window %u
+";
+
+    fn desktops() -> Vec<String> {
+        vec!["Hyprland".to_string()]
+    }
+
+    fn entry(id: &str, name: &str) -> DesktopEntry {
+        let contents = format!(
+            "[Desktop Entry]\nType=Application\nName={}\nExec={}\n",
+            name, id
+        );
+        parse_desktop(&format!("{}.desktop", id), &contents, &[], &desktops()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_desktop() {
+        let entry = parse_desktop("firefox.desktop", FIREFOX, &[], &desktops()).unwrap();
+        assert_eq!(entry.name, "Firefox");
+        assert_eq!(entry.comment, "Browse the Web");
+        assert_eq!(
+            entry.keywords,
+            vec!["Internet", "WWW", "Browser", "Web;Explorer"]
+        );
+        assert_eq!(entry.startup_wm_class, "firefox");
+        assert_eq!(entry.actions.len(), 2);
+        assert_eq!(entry.actions[1].name, "New Private Window");
+
+        let locales = locale_variants("de_DE.UTF-8");
+        let entry = parse_desktop("firefox.desktop", FIREFOX, &locales, &desktops()).unwrap();
+        assert_eq!(entry.name, "Firefox-Browser");
+        assert_eq!(entry.generic_name, "Webbrowser");
+        assert_eq!(entry.keywords, vec!["Internet", "Netz"]);
+        assert_eq!(entry.actions[0].name, "Neues Fenster");
+        assert_eq!(entry.actions[1].name, "New Private Window");
+    }
+
+    #[test]
+    fn test_parse_desktop_visibility() {
+        let base = "[Desktop Entry]\nType=Application\nName=App\nExec=app\n";
+        let parse = |extra: &str| {
+            parse_desktop(
+                "app.desktop",
+                &format!("{}{}", base, extra),
+                &[],
+                &desktops(),
+            )
+        };
+
+        assert!(parse("").is_some());
+        assert!(parse("Hidden=true\n").is_none());
+        assert!(parse("OnlyShowIn=GNOME;KDE;\n").is_none());
+        assert!(parse("OnlyShowIn=GNOME;Hyprland;\n").is_some());
+        assert!(parse("NotShowIn=Hyprland;\n").is_none());
+        assert!(parse("NoDisplay=true\n").unwrap().no_display);
+        assert!(parse_desktop(
+            "l.desktop",
+            "[Desktop Entry]\nType=Link\nName=L\nURL=x\n",
+            &[],
+            &desktops()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_locale_variants() {
+        assert_eq!(
+            locale_variants("sr_YU.UTF-8@Latn"),
+            vec!["sr_YU@Latn", "sr_YU", "sr@Latn", "sr"]
+        );
+        assert_eq!(locale_variants("de"), vec!["de"]);
+        assert!(locale_variants("C").is_empty());
+    }
+
+    #[test]
+    fn test_command() {
+        let mut entry = parse_desktop("firefox.desktop", FIREFOX, &[], &desktops()).unwrap();
+        assert_eq!(
+            entry.command(None, "ghostty").unwrap(),
+            "/usr/lib/firefox/firefox"
+        );
+        assert_eq!(
+            entry.command(Some("new-window"), "ghostty").unwrap(),
+            "/usr/lib/firefox/firefox --new-window"
+        );
+        assert!(entry.command(Some("missing"), "ghostty").is_err());
+
+        entry.exec = "htop %i --title %c 100%%".to_string();
+        entry.terminal = true;
+        assert_eq!(
+            entry.command(None, "ghostty").unwrap(),
+            "ghostty -e htop --icon 'firefox' --title 'Firefox' 100%"
+        );
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let mut index = AppIndex {
+            entries: vec![
+                entry("org.gnome.Files", "Files"),
+                entry("firefox", "Firefox"),
+                entry("fish", "Fish"),
+                entry("steam", "Steam"),
+            ],
+        };
+        index.entries[3].keywords = vec!["games".to_string()];
+        let mut usage = AppUsage::default();
+        let names = |results: Vec<(&DesktopEntry, f64)>| -> Vec<String> {
+            results.into_iter().map(|(e, _)| e.name.clone()).collect()
+        };
+
+        assert_eq!(
+            names(index.search("fi", &usage, 0)),
+            vec!["Files", "Firefox", "Fish"]
+        );
+        assert_eq!(names(index.search("ffx", &usage, 0)), vec!["Firefox"]);
+        assert_eq!(names(index.search("game", &usage, 0)), vec!["Steam"]);
+
+        // Launches move an app up among similar matches
+        usage.record("fish.desktop", 0);
+        usage.record("fish.desktop", 0);
+        assert_eq!(index.search("fi", &usage, 0)[0].0.name, "Fish");
+        assert_eq!(index.search("", &usage, 0)[0].0.name, "Fish");
+
+        index.entries[2].no_display = true;
+        assert_eq!(
+            names(index.search("fi", &usage, 0)),
+            vec!["Files", "Firefox"]
+        );
+        assert!(index.get("fish").is_some());
+    }
+
+    #[test]
+    fn test_scan_shadowing() {
+        let root = env::temp_dir().join(format!("terra-apps-{}", std::process::id()));
+        let (user, system) = (root.join("user"), root.join("system"));
+        fs::create_dir_all(user.join("sub")).unwrap();
+        fs::create_dir_all(&system).unwrap();
+        let app = |name: &str| {
+            format!(
+                "[Desktop Entry]\nType=Application\nName={}\nExec=app\n",
+                name
+            )
+        };
+        fs::write(system.join("a.desktop"), app("System A")).unwrap();
+        fs::write(system.join("b.desktop"), app("System B")).unwrap();
+        fs::write(user.join("a.desktop"), app("User A")).unwrap();
+        fs::write(user.join("b.desktop"), "[Desktop Entry]\nHidden=true\n").unwrap();
+        fs::write(user.join("sub/c.desktop"), app("Nested C")).unwrap();
+
+        let before = fingerprint(&[user.clone(), system.clone()]);
+        let index = AppIndex::scan(&[user.clone(), system.clone()], &[], &desktops());
+        let ids: Vec<&str> = index.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["sub-c.desktop", "a.desktop"]);
+        assert_eq!(index.get("a").unwrap().name, "User A");
+
+        fs::remove_file(user.join("b.desktop")).unwrap();
+        assert_ne!(fingerprint(&[user.clone(), system.clone()]), before);
+        let index = AppIndex::scan(&[user, system], &[], &desktops());
+        assert_eq!(index.get("b.desktop").unwrap().name, "System B");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
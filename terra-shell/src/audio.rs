@@ -1,36 +1,131 @@
 //! Audio control module (PipeWire/PulseAudio via wpctl)
 
-use std::process::Command;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::process::Command;
 use tokio::time::{interval, Duration};
 use tracing::debug;
 
+use crate::module::{ok, Module};
+use crate::state::Version;
 use crate::AppState;
 
+const SINK: &str = "@DEFAULT_AUDIO_SINK@";
+const SOURCE: &str = "@DEFAULT_AUDIO_SOURCE@";
+
+/// Audio domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AudioState {
+    pub volume: u8,
+    pub muted: bool,
+    pub mic: MicState,
+}
+
+/// Default source volume
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MicState {
+    pub volume: u8,
+    pub muted: bool,
+}
+
+/// Default sink volume and mute control
+pub struct AudioModule {
+    state: Arc<AppState>,
+}
+
+impl AudioModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl Module for AudioModule {
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["audio", "volume", "mute"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.audio.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"audio": self.state.audio.get()})
+    }
+
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        match command {
+            "volume" => {
+                if let Some(level) = args.first().and_then(|a| a.parse::<u8>().ok()) {
+                    set_volume(level).await;
+                    self.state.audio.update(|s| s.volume = level);
+                }
+                ok()
+            }
+            "mute" => {
+                toggle_mute().await;
+                self.state.audio.update(|s| s.muted = !s.muted);
+                ok()
+            }
+            _ => {
+                let s = self.state.audio.get();
+                json!({
+                    "type": "audio",
+                    "volume": s.volume,
+                    "muted": s.muted,
+                    "mic": s.mic
+                })
+            }
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        monitor(self.state.clone()).await;
+    }
+}
+
 /// Monitor audio status
-pub async fn monitor(state: Arc<RwLock<AppState>>) {
+async fn monitor(state: Arc<AppState>) {
     let mut interval = interval(Duration::from_millis(500));
     
     loop {
         interval.tick().await;
         
-        if let Some((volume, muted)) = get_volume() {
-            let mut s = state.write().await;
-            if s.volume != volume || s.volume_muted != muted {
-                debug!("Audio: {}% (muted: {})", volume, muted);
-                s.volume = volume;
-                s.volume_muted = muted;
+        let Some((volume, muted)) = get_volume().await else {
+            continue;
+        };
+        let mic = get_volume_of(SOURCE).await;
+        
+        let changed = state.audio.update(|s| {
+            s.volume = volume;
+            s.muted = muted;
+            if let Some((volume, muted)) = mic {
+                s.mic = MicState { volume, muted };
             }
+        });
+        if changed {
+            debug!("Audio: {:?}", state.audio.get());
         }
     }
 }
 
 /// Get current volume from wpctl
-pub fn get_volume() -> Option<(u8, bool)> {
+pub async fn get_volume() -> Option<(u8, bool)> {
+    get_volume_of(SINK).await
+}
+
+/// Get volume of a wpctl node, e.g. the default source
+pub async fn get_volume_of(node: &str) -> Option<(u8, bool)> {
     let output = Command::new("wpctl")
-        .args(["get-volume", "@DEFAULT_AUDIO_SINK@"])
+        .args(["get-volume", node])
         .output()
+        .await
         .ok()?;
     
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -49,19 +144,21 @@ pub fn get_volume() -> Option<(u8, bool)> {
 }
 
 /// Set volume
-pub fn set_volume(level: u8) -> bool {
+pub async fn set_volume(level: u8) -> bool {
     Command::new("wpctl")
-        .args(["set-volume", "@DEFAULT_AUDIO_SINK@", &format!("{}%", level)])
+        .args(["set-volume", SINK, &format!("{}%", level)])
         .status()
+        .await
         .map(|s| s.success())
         .unwrap_or(false)
 }
 
 /// Toggle mute
-pub fn toggle_mute() -> bool {
+pub async fn toggle_mute() -> bool {
     Command::new("wpctl")
-        .args(["set-mute", "@DEFAULT_AUDIO_SINK@", "toggle"])
+        .args(["set-mute", SINK, "toggle"])
         .status()
+        .await
         .map(|s| s.success())
         .unwrap_or(false)
 }
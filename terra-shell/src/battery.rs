@@ -1,16 +1,69 @@
 //! Battery monitoring module
 
 use std::fs;
-use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
 use tokio::time::{interval, Duration};
 use tracing::debug;
 
+use crate::module::{self, Module};
+use crate::state::Version;
 use crate::AppState;
 
+/// Battery domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BatteryState {
+    pub level: u8,
+    pub charging: bool,
+}
+
+/// Battery level and charging state
+pub struct BatteryModule {
+    state: Arc<AppState>,
+}
+
+impl BatteryModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl Module for BatteryModule {
+    fn name(&self) -> &'static str {
+        "battery"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["battery"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.battery.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"battery": self.state.battery.get()})
+    }
+
+    async fn handle(&self, _command: &str, _args: &[&str]) -> Value {
+        let s = self.state.battery.get();
+        json!({
+            "type": "battery",
+            "level": s.level,
+            "charging": s.charging
+        })
+    }
+
+    async fn run(self: Arc<Self>) {
+        monitor(self.state.clone()).await;
+    }
+}
+
 /// Monitor battery status
-pub async fn monitor(state: Arc<RwLock<AppState>>) {
+async fn monitor(state: Arc<AppState>) {
     let mut interval = interval(Duration::from_secs(30));
     
     loop {
@@ -18,24 +71,18 @@ pub async fn monitor(state: Arc<RwLock<AppState>>) {
         
         if let Some((level, charging)) = read_battery() {
             debug!("Battery: {}% (charging: {})", level, charging);
-            let mut s = state.write().await;
-            s.battery_level = level;
-            s.battery_charging = charging;
+            state.battery.set(BatteryState { level, charging });
         }
     }
 }
 
 /// Read battery info from sysfs
 fn read_battery() -> Option<(u8, bool)> {
-    // Try common battery paths
-    let paths = [
-        "/sys/class/power_supply/BAT0",
-        "/sys/class/power_supply/BAT1",
-        "/sys/class/power_supply/battery",
-    ];
+    // Try common battery names
+    let supplies = module::sysfs("class/power_supply");
     
-    for bat_path in paths {
-        let path = Path::new(bat_path);
+    for name in ["BAT0", "BAT1", "battery"] {
+        let path = supplies.join(name);
         if path.exists() {
             let capacity = fs::read_to_string(path.join("capacity"))
                 .ok()?
@@ -0,0 +1,465 @@
+//! Bluetooth module (BlueZ over the system bus)
+//!
+//! Mirrors the adapter and device objects exported by `org.bluez`,
+//! refreshing whenever BlueZ signals a change, and exposes power, scan,
+//! pair, connect, disconnect and remove commands keyed by device address.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::stream::{select_all, SelectAll};
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Value as Json};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, info, warn};
+use zbus::fdo::{ManagedObjects, ObjectManagerProxy, PropertiesProxy};
+use zbus::message::Type as MessageType;
+use zbus::names::InterfaceName;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value};
+use zbus::{Connection, MatchRule, MessageStream};
+
+use crate::dbus::{prop, prop_string};
+use crate::module::{self, ok, Module};
+use crate::state::Version;
+use crate::AppState;
+
+const BLUEZ: &str = "org.bluez";
+const ADAPTER_INTERFACE: &str = "org.bluez.Adapter1";
+const DEVICE_INTERFACE: &str = "org.bluez.Device1";
+const BATTERY_INTERFACE: &str = "org.bluez.Battery1";
+
+/// Coalesce bursts of BlueZ signals (e.g. during discovery)
+const REFRESH_DELAY: Duration = Duration::from_millis(200);
+
+/// A Bluetooth controller
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Adapter {
+    pub address: String,
+    pub name: String,
+    pub powered: bool,
+    pub discovering: bool,
+    #[serde(skip)]
+    path: String,
+}
+
+/// A known or discovered device
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Device {
+    pub address: String,
+    pub name: String,
+    /// Freedesktop icon name describing the device type, e.g. `audio-headset`
+    pub icon: String,
+    pub paired: bool,
+    pub trusted: bool,
+    pub connected: bool,
+    /// Battery percentage reported through `org.bluez.Battery1`
+    pub battery: Option<u8>,
+    #[serde(skip)]
+    path: String,
+    /// Object path of the adapter the device belongs to
+    #[serde(skip)]
+    adapter: String,
+}
+
+/// Bluetooth domain of [`AppState`]: adapter and device state
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BluetoothState {
+    pub adapter: Option<Adapter>,
+    pub devices: Vec<Device>,
+}
+
+/// Bluetooth adapter and device control
+pub struct BluetoothModule {
+    state: Arc<AppState>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl BluetoothModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self {
+            state,
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connection(&self) -> Result<Connection, Json> {
+        self.connection
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| module::error("bluetooth not available"))
+    }
+
+    /// Re-read every BlueZ object
+    async fn refresh(&self, connection: &Connection) -> zbus::Result<()> {
+        let objects = ObjectManagerProxy::builder(connection)
+            .destination(BLUEZ)?
+            .path("/")?
+            .build()
+            .await?
+            .get_managed_objects()
+            .await?;
+        let state = parse_objects(&objects);
+        debug!(
+            "Bluetooth: {} devices, powered {:?}",
+            state.devices.len(),
+            state.adapter.as_ref().map(|a| a.powered)
+        );
+        self.state.bluetooth.set(state);
+        Ok(())
+    }
+
+    async fn adapter_path(&self) -> Result<String, Json> {
+        self.state
+            .bluetooth
+            .read(|s| s.adapter.as_ref().map(|a| a.path.clone()))
+            .ok_or_else(|| module::error("no bluetooth adapter"))
+    }
+
+    async fn device(&self, address: &str) -> Result<Device, Json> {
+        self.state
+            .bluetooth
+            .read(|s| {
+                s.devices
+                    .iter()
+                    .find(|d| d.address.eq_ignore_ascii_case(address))
+                    .cloned()
+            })
+            .ok_or_else(|| module::error(format!("unknown device: {}", address)))
+    }
+
+    async fn device_path(&self, address: &str) -> Result<String, Json> {
+        self.device(address).await.map(|d| d.path)
+    }
+
+    /// Call a method without arguments on a BlueZ object
+    async fn call(&self, path: &str, interface: &str, method: &str) -> Result<(), Json> {
+        let connection = self.connection().await?;
+        connection
+            .call_method(Some(BLUEZ), path, Some(interface), method, &())
+            .await
+            .map_err(|e| module::error(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn set_property(
+        &self,
+        path: &str,
+        interface: &'static str,
+        name: &str,
+        value: Value<'_>,
+    ) -> Result<(), Json> {
+        let connection = self.connection().await?;
+        let result = async {
+            PropertiesProxy::builder(&connection)
+                .destination(BLUEZ)?
+                .path(path.to_string())?
+                .build()
+                .await?
+                .set(InterfaceName::from_static_str_unchecked(interface), name, value)
+                .await
+                .map_err(zbus::Error::from)
+        };
+        result.await.map_err(|e| module::error(e.to_string()))
+    }
+
+    async fn run_command(&self, action: &str, target: Option<&str>) -> Result<(), Json> {
+        match (action, target) {
+            ("power", mode) => {
+                let adapter = self.adapter_path().await?;
+                let current = self
+                    .state
+                    .bluetooth
+                    .read(|s| s.adapter.as_ref().is_some_and(|a| a.powered));
+                let powered = match mode {
+                    Some("on") => true,
+                    Some("off") => false,
+                    Some("toggle") | None => !current,
+                    Some(other) => return Err(module::error(format!("unknown power mode: {}", other))),
+                };
+                self.set_property(&adapter, ADAPTER_INTERFACE, "Powered", Value::from(powered))
+                    .await
+            }
+            ("scan", mode) => {
+                let adapter = self.adapter_path().await?;
+                let method = match mode {
+                    Some("off") => "StopDiscovery",
+                    Some("on") | None => "StartDiscovery",
+                    Some(other) => return Err(module::error(format!("unknown scan mode: {}", other))),
+                };
+                self.call(&adapter, ADAPTER_INTERFACE, method).await
+            }
+            ("pair", Some(address)) => {
+                let device = self.device_path(address).await?;
+                self.call(&device, DEVICE_INTERFACE, "Pair").await?;
+                // Trusted devices may reconnect on their own
+                self.set_property(&device, DEVICE_INTERFACE, "Trusted", Value::from(true))
+                    .await
+            }
+            ("connect", Some(address)) => {
+                let device = self.device_path(address).await?;
+                self.call(&device, DEVICE_INTERFACE, "Connect").await
+            }
+            ("disconnect", Some(address)) => {
+                let device = self.device_path(address).await?;
+                self.call(&device, DEVICE_INTERFACE, "Disconnect").await
+            }
+            ("remove", Some(address)) => {
+                // Removed through its own adapter, which need not be the one shown
+                let device = self.device(address).await?;
+                let path = OwnedObjectPath::try_from(device.path)
+                    .map_err(|e| module::error(e.to_string()))?;
+                let connection = self.connection().await?;
+                connection
+                    .call_method(Some(BLUEZ), device.adapter, Some(ADAPTER_INTERFACE), "RemoveDevice", &(path,))
+                    .await
+                    .map_err(|e| module::error(e.to_string()))?;
+                Ok(())
+            }
+            _ => Err(module::error(
+                "usage: bluetooth [power on|off|toggle|scan on|off|pair|connect|disconnect|remove <address>]",
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl Module for BluetoothModule {
+    fn name(&self) -> &'static str {
+        "bluetooth"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["bluetooth"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.bluetooth.version())
+    }
+
+    /// Only paired or connected devices, discovery results stay in `bluetooth`
+    async fn snapshot(&self) -> Json {
+        let state = self.state.bluetooth.get();
+        let devices: Vec<&Device> = state
+            .devices
+            .iter()
+            .filter(|d| d.paired || d.connected)
+            .collect();
+        json!({
+            "bluetooth": {
+                "available": state.adapter.is_some(),
+                "powered": state.adapter.as_ref().is_some_and(|a| a.powered),
+                "discovering": state.adapter.as_ref().is_some_and(|a| a.discovering),
+                "devices": devices
+            }
+        })
+    }
+
+    async fn handle(&self, _command: &str, args: &[&str]) -> Json {
+        let Some(action) = args.first().copied() else {
+            let state = self.state.bluetooth.get();
+            return json!({
+                "type": "bluetooth",
+                "adapter": state.adapter,
+                "devices": state.devices
+            });
+        };
+
+        match self.run_command(action, args.get(1).copied()).await {
+            Ok(()) => {
+                if let Ok(connection) = self.connection().await {
+                    let _ = self.refresh(&connection).await;
+                }
+                ok()
+            }
+            Err(e) => e,
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let connection = match Connection::system().await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Failed to connect to system bus: {}", e);
+                return;
+            }
+        };
+        *self.connection.lock().await = Some(connection.clone());
+
+        let mut signals = match signals(&connection).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to watch BlueZ signals: {}", e);
+                return;
+            }
+        };
+
+        match self.refresh(&connection).await {
+            Ok(()) => info!("Connected to BlueZ"),
+            Err(e) => warn!("BlueZ not available: {}", e),
+        }
+
+        while signals.next().await.is_some() {
+            // Drain the burst, then refresh once
+            sleep(REFRESH_DELAY).await;
+            while let Ok(Some(_)) = tokio::time::timeout(Duration::ZERO, signals.next()).await {}
+
+            if let Err(e) = self.refresh(&connection).await {
+                // bluetoothd is gone until its name gets an owner again
+                debug!("Failed to refresh BlueZ objects: {}", e);
+                self.state.bluetooth.set(BluetoothState::default());
+            }
+        }
+    }
+
+    async fn stop(&self) {
+        self.connection.lock().await.take();
+    }
+}
+
+/// Signals that may change BlueZ objects: property changes below
+/// `/org/bluez`, objects added and removed on `/`, and bluetoothd starting
+/// or exiting. The sender isn't checked locally for a well-known name, so
+/// paths and interfaces keep other services' signals out.
+async fn signals(connection: &Connection) -> zbus::Result<SelectAll<MessageStream>> {
+    let signal = || MatchRule::builder().msg_type(MessageType::Signal);
+    let rules = [
+        signal().sender(BLUEZ)?.path_namespace("/org/bluez")?.build(),
+        signal()
+            .sender(BLUEZ)?
+            .path("/")?
+            .interface("org.freedesktop.DBus.ObjectManager")?
+            .build(),
+        signal()
+            .sender("org.freedesktop.DBus")?
+            .interface("org.freedesktop.DBus")?
+            .member("NameOwnerChanged")?
+            .arg(0, BLUEZ)?
+            .build(),
+    ];
+    let mut streams = Vec::new();
+    for rule in rules {
+        streams.push(MessageStream::for_match_rule(rule, connection, None).await?);
+    }
+    Ok(select_all(streams))
+}
+
+/// Build state from `GetManagedObjects`, using the first adapter for power
+/// and discovery and listing the devices of all adapters
+fn parse_objects(objects: &ManagedObjects) -> BluetoothState {
+    let mut state = BluetoothState::default();
+    let interface = |path: &OwnedObjectPath, name: &str| -> Option<&HashMap<String, OwnedValue>> {
+        objects
+            .get(path)?
+            .iter()
+            .find(|(iface, _)| iface.as_str() == name)
+            .map(|(_, props)| props)
+    };
+
+    let mut paths: Vec<&OwnedObjectPath> = objects.keys().collect();
+    paths.sort_by_key(|p| p.as_str());
+
+    for path in paths {
+        if let Some(props) = interface(path, ADAPTER_INTERFACE) {
+            if state.adapter.is_none() {
+                state.adapter = Some(Adapter {
+                    address: prop_string(props, "Address"),
+                    name: prop_string(props, "Alias"),
+                    powered: prop::<bool>(props, "Powered").unwrap_or(false),
+                    discovering: prop::<bool>(props, "Discovering").unwrap_or(false),
+                    path: path.to_string(),
+                });
+            }
+        }
+
+        if let Some(props) = interface(path, DEVICE_INTERFACE) {
+            let battery = interface(path, BATTERY_INTERFACE)
+                .and_then(|battery| prop::<u8>(battery, "Percentage"));
+            let name = match prop_string(props, "Alias") {
+                alias if alias.is_empty() => prop_string(props, "Address"),
+                alias => alias,
+            };
+            state.devices.push(Device {
+                address: prop_string(props, "Address"),
+                name,
+                icon: prop_string(props, "Icon"),
+                paired: prop::<bool>(props, "Paired").unwrap_or(false),
+                trusted: prop::<bool>(props, "Trusted").unwrap_or(false),
+                connected: prop::<bool>(props, "Connected").unwrap_or(false),
+                battery,
+                path: path.to_string(),
+                adapter: prop::<ObjectPath>(props, "Adapter")
+                    .map(|p| p.to_string())
+                    .unwrap_or_default(),
+            });
+        }
+    }
+
+    // Connected first, then paired, then by name
+    state
+        .devices
+        .sort_by(|a, b| (!a.connected, !a.paired, &a.name).cmp(&(!b.connected, !b.paired, &b.name)));
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::names::OwnedInterfaceName;
+
+    use crate::dbus::props;
+
+    fn interface(name: &'static str) -> OwnedInterfaceName {
+        InterfaceName::from_static_str_unchecked(name).into()
+    }
+
+    #[test]
+    fn test_parse_objects() {
+        let mut objects = ManagedObjects::new();
+        objects.insert(
+            OwnedObjectPath::try_from("/org/bluez/hci0").unwrap(),
+            HashMap::from([(
+                interface(ADAPTER_INTERFACE),
+                props(&[("Alias", Value::from("laptop")), ("Powered", Value::from(true))]),
+            )]),
+        );
+        objects.insert(
+            OwnedObjectPath::try_from("/org/bluez/hci0/dev_AA").unwrap(),
+            HashMap::from([
+                (
+                    interface(DEVICE_INTERFACE),
+                    props(&[
+                        ("Address", Value::from("AA:BB")),
+                        ("Alias", Value::from("Headset")),
+                        ("Icon", Value::from("audio-headset")),
+                        ("Paired", Value::from(true)),
+                        ("Connected", Value::from(true)),
+                        (
+                            "Adapter",
+                            Value::from(ObjectPath::try_from("/org/bluez/hci1").unwrap()),
+                        ),
+                    ]),
+                ),
+                (interface(BATTERY_INTERFACE), props(&[("Percentage", Value::from(80u8))])),
+            ]),
+        );
+        objects.insert(
+            OwnedObjectPath::try_from("/org/bluez/hci0/dev_CC").unwrap(),
+            HashMap::from([(interface(DEVICE_INTERFACE), props(&[("Address", Value::from("CC:DD"))]))]),
+        );
+
+        let state = parse_objects(&objects);
+        let adapter = state.adapter.unwrap();
+        assert!(adapter.powered);
+        assert_eq!(adapter.name, "laptop");
+        assert_eq!(state.devices.len(), 2);
+        assert_eq!(state.devices[0].name, "Headset");
+        assert_eq!(state.devices[0].battery, Some(80));
+        assert_eq!(state.devices[0].adapter, "/org/bluez/hci1");
+        assert_eq!(state.devices[1].name, "CC:DD");
+        assert_eq!(state.devices[1].battery, None);
+    }
+}
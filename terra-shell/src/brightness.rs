@@ -1,16 +1,103 @@
 //! Brightness control module
 
-use std::process::Command;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::process::Command;
+use tokio::time::{interval, Duration};
+use tracing::debug;
+
+use crate::module::{error, ok, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// Screen backlight level and control
+pub struct BrightnessModule {
+    state: Arc<AppState>,
+}
+
+impl BrightnessModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl Module for BrightnessModule {
+    fn name(&self) -> &'static str {
+        "brightness"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["brightness"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.brightness.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"brightness": self.state.brightness.get()})
+    }
+
+    /// `brightness` queries, `brightness 40` sets, `brightness +5` / `-5` steps
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        let Some(arg) = args.first() else {
+            return json!({"type": "brightness", "level": self.state.brightness.get()});
+        };
+
+        let success = if let Some(amount) = arg.strip_prefix('+') {
+            match amount.parse() {
+                Ok(amount) => increase(amount).await,
+                Err(_) => false,
+            }
+        } else if let Some(amount) = arg.strip_prefix('-') {
+            match amount.parse() {
+                Ok(amount) => decrease(amount).await,
+                Err(_) => false,
+            }
+        } else {
+            match arg.parse() {
+                Ok(level) => set_brightness(level).await,
+                Err(_) => false,
+            }
+        };
+
+        if !success {
+            return error("brightness change failed");
+        }
+        if let Some(level) = get_brightness().await {
+            self.state.brightness.set(level);
+        }
+        ok()
+    }
+
+    async fn run(self: Arc<Self>) {
+        // Short enough for hardware key changes to reach the OSD promptly
+        let mut interval = interval(Duration::from_millis(500));
+
+        loop {
+            interval.tick().await;
+
+            if let Some(level) = get_brightness().await {
+                if self.state.brightness.set(level) {
+                    debug!("Brightness: {}%", level);
+                }
+            }
+        }
+    }
+}
 
 /// Get current brightness (0-100)
-pub fn get_brightness() -> Option<u8> {
+pub async fn get_brightness() -> Option<u8> {
     let output = Command::new("brightnessctl")
         .args(["info", "-m"])
         .output()
+        .await
         .ok()?;
     
     let stdout = String::from_utf8_lossy(&output.stdout);
-    // Output format: device,class,current,max,percentage
+    // Output format: device,class,current,percentage,max
     let parts: Vec<&str> = stdout.split(',').collect();
     
     if parts.len() >= 4 {
@@ -21,28 +108,31 @@ pub fn get_brightness() -> Option<u8> {
 }
 
 /// Set brightness level
-pub fn set_brightness(level: u8) -> bool {
+pub async fn set_brightness(level: u8) -> bool {
     Command::new("brightnessctl")
         .args(["set", &format!("{}%", level)])
         .status()
+        .await
         .map(|s| s.success())
         .unwrap_or(false)
 }
 
 /// Increase brightness
-pub fn increase(amount: u8) -> bool {
+pub async fn increase(amount: u8) -> bool {
     Command::new("brightnessctl")
         .args(["set", &format!("+{}%", amount)])
         .status()
+        .await
         .map(|s| s.success())
         .unwrap_or(false)
 }
 
 /// Decrease brightness
-pub fn decrease(amount: u8) -> bool {
+pub async fn decrease(amount: u8) -> bool {
     Command::new("brightnessctl")
         .args(["set", &format!("{}%-", amount)])
         .status()
+        .await
         .map(|s| s.success())
         .unwrap_or(false)
 }
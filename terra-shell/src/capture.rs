@@ -0,0 +1,464 @@
+//! Screenshots and screen recording
+//!
+//! Screenshots run grim on a region picked with slurp, a window (picked
+//! from the visible Hyprland clients, or the active one), the focused
+//! monitor or all outputs, and can copy the image with wl-copy.
+//! Recordings run wf-recorder, which records a single output, so `screen`
+//! records the focused monitor; the running session is tracked in
+//! [`AppState`] so the bar can show an indicator, and stopped with SIGINT
+//! so the file is finalized.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use jiff::fmt::strtime;
+use jiff::Zoned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tokio::time::{interval, timeout, Duration};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::hyprland;
+use crate::module::{self, current_timestamp, Module};
+use crate::state::Version;
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct CaptureSettings {
+    /// Screenshot directory, `~` is expanded
+    screenshot_dir: String,
+    /// strftime template for screenshot file names
+    screenshot_name: String,
+    /// Copy screenshots to the clipboard, the default when neither
+    /// `clipboard` nor `noclipboard` is given
+    clipboard: bool,
+    recording_dir: String,
+    recording_name: String,
+    /// Screenshot and region selection tools, grim/slurp compatible
+    grim: String,
+    slurp: String,
+    recorder: String,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            screenshot_dir: "~/Pictures/Screenshots".to_string(),
+            screenshot_name: "%Y-%m-%d_%H-%M-%S.png".to_string(),
+            clipboard: false,
+            recording_dir: "~/Videos/Recordings".to_string(),
+            recording_name: "%Y-%m-%d_%H-%M-%S.mp4".to_string(),
+            grim: "grim".to_string(),
+            slurp: "slurp".to_string(),
+            recorder: "wf-recorder".to_string(),
+        }
+    }
+}
+
+/// A running screen recording
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Recording {
+    pub file: String,
+    /// Unix timestamp in seconds
+    pub started: u64,
+    pub target: String,
+}
+
+/// What to capture
+#[derive(Debug, PartialEq)]
+enum Target {
+    /// `X,Y WxH` in layout coordinates
+    Geometry(String),
+    Output(String),
+    All,
+}
+
+/// Screenshot and wf-recorder control
+pub struct CaptureModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    settings: CaptureSettings,
+    recorder: Mutex<Option<Child>>,
+}
+
+impl CaptureModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        Self {
+            state,
+            events,
+            settings: config.section("capture"),
+            recorder: Mutex::new(None),
+        }
+    }
+
+    /// Resolve `region`, `window`, `active`, `monitor` or `screen`
+    async fn target(&self, kind: &str) -> Result<Target, String> {
+        match kind {
+            "region" => self.select(None).await.map(Target::Geometry),
+            "window" => {
+                let boxes = visible_clients().await.iter().map(client_geometry).collect::<Vec<_>>();
+                if boxes.is_empty() {
+                    return Err("no visible windows".to_string());
+                }
+                self.select(Some(boxes.join("\n"))).await.map(Target::Geometry)
+            }
+            "active" => {
+                let active = hyprland::get_active_window()
+                    .await
+                    .ok_or_else(|| "no active window".to_string())?;
+                hyprland::get_clients()
+                    .await
+                    .iter()
+                    .find(|c| c.address == active.address)
+                    .map(|c| Target::Geometry(client_geometry(c)))
+                    .ok_or_else(|| "active window not found".to_string())
+            }
+            "monitor" => hyprland::get_monitors()
+                .await
+                .into_iter()
+                .find(|m| m.focused)
+                .map(|m| Target::Output(m.name))
+                .ok_or_else(|| "no focused monitor".to_string()),
+            "screen" => Ok(Target::All),
+            other => Err(format!("unknown target: {}", other)),
+        }
+    }
+
+    /// Run slurp, optionally restricted to predefined boxes
+    async fn select(&self, boxes: Option<String>) -> Result<String, String> {
+        let mut command = Command::new(&self.settings.slurp);
+        command.stdout(Stdio::piped()).kill_on_drop(true);
+        if boxes.is_some() {
+            command.arg("-r").stdin(Stdio::piped());
+        }
+        let mut child = command
+            .spawn()
+            .map_err(|e| format!("{} failed: {}", self.settings.slurp, e))?;
+
+        if let (Some(boxes), Some(mut stdin)) = (boxes, child.stdin.take()) {
+            let _ = stdin.write_all(boxes.as_bytes()).await;
+        }
+
+        let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+        let geometry = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || geometry.is_empty() {
+            return Err("selection cancelled".to_string());
+        }
+        Ok(geometry)
+    }
+
+    async fn screenshot(&self, kind: &str, clipboard: bool) -> Result<PathBuf, String> {
+        let target = self.target(kind).await?;
+        let path = output_path(&self.settings.screenshot_dir, &self.settings.screenshot_name)?;
+
+        let mut command = Command::new(&self.settings.grim);
+        command.args(target_args(&target)).arg(&path);
+        let status = command
+            .status()
+            .await
+            .map_err(|e| format!("{} failed: {}", self.settings.grim, e))?;
+        if !status.success() {
+            return Err(format!("{} exited with {}", self.settings.grim, status));
+        }
+        info!("Screenshot saved to {}", path.display());
+
+        if clipboard && !copy_image(&path).await {
+            warn!("Failed to copy screenshot to the clipboard");
+        }
+        Ok(path)
+    }
+
+    async fn start_recording(&self, kind: &str, audio: bool) -> Result<Recording, String> {
+        if self.recorder.lock().await.is_some() {
+            return Err("already recording".to_string());
+        }
+        // Without -o wf-recorder asks which output to record on stdin
+        let target = match kind {
+            "screen" => self.target("monitor").await?,
+            kind => self.target(kind).await?,
+        };
+        let path = output_path(&self.settings.recording_dir, &self.settings.recording_name)?;
+
+        // The selection may take a while, so the lock is only held from here
+        let mut recorder = self.recorder.lock().await;
+        if recorder.is_some() {
+            return Err("already recording".to_string());
+        }
+
+        let mut command = Command::new(&self.settings.recorder);
+        command
+            .args(target_args(&target))
+            .arg("-f")
+            .arg(&path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        if audio {
+            command.arg("--audio");
+        }
+        let child = command
+            .spawn()
+            .map_err(|e| format!("{} failed: {}", self.settings.recorder, e))?;
+        *recorder = Some(child);
+
+        let recording = Recording {
+            file: path.to_string_lossy().into_owned(),
+            started: current_timestamp(),
+            target: kind.to_string(),
+        };
+        info!("Recording to {}", recording.file);
+        self.state.recording.set(Some(recording.clone()));
+        self.events.emit("recording", json!({"recording": true, "file": recording.file}));
+        Ok(recording)
+    }
+
+    /// Interrupt wf-recorder and wait for it to finish writing the file
+    async fn stop_recording(&self) -> Option<Recording> {
+        let child = self.recorder.lock().await.take();
+        if let Some(mut child) = child {
+            if let Some(pid) = child.id() {
+                // SAFETY: plain kill(2) on our own child
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGINT);
+                }
+            }
+            if timeout(Duration::from_secs(5), child.wait()).await.is_err() {
+                warn!("Recorder didn't exit, killing it");
+                let _ = child.kill().await;
+            }
+        }
+        self.finished().await
+    }
+
+    /// Clear the recording from state and announce it
+    async fn finished(&self) -> Option<Recording> {
+        let mut recording = None;
+        self.state.recording.update(|r| recording = r.take());
+        let recording = recording?;
+        info!("Recording saved to {}", recording.file);
+        self.events.emit("recording", json!({"recording": false, "file": recording.file}));
+        Some(recording)
+    }
+
+    fn recording_json(recording: &Option<Recording>) -> Value {
+        match recording {
+            Some(r) => json!({
+                "recording": true,
+                "file": r.file,
+                "target": r.target,
+                "started": r.started,
+                "elapsed": current_timestamp().saturating_sub(r.started)
+            }),
+            None => json!({"recording": false}),
+        }
+    }
+}
+
+#[async_trait]
+impl Module for CaptureModule {
+    fn name(&self) -> &'static str {
+        "capture"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["screenshot", "record"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.recording.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"recording": Self::recording_json(&self.state.recording.get())})
+    }
+
+    /// `screenshot region|window|active|monitor|screen [clipboard|noclipboard]`,
+    /// `record [start <target> [audio]|stop]`
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        if command == "screenshot" {
+            let kind = args.first().copied().unwrap_or("region");
+            let clipboard = match args.get(1).copied() {
+                Some("clipboard") => true,
+                Some("noclipboard") => false,
+                _ => self.settings.clipboard,
+            };
+            return match self.screenshot(kind, clipboard).await {
+                Ok(path) => {
+                    let path = path.to_string_lossy();
+                    self.events.emit("screenshot", json!({"path": path, "clipboard": clipboard}));
+                    json!({"type": "screenshot", "path": path, "clipboard": clipboard})
+                }
+                Err(e) => module::error(e),
+            };
+        }
+
+        match args.first().copied() {
+            None => {
+                let mut reply = Self::recording_json(&self.state.recording.get());
+                reply["type"] = json!("recording");
+                reply
+            }
+            Some("start") => {
+                let kind = args.get(1).copied().unwrap_or("monitor");
+                let audio = args.get(2) == Some(&"audio");
+                match self.start_recording(kind, audio).await {
+                    Ok(recording) => {
+                        let mut reply = Self::recording_json(&Some(recording));
+                        reply["type"] = json!("recording");
+                        reply
+                    }
+                    Err(e) => module::error(e),
+                }
+            }
+            Some("stop") => match self.stop_recording().await {
+                Some(recording) => json!({"type": "recording", "recording": false, "file": recording.file}),
+                None => module::error("not recording"),
+            },
+            Some(other) => module::error(format!("unknown record action: {}", other)),
+        }
+    }
+
+    /// Notice recorders that exit on their own (output unplugged, crash)
+    async fn run(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+            let exited = {
+                let mut recorder = self.recorder.lock().await;
+                let exited = recorder
+                    .as_mut()
+                    .is_some_and(|child| !matches!(child.try_wait(), Ok(None)));
+                if exited {
+                    recorder.take();
+                }
+                exited
+            };
+            if exited {
+                warn!("Recorder exited unexpectedly");
+                self.finished().await;
+            }
+        }
+    }
+
+    async fn stop(&self) {
+        self.stop_recording().await;
+    }
+}
+
+/// grim / wf-recorder arguments for a target
+fn target_args(target: &Target) -> Vec<String> {
+    match target {
+        Target::Geometry(geometry) => vec!["-g".to_string(), geometry.clone()],
+        Target::Output(name) => vec!["-o".to_string(), name.clone()],
+        Target::All => vec![],
+    }
+}
+
+/// `X,Y WxH` box of a window
+fn client_geometry(client: &hyprland::Client) -> String {
+    format!(
+        "{},{} {}x{}",
+        client.at[0], client.at[1], client.size[0], client.size[1]
+    )
+}
+
+/// Mapped windows on the workspaces currently shown on any monitor
+async fn visible_clients() -> Vec<hyprland::Client> {
+    let shown: Vec<i32> = hyprland::get_monitors()
+        .await
+        .iter()
+        .map(|m| m.active_workspace.id)
+        .collect();
+    hyprland::get_clients()
+        .await
+        .into_iter()
+        .filter(|c| c.mapped && !c.hidden && shown.contains(&c.workspace.id))
+        .collect()
+}
+
+/// Put a PNG on the clipboard with wl-copy
+async fn copy_image(path: &Path) -> bool {
+    let Ok(image) = File::open(path) else {
+        return false;
+    };
+    Command::new("wl-copy")
+        .args(["--type", "image/png"])
+        .stdin(image)
+        .status()
+        .await
+        .is_ok_and(|s| s.success())
+}
+
+/// Expand `~`, create the directory and format the file name template,
+/// appending `-N` when a file of that name already exists
+fn output_path(dir: &str, template: &str) -> Result<PathBuf, String> {
+    let dir = match dir.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir()
+            .ok_or_else(|| "no home directory".to_string())?
+            .join(rest),
+        None => PathBuf::from(dir),
+    };
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let name = strtime::format(template, &Zoned::now())
+        .map_err(|e| format!("invalid file name template {:?}: {}", template, e))?;
+    Ok(unused_path(dir.join(name)))
+}
+
+/// `path`, or the first of `stem-1.ext`, `stem-2.ext`, ... that doesn't exist
+fn unused_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_args() {
+        let geometry = Target::Geometry("10,20 300x200".to_string());
+        assert_eq!(target_args(&geometry), vec!["-g", "10,20 300x200"]);
+        assert_eq!(target_args(&Target::Output("DP-1".to_string())), vec!["-o", "DP-1"]);
+        assert!(target_args(&Target::All).is_empty());
+    }
+
+    #[test]
+    fn test_output_path() {
+        let dir = std::env::temp_dir().join(format!("terra-capture-{}", std::process::id()));
+        let path = output_path(dir.to_str().unwrap(), "shot_%Y.png").unwrap();
+        assert!(dir.is_dir());
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("shot_20") && name.ends_with(".png"));
+        assert!(output_path(dir.to_str().unwrap(), "shot_%").is_err());
+
+        // Two captures within the same second
+        std::fs::write(dir.join("fixed.png"), "").unwrap();
+        let path = output_path(dir.to_str().unwrap(), "fixed.png").unwrap();
+        assert_eq!(path, dir.join("fixed-1.png"));
+        std::fs::write(&path, "").unwrap();
+        let path = output_path(dir.to_str().unwrap(), "fixed.png").unwrap();
+        assert_eq!(path, dir.join("fixed-2.png"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
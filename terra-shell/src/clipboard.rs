@@ -0,0 +1,477 @@
+//! Clipboard history
+//!
+//! `wl-paste --watch` tells us when the selection changes; the new
+//! contents are then read with `wl-paste`, preferring images over text.
+//! Entries are deduplicated by content hash (a repeat moves the entry to
+//! the top), kept within size and count limits with pinned entries exempt,
+//! and stored in the data directory with images as separate files.
+//! Selections flagged by password managers are not recorded.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::{self, current_timestamp, ok, JsonFile, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// Delay before restarting `wl-paste --watch` after it exits
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// Characters of text shown in list replies
+const PREVIEW_LENGTH: usize = 200;
+
+/// Mime type password managers set on secrets
+const PASSWORD_HINT: &str = "x-kde-passwordManagerHint";
+
+const TEXT_TYPES: [&str; 4] = ["text/plain;charset=utf-8", "text/plain", "UTF8_STRING", "STRING"];
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ClipboardSettings {
+    /// Unpinned entries kept
+    max_entries: usize,
+    /// Largest text entry in bytes
+    max_text_size: usize,
+    /// Largest image entry in bytes
+    max_image_size: usize,
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 100,
+            max_text_size: 1024 * 1024,
+            max_image_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+/// A clipboard history entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipEntry {
+    pub id: u64,
+    pub mime: String,
+    /// Text contents, `None` for images
+    pub text: Option<String>,
+    /// Image file in the data directory
+    pub image: Option<PathBuf>,
+    pub size: usize,
+    pub hash: u64,
+    pub pinned: bool,
+    /// Unix timestamp of the last copy
+    pub time: u64,
+}
+
+impl ClipEntry {
+    fn to_json(&self, full: bool) -> Value {
+        let text = self.text.as_deref().map(|text| {
+            if full {
+                text.to_string()
+            } else {
+                text.chars().take(PREVIEW_LENGTH).collect()
+            }
+        });
+        json!({
+            "id": self.id,
+            "kind": if self.image.is_some() { "image" } else { "text" },
+            "mime": self.mime,
+            "text": text,
+            "image": self.image,
+            "size": self.size,
+            "pinned": self.pinned,
+            "time": self.time
+        })
+    }
+}
+
+/// Summary of the history, the clipboard domain of [`AppState`]. Entries
+/// can hold megabytes of text, so they stay behind the module's lock.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ClipboardState {
+    pub count: usize,
+    /// Id of the newest entry
+    pub latest: Option<u64>,
+}
+
+/// Clipboard history persisted to disk
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardStore {
+    /// Newest first
+    pub entries: Vec<ClipEntry>,
+    next_id: u64,
+}
+
+impl ClipboardStore {
+    /// Directory copied images are stored in
+    fn image_dir() -> Option<PathBuf> {
+        let dir = module::data_dir()?.join("clipboard");
+        fs::create_dir_all(&dir).ok()?;
+        Some(dir)
+    }
+
+    /// Record contents, returning the entry id
+    fn add(&mut self, mime: &str, data: &[u8], max_entries: usize) -> u64 {
+        let hash = content_hash(data);
+        if let Some(index) = self.entries.iter().position(|e| e.hash == hash) {
+            // Seen before: move it to the top
+            let mut entry = self.entries.remove(index);
+            entry.time = current_timestamp();
+            let id = entry.id;
+            self.entries.insert(0, entry);
+            return id;
+        }
+
+        let (text, image) = if mime.starts_with("image/") {
+            let ext = mime.trim_start_matches("image/");
+            let path = Self::image_dir().map(|d| d.join(format!("{:016x}.{}", hash, ext)));
+            if let Some(path) = &path {
+                if let Err(e) = fs::write(path, data) {
+                    warn!("Failed to store clipboard image: {}", e);
+                }
+            }
+            (None, path)
+        } else {
+            (Some(String::from_utf8_lossy(data).into_owned()), None)
+        };
+
+        self.next_id += 1;
+        self.entries.insert(
+            0,
+            ClipEntry {
+                id: self.next_id,
+                mime: mime.to_string(),
+                text,
+                image,
+                size: data.len(),
+                hash,
+                pinned: false,
+                time: current_timestamp(),
+            },
+        );
+        self.trim(max_entries);
+        self.next_id
+    }
+
+    /// Drop the oldest unpinned entries beyond the limit
+    fn trim(&mut self, max_entries: usize) {
+        let mut unpinned = 0;
+        self.entries.retain(|e| {
+            if e.pinned {
+                return true;
+            }
+            unpinned += 1;
+            if unpinned <= max_entries {
+                return true;
+            }
+            remove_image(e);
+            false
+        });
+    }
+
+    fn remove(&mut self, id: u64) -> bool {
+        let Some(index) = self.entries.iter().position(|e| e.id == id) else {
+            return false;
+        };
+        remove_image(&self.entries.remove(index));
+        true
+    }
+
+    /// Remove everything except pinned entries
+    fn clear(&mut self) {
+        self.entries.retain(|e| {
+            if !e.pinned {
+                remove_image(e);
+            }
+            e.pinned
+        });
+    }
+
+    fn summary(&self) -> ClipboardState {
+        ClipboardState {
+            count: self.entries.len(),
+            latest: self.entries.first().map(|e| e.id),
+        }
+    }
+
+    fn search(&self, query: &str) -> Vec<&ClipEntry> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|e| e.text.as_ref().is_some_and(|t| t.to_lowercase().contains(&query)))
+            .collect()
+    }
+}
+
+/// Clipboard history for picker widgets
+pub struct ClipboardModule {
+    settings: ClipboardSettings,
+    events: EventBus,
+    state: Arc<AppState>,
+    store: Mutex<ClipboardStore>,
+    file: JsonFile,
+}
+
+impl ClipboardModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        let file = JsonFile::new("clipboard.json");
+        let store: ClipboardStore = file.load();
+        state.clipboard.set(store.summary());
+        Self {
+            settings: config.section("clipboard"),
+            events,
+            state,
+            store: Mutex::new(store),
+            file,
+        }
+    }
+
+    /// Persist the history and publish its summary
+    fn save(&self, store: &ClipboardStore) {
+        self.file.save(store);
+        self.state.clipboard.set(store.summary());
+    }
+
+    /// Read the current selection and record it
+    async fn capture(&self) {
+        let Some(types) = wl_paste(&["--list-types"]).await else {
+            return;
+        };
+        let types = String::from_utf8_lossy(&types).into_owned();
+        let types: Vec<&str> = types.lines().map(str::trim).collect();
+        if types.contains(&PASSWORD_HINT) {
+            debug!("Skipping password manager selection");
+            return;
+        }
+
+        let image = types
+            .iter()
+            .find(|t| **t == "image/png")
+            .or_else(|| types.iter().find(|t| t.starts_with("image/")));
+        let (mime, limit) = match image {
+            Some(mime) => (*mime, self.settings.max_image_size),
+            None => match TEXT_TYPES.iter().find(|t| types.contains(t)) {
+                Some(mime) => (*mime, self.settings.max_text_size),
+                None => return,
+            },
+        };
+
+        let Some(data) = wl_paste(&["--no-newline", "--type", mime]).await else {
+            return;
+        };
+        if data.is_empty() || data.len() > limit {
+            debug!("Skipping clipboard entry of {} bytes", data.len());
+            return;
+        }
+
+        let id = {
+            let mut store = self.store.lock().await;
+            let id = store.add(mime, &data, self.settings.max_entries);
+            self.save(&store);
+            id
+        };
+        self.events.emit("clipboard", json!({"id": id}));
+    }
+
+    /// Put an entry back on the clipboard
+    async fn copy(&self, id: u64) -> Result<(), String> {
+        let entry = {
+            let store = self.store.lock().await;
+            store.entries.iter().find(|e| e.id == id).cloned()
+        };
+        let entry = entry.ok_or_else(|| format!("no entry: {}", id))?;
+        let data = match (&entry.text, &entry.image) {
+            (Some(text), _) => text.clone().into_bytes(),
+            (None, Some(path)) => fs::read(path).map_err(|e| e.to_string())?,
+            (None, None) => return Err("empty entry".to_string()),
+        };
+
+        let mut child = Command::new("wl-copy")
+            .args(["--type", &entry.mime])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("wl-copy failed: {}", e))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&data).await.map_err(|e| e.to_string())?;
+        }
+        let status = child.wait().await.map_err(|e| e.to_string())?;
+        if !status.success() {
+            return Err(format!("wl-copy exited with {}", status));
+        }
+        Ok(())
+    }
+
+    /// Run `wl-paste --watch` until it exits
+    async fn watch(&self) {
+        let child = Command::new("wl-paste")
+            .args(["--watch", "echo"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                warn!("Failed to run wl-paste: {}", e);
+                return;
+            }
+        };
+        let Some(stdout) = child.stdout.take() else {
+            return;
+        };
+
+        info!("Watching clipboard");
+        let mut lines = AsyncBufReader::new(stdout).lines();
+        while let Ok(Some(_)) = lines.next_line().await {
+            self.capture().await;
+        }
+    }
+}
+
+#[async_trait]
+impl Module for ClipboardModule {
+    fn name(&self) -> &'static str {
+        "clipboard"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["clipboard"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.clipboard.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"clipboard": self.state.clipboard.get()})
+    }
+
+    /// `clipboard [list [limit]|search <query>|get|copy|delete|pin|unpin <id>|clear]`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        let action = args.first().copied().unwrap_or("list");
+        if action == "search" {
+            let query = args[1..].join(" ");
+            let store = self.store.lock().await;
+            let list: Vec<Value> = store.search(&query).iter().map(|e| e.to_json(false)).collect();
+            return json!({"type": "clipboard", "list": list});
+        }
+        if action == "list" {
+            let limit = args.get(1).and_then(|l| l.parse().ok()).unwrap_or(usize::MAX);
+            let store = self.store.lock().await;
+            let list: Vec<Value> = store.entries.iter().take(limit).map(|e| e.to_json(false)).collect();
+            return json!({"type": "clipboard", "list": list});
+        }
+        if action == "clear" {
+            let mut store = self.store.lock().await;
+            store.clear();
+            self.save(&store);
+            return ok();
+        }
+
+        let Some(id) = args.get(1).and_then(|id| id.parse::<u64>().ok()) else {
+            return module::error(format!("usage: clipboard {} <id>", action));
+        };
+        if action == "copy" {
+            return match self.copy(id).await {
+                Ok(()) => ok(),
+                Err(e) => module::error(e),
+            };
+        }
+
+        let mut store = self.store.lock().await;
+        let reply = match action {
+            "get" => match store.entries.iter().find(|e| e.id == id) {
+                Some(entry) => return entry.to_json(true),
+                None => return module::error(format!("no entry: {}", id)),
+            },
+            "delete" if store.remove(id) => ok(),
+            "pin" | "unpin" => match store.entries.iter_mut().find(|e| e.id == id) {
+                Some(entry) => {
+                    entry.pinned = action == "pin";
+                    ok()
+                }
+                None => module::error(format!("no entry: {}", id)),
+            },
+            "delete" => module::error(format!("no entry: {}", id)),
+            other => module::error(format!("unknown clipboard action: {}", other)),
+        };
+        self.save(&store);
+        reply
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            self.watch().await;
+            tokio::time::sleep(RESTART_DELAY).await;
+        }
+    }
+}
+
+/// Run wl-paste and return its stdout
+async fn wl_paste(args: &[&str]) -> Option<Vec<u8>> {
+    let output = Command::new("wl-paste").args(args).output().await.ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn remove_image(entry: &ClipEntry) {
+    if let Some(path) = &entry.image {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup_and_limits() {
+        let mut store = ClipboardStore::default();
+        let first = store.add("text/plain", b"hello", 2);
+        store.add("text/plain", b"world", 2);
+        assert_eq!(store.add("text/plain", b"hello", 2), first);
+        assert_eq!(store.entries.len(), 2);
+        assert_eq!(store.entries[0].id, first);
+
+        store.entries[1].pinned = true;
+        store.add("text/plain", b"three", 2);
+        store.add("text/plain", b"four", 2);
+        // Two unpinned plus the pinned one
+        let texts: Vec<_> = store.entries.iter().map(|e| e.text.clone().unwrap()).collect();
+        assert_eq!(texts, vec!["four", "three", "world"]);
+
+        store.clear();
+        assert_eq!(store.entries.len(), 1);
+        assert!(store.entries[0].pinned);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut store = ClipboardStore::default();
+        store.add("text/plain", b"Hello World", 10);
+        store.add("text/plain", b"goodbye", 10);
+        let found = store.search("world");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].text.as_deref(), Some("Hello World"));
+    }
+}
@@ -0,0 +1,688 @@
+//! Clock, calendar, timers, stopwatches and alarms
+//!
+//! Publishes a `clock` event on every minute (or second) boundary with the
+//! local time and any configured extra time zones, so widgets don't each
+//! run their own timer. Timers, stopwatches and alarms are created over
+//! IPC and stored in wall-clock time, so they survive daemon restarts;
+//! ones that ran out while the daemon was down fire on startup marked
+//! `late`. Expiry emits a `timer` event and sends a notification.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use jiff::civil::{Date, DateTime, Time, Weekday};
+use jiff::tz::TimeZone;
+use jiff::{Span, Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+use zbus::zvariant::Value as DbusValue;
+use zbus::Connection;
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::{self, ok, JsonFile, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// Expired this long ago counts as late (daemon was down or suspended)
+const LATE_MS: i64 = 60_000;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ClockSettings {
+    /// Extra IANA time zones to publish, e.g. `America/New_York`
+    timezones: Vec<String>,
+    /// Publish every second instead of every minute
+    seconds: bool,
+    /// First day of the week in `calendar`: `monday` or `sunday`
+    week_start: String,
+    /// Send a desktop notification when a timer or alarm fires
+    notify: bool,
+}
+
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            timezones: Vec::new(),
+            seconds: false,
+            week_start: "monday".to_string(),
+            notify: true,
+        }
+    }
+}
+
+/// A countdown timer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Timer {
+    name: String,
+    /// Total length in milliseconds
+    duration: u64,
+    /// Unix milliseconds it runs out at, `None` while paused
+    deadline: Option<i64>,
+    /// Milliseconds left when paused
+    remaining: u64,
+}
+
+impl Timer {
+    fn remaining(&self, now: i64) -> u64 {
+        match self.deadline {
+            Some(deadline) => deadline.saturating_sub(now).max(0) as u64,
+            None => self.remaining,
+        }
+    }
+
+    fn to_json(&self, now: i64) -> Value {
+        json!({
+            "name": self.name,
+            "duration": self.duration,
+            "remaining": self.remaining(now),
+            "running": self.deadline.is_some()
+        })
+    }
+}
+
+/// A stopwatch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Stopwatch {
+    name: String,
+    /// Unix milliseconds of the last start, `None` while stopped
+    started: Option<i64>,
+    /// Milliseconds accumulated before the last start
+    elapsed: u64,
+}
+
+impl Stopwatch {
+    fn elapsed(&self, now: i64) -> u64 {
+        let running = self.started.map_or(0, |started| now.saturating_sub(started).max(0) as u64);
+        self.elapsed + running
+    }
+
+    fn to_json(&self, now: i64) -> Value {
+        json!({
+            "name": self.name,
+            "elapsed": self.elapsed(now),
+            "running": self.started.is_some()
+        })
+    }
+}
+
+/// An alarm at a wall-clock time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Alarm {
+    name: String,
+    /// Unix milliseconds of the next ring
+    at: i64,
+    /// Ring again at the same local time tomorrow
+    daily: bool,
+}
+
+impl Alarm {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "at": self.at / 1000,
+            "time": local(self.at).map(|z| z.strftime("%Y-%m-%d %H:%M").to_string()),
+            "daily": self.daily
+        })
+    }
+}
+
+/// Persisted timers, stopwatches and alarms
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClockStore {
+    timers: Vec<Timer>,
+    stopwatches: Vec<Stopwatch>,
+    alarms: Vec<Alarm>,
+}
+
+/// Clock domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClockState {
+    /// Unix second of the last `clock` event
+    pub tick: i64,
+    pub store: ClockStore,
+}
+
+impl ClockStore {
+    /// Remove what ran out by `now` and return `(kind, name, late)` for each
+    fn expire(&mut self, now: i64) -> Vec<(&'static str, String, bool)> {
+        let mut fired = Vec::new();
+
+        self.timers.retain(|t| match t.deadline {
+            Some(deadline) if deadline <= now => {
+                fired.push(("timer", t.name.clone(), now - deadline > LATE_MS));
+                false
+            }
+            _ => true,
+        });
+
+        self.alarms.retain_mut(|a| {
+            if a.at > now {
+                return true;
+            }
+            fired.push(("alarm", a.name.clone(), now - a.at > LATE_MS));
+            if !a.daily {
+                return false;
+            }
+            // Same local time on the next day that is still ahead
+            while a.at <= now {
+                match local(a.at).and_then(|z| z.checked_add(Span::new().days(1)).ok()) {
+                    Some(next) => a.at = next.timestamp().as_millisecond(),
+                    None => return false,
+                }
+            }
+            true
+        });
+
+        fired
+    }
+}
+
+/// Time publisher and timer service
+pub struct ClockModule {
+    settings: ClockSettings,
+    events: EventBus,
+    state: Arc<AppState>,
+    store: Mutex<ClockStore>,
+    file: JsonFile,
+    session: Mutex<Option<Connection>>,
+}
+
+impl ClockModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        let file = JsonFile::new("clock.json");
+        let store: ClockStore = file.load();
+        state.clock.update(|clock| clock.store = store.clone());
+        Self {
+            settings: config.section("clock"),
+            events,
+            state,
+            store: Mutex::new(store),
+            file,
+            session: Mutex::new(None),
+        }
+    }
+
+    /// Persist the store and publish it
+    fn save(&self, store: &ClockStore) {
+        self.file.save(store);
+        self.state.clock.update(|clock| clock.store = store.clone());
+    }
+
+    /// Local time and every configured zone
+    fn times(&self, now: Timestamp) -> Value {
+        let zones: Vec<Value> = self
+            .settings
+            .timezones
+            .iter()
+            .filter_map(|name| match TimeZone::get(name) {
+                Ok(tz) => Some(zone_json(&now.to_zoned(tz), self.settings.seconds)),
+                Err(e) => {
+                    warn!("Unknown time zone {}: {}", name, e);
+                    None
+                }
+            })
+            .collect();
+        json!({
+            "unix": now.as_second(),
+            "local": zone_json(&now.to_zoned(TimeZone::system()), self.settings.seconds),
+            "zones": zones
+        })
+    }
+
+    async fn session(&self) -> Option<Connection> {
+        let mut session = self.session.lock().await;
+        if session.is_none() {
+            *session = Connection::session().await.ok();
+        }
+        session.clone()
+    }
+
+    async fn notify(&self, summary: &str, body: &str) {
+        let Some(session) = self.session().await else {
+            return;
+        };
+        let hints = HashMap::from([("urgency", DbusValue::U8(2))]);
+        let result = session
+            .call_method(
+                Some("org.freedesktop.Notifications"),
+                "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"),
+                "Notify",
+                &(
+                    "terra-shell",
+                    0u32,
+                    "alarm-symbolic",
+                    summary,
+                    body,
+                    Vec::<&str>::new(),
+                    hints,
+                    -1i32,
+                ),
+            )
+            .await;
+        if let Err(e) = result {
+            warn!("Failed to send notification: {}", e);
+        }
+    }
+
+    /// Fire what ran out
+    async fn check_expired(&self, now: i64) {
+        let fired = {
+            let mut store = self.store.lock().await;
+            let fired = store.expire(now);
+            if !fired.is_empty() {
+                self.save(&store);
+            }
+            fired
+        };
+
+        for (kind, name, late) in fired {
+            info!("{} {} fired", kind, name);
+            self.events.emit("timer", json!({"kind": kind, "name": name, "late": late}));
+            if self.settings.notify {
+                let summary = if kind == "alarm" { "Alarm" } else { "Timer finished" };
+                self.notify(summary, &name).await;
+            }
+        }
+    }
+
+    async fn handle_timer(&self, args: &[&str], now: i64) -> Value {
+        let mut store = self.store.lock().await;
+        let reply = match args {
+            [] => {
+                let list: Vec<Value> = store.timers.iter().map(|t| t.to_json(now)).collect();
+                return json!({"type": "timers", "list": list});
+            }
+            ["start", name, duration] => {
+                let Some(duration) = parse_duration(duration) else {
+                    return module::error(format!("invalid duration: {}", duration));
+                };
+                let Some(deadline) = deadline(now, duration) else {
+                    return module::error("duration too long");
+                };
+                store.timers.retain(|t| t.name != *name);
+                let timer = Timer {
+                    name: name.to_string(),
+                    duration,
+                    deadline: Some(deadline),
+                    remaining: duration,
+                };
+                let reply = timer.to_json(now);
+                store.timers.push(timer);
+                reply
+            }
+            ["cancel", name] => {
+                let before = store.timers.len();
+                store.timers.retain(|t| t.name != *name);
+                if store.timers.len() == before {
+                    return module::error(format!("no timer: {}", name));
+                }
+                ok()
+            }
+            [action @ ("pause" | "resume"), name] => {
+                let Some(timer) = store.timers.iter_mut().find(|t| t.name == *name) else {
+                    return module::error(format!("no timer: {}", name));
+                };
+                if *action == "pause" {
+                    timer.remaining = timer.remaining(now);
+                    timer.deadline = None;
+                } else if timer.deadline.is_none() {
+                    let Some(deadline) = deadline(now, timer.remaining) else {
+                        return module::error("duration too long");
+                    };
+                    timer.deadline = Some(deadline);
+                }
+                timer.to_json(now)
+            }
+            _ => return module::error("usage: timer [start <name> <duration>|pause|resume|cancel <name>]"),
+        };
+        self.save(&store);
+        reply
+    }
+
+    async fn handle_stopwatch(&self, args: &[&str], now: i64) -> Value {
+        let mut store = self.store.lock().await;
+        let (action, name) = match args {
+            [] => {
+                let list: Vec<Value> = store.stopwatches.iter().map(|s| s.to_json(now)).collect();
+                return json!({"type": "stopwatches", "list": list});
+            }
+            [action, name] => (*action, *name),
+            _ => return module::error("usage: stopwatch [start|stop|reset|remove <name>]"),
+        };
+
+        if action == "remove" {
+            store.stopwatches.retain(|s| s.name != name);
+            self.save(&store);
+            return ok();
+        }
+        if action == "start" && !store.stopwatches.iter().any(|s| s.name == name) {
+            store.stopwatches.push(Stopwatch {
+                name: name.to_string(),
+                started: None,
+                elapsed: 0,
+            });
+        }
+        let Some(stopwatch) = store.stopwatches.iter_mut().find(|s| s.name == name) else {
+            return module::error(format!("no stopwatch: {}", name));
+        };
+        match action {
+            "start" => {
+                stopwatch.started.get_or_insert(now);
+            }
+            "stop" => {
+                stopwatch.elapsed = stopwatch.elapsed(now);
+                stopwatch.started = None;
+            }
+            "reset" => {
+                stopwatch.elapsed = 0;
+                stopwatch.started = stopwatch.started.map(|_| now);
+            }
+            other => return module::error(format!("unknown stopwatch action: {}", other)),
+        }
+        let reply = stopwatch.to_json(now);
+        self.save(&store);
+        reply
+    }
+
+    async fn handle_alarm(&self, args: &[&str], now: i64) -> Value {
+        let mut store = self.store.lock().await;
+        let reply = match args {
+            [] => {
+                let list: Vec<Value> = store.alarms.iter().map(Alarm::to_json).collect();
+                return json!({"type": "alarms", "list": list});
+            }
+            ["set", name, when, rest @ ..] => {
+                let daily = rest.first() == Some(&"daily");
+                let Some(at) = parse_alarm(when, now) else {
+                    return module::error(format!("invalid time: {}", when));
+                };
+                store.alarms.retain(|a| a.name != *name);
+                let alarm = Alarm {
+                    name: name.to_string(),
+                    at,
+                    daily,
+                };
+                let reply = alarm.to_json();
+                store.alarms.push(alarm);
+                reply
+            }
+            ["remove", name] => {
+                let before = store.alarms.len();
+                store.alarms.retain(|a| a.name != *name);
+                if store.alarms.len() == before {
+                    return module::error(format!("no alarm: {}", name));
+                }
+                ok()
+            }
+            _ => return module::error("usage: alarm [set <name> <HH:MM|YYYY-MM-DDTHH:MM> [daily]|remove <name>]"),
+        };
+        self.save(&store);
+        reply
+    }
+}
+
+#[async_trait]
+impl Module for ClockModule {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["clock", "calendar", "timer", "stopwatch", "alarm"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.clock.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        let now = Timestamp::now();
+        let ms = now.as_millisecond();
+        let store = self.state.clock.get().store;
+        json!({
+            "clock": self.times(now),
+            "timers": store.timers.iter().map(|t| t.to_json(ms)).collect::<Vec<_>>(),
+            "stopwatches": store.stopwatches.iter().map(|s| s.to_json(ms)).collect::<Vec<_>>(),
+            "alarms": store.alarms.iter().map(Alarm::to_json).collect::<Vec<_>>()
+        })
+    }
+
+    /// `clock`, `calendar [YYYY-MM]`, `timer ...`, `stopwatch ...`, `alarm ...`
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        let now = Timestamp::now();
+        match command {
+            "clock" => {
+                let mut reply = self.times(now);
+                reply["type"] = json!("clock");
+                reply
+            }
+            "calendar" => {
+                let today = now.to_zoned(TimeZone::system()).date();
+                let month = match args.first() {
+                    Some(arg) => match parse_month(arg) {
+                        Some(month) => month,
+                        None => return module::error(format!("invalid month: {}", arg)),
+                    },
+                    None => today.first_of_month(),
+                };
+                let sunday_first = self.settings.week_start == "sunday";
+                calendar(month, today, sunday_first)
+            }
+            "timer" => self.handle_timer(args, now.as_millisecond()).await,
+            "stopwatch" => self.handle_stopwatch(args, now.as_millisecond()).await,
+            _ => self.handle_alarm(args, now.as_millisecond()).await,
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            let now = Timestamp::now();
+            self.check_expired(now.as_millisecond()).await;
+
+            let second = now.as_second();
+            if self.settings.seconds || second.rem_euclid(60) == 0 {
+                self.state.clock.update(|clock| clock.tick = second);
+                self.events.emit("clock", self.times(now));
+            }
+
+            // Wake just after the next second boundary
+            let into_second = now.as_millisecond() - second * 1000;
+            sleep(Duration::from_millis((1000 - into_second).clamp(1, 1000) as u64 + 5)).await;
+        }
+    }
+}
+
+/// Zoned time from Unix milliseconds in the system zone
+fn local(ms: i64) -> Option<Zoned> {
+    Some(Timestamp::from_millisecond(ms).ok()?.to_zoned(TimeZone::system()))
+}
+
+fn zone_json(zoned: &Zoned, seconds: bool) -> Value {
+    let time = if seconds { "%H:%M:%S" } else { "%H:%M" };
+    json!({
+        "timezone": zoned.time_zone().iana_name().unwrap_or("UTC"),
+        "date": zoned.strftime("%Y-%m-%d").to_string(),
+        "time": zoned.strftime(time).to_string(),
+        "weekday": zoned.strftime("%A").to_string(),
+        "offset": zoned.strftime("%:z").to_string()
+    })
+}
+
+/// Month grid padded to whole weeks
+fn calendar(month: Date, today: Date, sunday_first: bool) -> Value {
+    let first = month.first_of_month();
+    let weekday_offset = |weekday: Weekday| {
+        if sunday_first {
+            weekday.to_sunday_zero_offset()
+        } else {
+            weekday.to_monday_zero_offset()
+        }
+    };
+    let mut day = first
+        .checked_sub(Span::new().days(weekday_offset(first.weekday())))
+        .unwrap_or(first);
+    let last = first.last_of_month();
+
+    let mut weeks = Vec::new();
+    while day <= last {
+        let mut week = Vec::with_capacity(7);
+        for _ in 0..7 {
+            week.push(json!({
+                "day": day.day(),
+                "date": day.to_string(),
+                "current_month": day.month() == first.month(),
+                "today": day == today
+            }));
+            day = day.tomorrow().unwrap_or(day);
+        }
+        weeks.push(week);
+    }
+
+    json!({
+        "type": "calendar",
+        "year": first.year(),
+        "month": first.month(),
+        "name": first.strftime("%B").to_string(),
+        "today": today.to_string(),
+        "weeks": weeks
+    })
+}
+
+/// `YYYY-MM` to the first of that month
+fn parse_month(s: &str) -> Option<Date> {
+    let (year, month) = s.split_once('-')?;
+    Date::new(year.parse().ok()?, month.parse().ok()?, 1).ok()
+}
+
+/// `90`, `90s`, `5m`, `1h30m`, `1h2m3s` to milliseconds, at most `i64::MAX`
+pub fn parse_duration(s: &str) -> Option<u64> {
+    if let Ok(seconds) = s.parse::<u64>() {
+        return seconds.checked_mul(1000).filter(|&ms| i64::try_from(ms).is_ok());
+    }
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let value: u64 = number.parse().ok()?;
+        number.clear();
+        let unit = match c {
+            'h' => 3_600_000,
+            'm' => 60_000,
+            's' => 1000,
+            _ => return None,
+        };
+        total = total.checked_add(value.checked_mul(unit)?)?;
+    }
+    i64::try_from(total).ok()?;
+    (number.is_empty() && total > 0).then_some(total)
+}
+
+/// `duration` ms after `now`, unless that is past the end of time
+fn deadline(now: i64, duration: u64) -> Option<i64> {
+    now.checked_add(i64::try_from(duration).ok()?)
+}
+
+/// `HH:MM` (next occurrence) or `YYYY-MM-DDTHH:MM` in local time, to Unix ms
+fn parse_alarm(s: &str, now: i64) -> Option<i64> {
+    let tz = TimeZone::system();
+    if let Ok(datetime) = s.parse::<DateTime>() {
+        return Some(datetime.to_zoned(tz).ok()?.timestamp().as_millisecond());
+    }
+
+    let time: Time = s.parse().ok()?;
+    let today = Timestamp::from_millisecond(now).ok()?.to_zoned(tz.clone()).date();
+    let mut at = today.to_datetime(time).to_zoned(tz.clone()).ok()?;
+    if at.timestamp().as_millisecond() <= now {
+        at = today.tomorrow().ok()?.to_datetime(time).to_zoned(tz).ok()?;
+    }
+    Some(at.timestamp().as_millisecond())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Some(90_000));
+        assert_eq!(parse_duration("5m"), Some(300_000));
+        assert_eq!(parse_duration("1h30m"), Some(5_400_000));
+        assert_eq!(parse_duration("1h2m3s"), Some(3_723_000));
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("10m5"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("18446744073709551615"), None);
+        assert_eq!(parse_duration("9999999999999999h"), None);
+        assert_eq!(parse_duration("5124095576030431h5124095576030431h"), None);
+    }
+
+    #[test]
+    fn test_calendar() {
+        let month = Date::new(2026, 2, 1).unwrap();
+        let today = Date::new(2026, 2, 14).unwrap();
+        let cal = calendar(month, today, false);
+        let weeks = cal["weeks"].as_array().unwrap();
+        // 2026-02-01 is a Sunday: the first week starts on Monday 26 January
+        assert_eq!(weeks[0][0]["date"], "2026-01-26");
+        assert_eq!(weeks[0][6]["day"], 1);
+        assert_eq!(weeks.len(), 5);
+        assert_eq!(weeks[2][5]["today"], true);
+
+        let cal = calendar(month, today, true);
+        assert_eq!(cal["weeks"][0][0]["date"], "2026-02-01");
+        assert_eq!(cal["weeks"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut store = ClockStore::default();
+        store.timers.push(Timer {
+            name: "tea".to_string(),
+            duration: 1000,
+            deadline: Some(10_000),
+            remaining: 1000,
+        });
+        store.timers.push(Timer {
+            name: "paused".to_string(),
+            duration: 1000,
+            deadline: None,
+            remaining: 500,
+        });
+        store.alarms.push(Alarm {
+            name: "wake".to_string(),
+            at: 5_000,
+            daily: true,
+        });
+
+        let fired = store.expire(10_000 + LATE_MS + 1);
+        assert_eq!(
+            fired,
+            vec![("timer", "tea".to_string(), true), ("alarm", "wake".to_string(), true)]
+        );
+        assert_eq!(store.timers.len(), 1);
+        // Daily alarms move to the next day
+        assert_eq!(store.alarms.len(), 1);
+        assert!(store.alarms[0].at > 10_000 + LATE_MS);
+        assert!(store.expire(20_000 + LATE_MS).is_empty());
+    }
+
+    #[test]
+    fn test_stopwatch_elapsed() {
+        let stopwatch = Stopwatch {
+            name: "run".to_string(),
+            started: Some(1_000),
+            elapsed: 500,
+        };
+        assert_eq!(stopwatch.elapsed(3_000), 2_500);
+    }
+}
@@ -0,0 +1,107 @@
+//! Daemon configuration
+//!
+//! Loaded from `~/.config/terra-shell/config.json`. Every module has an
+//! optional section under `modules`, keyed by module name:
+//!
+//! ```json
+//! {
+//!     "modules": {
+//!         "media": { "enabled": false },
+//!         "custom": { "scripts": [{ "name": "vpn", "command": "vpn-status" }] }
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use tracing::warn;
+
+/// Top-level daemon configuration
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Per-module settings, keyed by module name
+    pub modules: HashMap<String, serde_json::Value>,
+}
+
+impl Config {
+    /// Get config file path
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("terra-shell").join("config.json"))
+    }
+
+    /// Load config from disk, falling back to defaults
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => return Self::default(),
+        };
+
+        match serde_json::from_str(&contents) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Invalid config {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Whether a module is enabled, given its default
+    pub fn is_enabled(&self, module: &str, default: bool) -> bool {
+        self.modules
+            .get(module)
+            .and_then(|section| section.get("enabled"))
+            .and_then(|enabled| enabled.as_bool())
+            .unwrap_or(default)
+    }
+
+    /// Deserialize a module's settings section, using defaults for missing fields
+    pub fn section<T: DeserializeOwned + Default>(&self, module: &str) -> T {
+        match self.modules.get(module) {
+            Some(section) => serde_json::from_value(section.clone()).unwrap_or_else(|e| {
+                warn!("Invalid config for module {}: {}", module, e);
+                T::default()
+            }),
+            None => T::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_enabled() {
+        let config: Config =
+            serde_json::from_str(r#"{"modules": {"media": {"enabled": false}}}"#).unwrap();
+        assert!(!config.is_enabled("media", true));
+        assert!(config.is_enabled("battery", true));
+        assert!(!config.is_enabled("battery", false));
+    }
+
+    #[test]
+    fn test_module_section() {
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(default)]
+        struct Settings {
+            interval: u64,
+        }
+
+        let config: Config =
+            serde_json::from_str(r#"{"modules": {"battery": {"enabled": true, "interval": 60}}}"#)
+                .unwrap();
+        let settings: Settings = config.section("battery");
+        assert_eq!(settings.interval, 60);
+        let settings: Settings = config.section("audio");
+        assert_eq!(settings.interval, 0);
+    }
+}
@@ -0,0 +1,118 @@
+//! D-Bus helpers shared by modules
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value as Json};
+use zbus::zvariant::{OwnedValue, Value};
+
+/// Convert a D-Bus value into JSON for IPC replies
+pub fn to_json(value: &Value<'_>) -> Json {
+    match value {
+        Value::U8(v) => json!(v),
+        Value::Bool(v) => json!(v),
+        Value::I16(v) => json!(v),
+        Value::U16(v) => json!(v),
+        Value::I32(v) => json!(v),
+        Value::U32(v) => json!(v),
+        Value::I64(v) => json!(v),
+        Value::U64(v) => json!(v),
+        Value::F64(v) => json!(v),
+        Value::Str(v) => json!(v.as_str()),
+        Value::Signature(v) => json!(v.to_string()),
+        Value::ObjectPath(v) => json!(v.as_str()),
+        Value::Value(v) => to_json(v),
+        Value::Array(array) => Json::Array(array.iter().map(to_json).collect()),
+        Value::Dict(dict) => {
+            let map = dict
+                .iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        Value::Str(s) => s.to_string(),
+                        other => to_json(other).to_string(),
+                    };
+                    (key, to_json(v))
+                })
+                .collect();
+            Json::Object(map)
+        }
+        Value::Structure(s) => Json::Array(s.fields().iter().map(to_json).collect()),
+        Value::Fd(_) => Json::Null,
+    }
+}
+
+/// Typed property lookup in a `GetAll` / `a{sv}` reply
+pub fn prop<'a, T>(props: &'a HashMap<String, OwnedValue>, name: &str) -> Option<T>
+where
+    T: TryFrom<&'a Value<'a>>,
+    T::Error: Into<zbus::zvariant::Error>,
+{
+    props.get(name)?.downcast_ref::<T>().ok()
+}
+
+/// String property, empty when missing
+pub fn prop_string(props: &HashMap<String, OwnedValue>, name: &str) -> String {
+    prop::<&str>(props, name).unwrap_or_default().to_string()
+}
+
+/// Property map from literals, standing in for a `GetAll` reply in tests
+#[cfg(test)]
+pub fn props<V>(entries: &[(&str, V)]) -> HashMap<String, OwnedValue>
+where
+    V: Clone + Into<Value<'static>>,
+{
+    entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), OwnedValue::try_from(v.clone().into()).unwrap()))
+        .collect()
+}
+
+/// Decode a NUL-terminated `ay` bytestring, as UDisks2 uses for paths
+pub fn bytestring(value: &Value<'_>) -> Option<String> {
+    let Value::Array(array) = value else {
+        return None;
+    };
+    let bytes: Vec<u8> = array
+        .iter()
+        .map_while(|byte| match byte {
+            Value::U8(0) => None,
+            Value::U8(b) => Some(*b),
+            _ => None,
+        })
+        .collect();
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut dict = HashMap::new();
+        dict.insert("label", Value::from("Quit"));
+        dict.insert("enabled", Value::from(true));
+        let value = Value::from(dict);
+        assert_eq!(to_json(&value), json!({"label": "Quit", "enabled": true}));
+
+        let value = Value::from(vec![1u32, 2, 3]);
+        assert_eq!(to_json(&value), json!([1, 2, 3]));
+    }
+
+    #[test]
+    fn test_prop() {
+        let mut props = HashMap::new();
+        props.insert("Name".to_string(), OwnedValue::from(42u32));
+        props.insert("Label".to_string(), OwnedValue::try_from(Value::from("USB")).unwrap());
+        assert_eq!(prop::<u32>(&props, "Name"), Some(42));
+        assert_eq!(prop::<bool>(&props, "Name"), None);
+        assert_eq!(prop_string(&props, "Label"), "USB");
+        assert_eq!(prop_string(&props, "Missing"), "");
+    }
+
+    #[test]
+    fn test_bytestring() {
+        let value = Value::from(b"/dev/sdb1\0".to_vec());
+        assert_eq!(bytestring(&value).as_deref(), Some("/dev/sdb1"));
+        assert_eq!(bytestring(&Value::from("/dev/sdb1")), None);
+    }
+}
@@ -0,0 +1,546 @@
+//! Removable drives (UDisks2 over the system bus)
+//!
+//! Mirrors the drive, block and filesystem objects exported by
+//! `org.freedesktop.UDisks2`, keeping removable drives (USB sticks, SD
+//! cards, optical media) with their filesystems, mount points and usage.
+//! A `drive` event is emitted when one is plugged in or removed. Mounting
+//! goes through UDisks2, so it lands under `/run/media/$USER` with the
+//! usual polkit rules.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Value as Json};
+use tokio::sync::Mutex;
+use tokio::time::{interval, sleep, Duration};
+use tracing::{debug, info, warn};
+use zbus::fdo::{ManagedObjects, ObjectManagerProxy};
+use zbus::message::Type as MessageType;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{Connection, MatchRule, MessageStream};
+
+use crate::dbus::{bytestring, prop, prop_string};
+use crate::events::EventBus;
+use crate::module::{self, Module};
+use crate::state::Version;
+use crate::system::{self, DiskUsage};
+use crate::AppState;
+
+const UDISKS: &str = "org.freedesktop.UDisks2";
+const UDISKS_PATH: &str = "/org/freedesktop/UDisks2";
+const DRIVE_INTERFACE: &str = "org.freedesktop.UDisks2.Drive";
+const BLOCK_INTERFACE: &str = "org.freedesktop.UDisks2.Block";
+const FILESYSTEM_INTERFACE: &str = "org.freedesktop.UDisks2.Filesystem";
+
+/// Coalesce the burst of signals a plugged in drive causes
+const REFRESH_DELAY: Duration = Duration::from_millis(300);
+
+/// How often usage of mounted volumes is re-read
+const USAGE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A filesystem on a removable drive
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Volume {
+    /// e.g. `/dev/sdb1`
+    pub device: String,
+    pub label: String,
+    /// e.g. `vfat`, `exfat`, `ext4`
+    pub fstype: String,
+    /// Bytes
+    pub size: u64,
+    pub mount_point: Option<String>,
+    pub usage: Option<DiskUsage>,
+    #[serde(skip)]
+    path: String,
+}
+
+/// A removable drive and its filesystems
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Drive {
+    /// UDisks2 drive id, stable across plugs
+    pub id: String,
+    /// Vendor and model
+    pub name: String,
+    /// Bytes, 0 without media
+    pub size: u64,
+    /// Connection bus, e.g. `usb` or `sdio`
+    pub bus: String,
+    pub ejectable: bool,
+    pub can_power_off: bool,
+    pub volumes: Vec<Volume>,
+    #[serde(skip)]
+    path: String,
+}
+
+impl Drive {
+    /// Match by id, or by the device or label of one of its volumes
+    fn matches(&self, name: &str) -> bool {
+        self.id == name || self.volumes.iter().any(|v| v.matches(name))
+    }
+}
+
+impl Volume {
+    /// Match by device, with or without `/dev/`, or label
+    fn matches(&self, name: &str) -> bool {
+        self.device == name
+            || self.device.strip_prefix("/dev/") == Some(name)
+            || (!self.label.is_empty() && self.label == name)
+    }
+}
+
+/// Removable drive monitor and mount control
+pub struct DrivesModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl DrivesModule {
+    pub fn new(state: Arc<AppState>, events: EventBus) -> Self {
+        Self {
+            state,
+            events,
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connection(&self) -> Result<Connection, Json> {
+        self.connection
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| module::error("udisks not available"))
+    }
+
+    /// Re-read every UDisks2 object, announcing drives that came or went
+    async fn refresh(&self, connection: &Connection, announce: bool) -> zbus::Result<()> {
+        let objects = ObjectManagerProxy::builder(connection)
+            .destination(UDISKS)?
+            .path(UDISKS_PATH)?
+            .build()
+            .await?
+            .get_managed_objects()
+            .await?;
+        let mut drives = parse_objects(&objects);
+        // statvfs blocks, for long on an unresponsive network mount
+        let drives = tokio::task::spawn_blocking(move || {
+            for volume in drives.iter_mut().flat_map(|d| d.volumes.iter_mut()) {
+                volume.usage = volume.mount_point.as_deref().and_then(system::disk_usage);
+            }
+            drives
+        })
+        .await
+        .map_err(|e| zbus::Error::Failure(e.to_string()))?;
+
+        let before = self.state.drives.get();
+        if self.state.drives.set(drives.clone()) {
+            debug!(
+                "Drives: {:?}",
+                drives.iter().map(|d| &d.id).collect::<Vec<_>>()
+            );
+        }
+        if announce {
+            let ids =
+                |drives: &[Drive]| drives.iter().map(|d| d.id.clone()).collect::<HashSet<_>>();
+            let (old, new) = (ids(&before), ids(&drives));
+            for drive in drives.iter().filter(|d| !old.contains(&d.id)) {
+                info!("Drive added: {}", drive.name);
+                self.events
+                    .emit("drive", json!({"action": "added", "drive": drive}));
+            }
+            for drive in before.iter().filter(|d| !new.contains(&d.id)) {
+                info!("Drive removed: {}", drive.name);
+                self.events
+                    .emit("drive", json!({"action": "removed", "drive": drive}));
+            }
+        }
+        Ok(())
+    }
+
+    fn find_volume(&self, name: &str) -> Result<Volume, Json> {
+        self.state
+            .drives
+            .read(|drives| {
+                drives
+                    .iter()
+                    .flat_map(|d| &d.volumes)
+                    .find(|v| v.matches(name))
+                    .cloned()
+            })
+            .ok_or_else(|| module::error(format!("unknown volume: {}", name)))
+    }
+
+    fn find_drive(&self, name: &str) -> Result<Drive, Json> {
+        self.state
+            .drives
+            .read(|drives| drives.iter().find(|d| d.matches(name)).cloned())
+            .ok_or_else(|| module::error(format!("unknown drive: {}", name)))
+    }
+
+    /// Call a UDisks2 method taking only an options dict
+    async fn call(&self, path: &str, interface: &str, method: &str) -> Result<zbus::Message, Json> {
+        let connection = self.connection().await?;
+        let options: HashMap<&str, Value> = HashMap::new();
+        connection
+            .call_method(Some(UDISKS), path, Some(interface), method, &(options,))
+            .await
+            .map_err(|e| module::error(e.to_string()))
+    }
+
+    async fn mount(&self, volume: &Volume) -> Result<String, Json> {
+        if let Some(mount_point) = &volume.mount_point {
+            return Ok(mount_point.clone());
+        }
+        let reply = self
+            .call(&volume.path, FILESYSTEM_INTERFACE, "Mount")
+            .await?;
+        reply
+            .body()
+            .deserialize::<String>()
+            .map_err(|e| module::error(e.to_string()))
+    }
+
+    async fn unmount(&self, volume: &Volume) -> Result<(), Json> {
+        if volume.mount_point.is_some() {
+            self.call(&volume.path, FILESYSTEM_INTERFACE, "Unmount")
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Unmount everything on the drive, then eject and/or power it off
+    async fn eject(&self, drive: &Drive) -> Result<(), Json> {
+        for volume in &drive.volumes {
+            self.unmount(volume).await?;
+        }
+        if drive.ejectable {
+            self.call(&drive.path, DRIVE_INTERFACE, "Eject").await?;
+        }
+        if drive.can_power_off {
+            self.call(&drive.path, DRIVE_INTERFACE, "PowerOff").await?;
+        }
+        Ok(())
+    }
+
+    async fn run_command(&self, action: &str, target: &str) -> Result<Json, Json> {
+        match action {
+            "mount" => {
+                let volume = self.find_volume(target)?;
+                let mount_point = self.mount(&volume).await?;
+                Ok(json!({"type": "mount", "device": volume.device, "mount_point": mount_point}))
+            }
+            "unmount" => {
+                self.unmount(&self.find_volume(target)?).await?;
+                Ok(module::ok())
+            }
+            "eject" | "power-off" => {
+                self.eject(&self.find_drive(target)?).await?;
+                Ok(module::ok())
+            }
+            _ => Err(module::error(
+                "usage: drives [mount|unmount <volume>|eject|power-off <drive>]",
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl Module for DrivesModule {
+    fn name(&self) -> &'static str {
+        "drives"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["drives"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.drives.version())
+    }
+
+    async fn snapshot(&self) -> Json {
+        json!({"drives": self.state.drives.get()})
+    }
+
+    /// `drives`, `drives mount|unmount <volume>`, `drives eject|power-off <drive>`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Json {
+        let [action, target] = args else {
+            if !args.is_empty() {
+                return module::error(
+                    "usage: drives [mount|unmount <volume>|eject|power-off <drive>]",
+                );
+            }
+            return json!({"type": "drives", "drives": self.state.drives.get()});
+        };
+
+        let result = self.run_command(action, target).await;
+        if let Ok(connection) = self.connection().await {
+            let _ = self.refresh(&connection, true).await;
+        }
+        result.unwrap_or_else(|e| e)
+    }
+
+    async fn run(self: Arc<Self>) {
+        let connection = match Connection::system().await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Failed to connect to system bus: {}", e);
+                return;
+            }
+        };
+        *self.connection.lock().await = Some(connection.clone());
+
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .sender(UDISKS)
+            .map(|b| b.build());
+        let mut signals = match rule {
+            Ok(rule) => match MessageStream::for_match_rule(rule, &connection, None).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to watch UDisks2 signals: {}", e);
+                    return;
+                }
+            },
+            Err(e) => {
+                warn!("Invalid match rule: {}", e);
+                return;
+            }
+        };
+
+        match self.refresh(&connection, false).await {
+            Ok(()) => info!("Connected to UDisks2"),
+            Err(e) => warn!("UDisks2 not available: {}", e),
+        }
+
+        let mut usage = interval(USAGE_INTERVAL);
+        loop {
+            tokio::select! {
+                signal = signals.next() => {
+                    if signal.is_none() {
+                        break;
+                    }
+                    // Drain the burst, then refresh once
+                    sleep(REFRESH_DELAY).await;
+                    while let Ok(Some(_)) = tokio::time::timeout(Duration::ZERO, signals.next()).await {}
+                }
+                _ = usage.tick() => {
+                    let mounted = self.state.drives.read(|drives| {
+                        drives.iter().flat_map(|d| &d.volumes).any(|v| v.mount_point.is_some())
+                    });
+                    if !mounted {
+                        continue;
+                    }
+                }
+            }
+            if let Err(e) = self.refresh(&connection, true).await {
+                debug!("Failed to refresh UDisks2 objects: {}", e);
+            }
+        }
+    }
+
+    async fn stop(&self) {
+        self.connection.lock().await.take();
+    }
+}
+
+/// Build the removable drive list from `GetManagedObjects`
+fn parse_objects(objects: &ManagedObjects) -> Vec<Drive> {
+    let interface = |path: &OwnedObjectPath, name: &str| -> Option<&HashMap<String, OwnedValue>> {
+        objects
+            .get(path)?
+            .iter()
+            .find(|(iface, _)| iface.as_str() == name)
+            .map(|(_, props)| props)
+    };
+
+    let mut paths: Vec<&OwnedObjectPath> = objects.keys().collect();
+    paths.sort_by_key(|p| p.as_str());
+
+    let mut drives = Vec::new();
+    for path in &paths {
+        let Some(props) = interface(path, DRIVE_INTERFACE) else {
+            continue;
+        };
+        let bus = prop_string(props, "ConnectionBus");
+        let removable = prop::<bool>(props, "Removable").unwrap_or(false)
+            || prop::<bool>(props, "MediaRemovable").unwrap_or(false)
+            || bus == "usb"
+            || bus == "sdio";
+        if !removable {
+            continue;
+        }
+        let name = [prop_string(props, "Vendor"), prop_string(props, "Model")]
+            .into_iter()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        drives.push(Drive {
+            id: prop_string(props, "Id"),
+            name,
+            size: prop::<u64>(props, "Size").unwrap_or(0),
+            bus,
+            ejectable: prop::<bool>(props, "Ejectable").unwrap_or(false),
+            can_power_off: prop::<bool>(props, "CanPowerOff").unwrap_or(false),
+            volumes: Vec::new(),
+            path: path.to_string(),
+        });
+    }
+
+    for path in &paths {
+        let (Some(block), Some(filesystem)) = (
+            interface(path, BLOCK_INTERFACE),
+            interface(path, FILESYSTEM_INTERFACE),
+        ) else {
+            continue;
+        };
+        if prop::<bool>(block, "HintIgnore").unwrap_or(false) {
+            continue;
+        }
+        let drive_path = prop::<zbus::zvariant::ObjectPath>(block, "Drive");
+        let Some(drive) = drives
+            .iter_mut()
+            .find(|d| drive_path.as_ref().is_some_and(|p| p.as_str() == d.path))
+        else {
+            continue;
+        };
+        let mount_point = match filesystem.get("MountPoints").map(|v| &**v) {
+            Some(Value::Array(points)) => points.iter().find_map(bytestring),
+            _ => None,
+        };
+        drive.volumes.push(Volume {
+            device: block
+                .get("Device")
+                .and_then(|v| bytestring(v))
+                .unwrap_or_default(),
+            label: prop_string(block, "IdLabel"),
+            fstype: prop_string(block, "IdType"),
+            size: prop::<u64>(block, "Size").unwrap_or(0),
+            mount_point,
+            usage: None,
+            path: path.to_string(),
+        });
+    }
+    drives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::names::{InterfaceName, OwnedInterfaceName};
+
+    use crate::dbus::props;
+
+    fn interface(name: &'static str) -> OwnedInterfaceName {
+        InterfaceName::from_static_str_unchecked(name).into()
+    }
+
+    fn path(path: &str) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(format!("{}/{}", UDISKS_PATH, path)).unwrap()
+    }
+
+    fn bytes(s: &str) -> Value<'static> {
+        Value::from(format!("{}\0", s).into_bytes())
+    }
+
+    #[test]
+    fn test_parse_objects() {
+        let mut objects = ManagedObjects::new();
+        objects.insert(
+            path("drives/Samsung_SSD"),
+            HashMap::from([(
+                interface(DRIVE_INTERFACE),
+                props(&[
+                    ("Id", Value::from("Samsung-SSD")),
+                    ("ConnectionBus", Value::from("")),
+                ]),
+            )]),
+        );
+        objects.insert(
+            path("drives/SanDisk_Ultra"),
+            HashMap::from([(
+                interface(DRIVE_INTERFACE),
+                props(&[
+                    ("Id", Value::from("SanDisk-Ultra-123")),
+                    ("Vendor", Value::from("SanDisk ")),
+                    ("Model", Value::from("Ultra")),
+                    ("ConnectionBus", Value::from("usb")),
+                    ("Size", Value::from(32_000_000_000u64)),
+                    ("CanPowerOff", Value::from(true)),
+                ]),
+            )]),
+        );
+        let drive = |name: &str| {
+            Value::from(
+                zbus::zvariant::ObjectPath::try_from(format!("{}/drives/{}", UDISKS_PATH, name))
+                    .unwrap(),
+            )
+        };
+        objects.insert(
+            path("block_devices/sdb1"),
+            HashMap::from([
+                (
+                    interface(BLOCK_INTERFACE),
+                    props(&[
+                        ("Device", bytes("/dev/sdb1")),
+                        ("Drive", drive("SanDisk_Ultra")),
+                        ("IdLabel", Value::from("STICK")),
+                        ("IdType", Value::from("vfat")),
+                        ("Size", Value::from(31_000_000_000u64)),
+                    ]),
+                ),
+                (
+                    interface(FILESYSTEM_INTERFACE),
+                    props(&[(
+                        "MountPoints",
+                        Value::from(vec![b"/run/media/me/STICK\0".to_vec()]),
+                    )]),
+                ),
+            ]),
+        );
+        // The whole disk has no filesystem, the SSD's partition is not removable
+        objects.insert(
+            path("block_devices/sdb"),
+            HashMap::from([(
+                interface(BLOCK_INTERFACE),
+                props(&[
+                    ("Device", bytes("/dev/sdb")),
+                    ("Drive", drive("SanDisk_Ultra")),
+                ]),
+            )]),
+        );
+        objects.insert(
+            path("block_devices/nvme0n1p2"),
+            HashMap::from([
+                (
+                    interface(BLOCK_INTERFACE),
+                    props(&[
+                        ("Device", bytes("/dev/nvme0n1p2")),
+                        ("Drive", drive("Samsung_SSD")),
+                    ]),
+                ),
+                (interface(FILESYSTEM_INTERFACE), HashMap::new()),
+            ]),
+        );
+
+        let drives = parse_objects(&objects);
+        assert_eq!(drives.len(), 1);
+        let drive = &drives[0];
+        assert_eq!(drive.name, "SanDisk Ultra");
+        assert!(drive.can_power_off);
+        assert_eq!(drive.volumes.len(), 1);
+        let volume = &drive.volumes[0];
+        assert_eq!(volume.device, "/dev/sdb1");
+        assert_eq!(volume.label, "STICK");
+        assert_eq!(volume.mount_point.as_deref(), Some("/run/media/me/STICK"));
+
+        assert!(drive.matches("sdb1"));
+        assert!(drive.matches("STICK"));
+        assert!(drive.matches("SanDisk-Ultra-123"));
+        assert!(!drive.matches("sdb"));
+    }
+}
@@ -0,0 +1,71 @@
+//! Event stream for subscribed IPC clients
+//!
+//! Modules emit named events on the [`EventBus`], clients that sent
+//! `subscribe` receive them as JSON lines interleaved with command replies.
+
+use serde_json::{json, Value};
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// Events buffered per subscriber before it starts missing some
+const CAPACITY: usize = 256;
+
+/// An emitted event, already serialized as sent to clients
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: &'static str,
+    pub payload: Value,
+}
+
+/// Fan-out of module events to IPC subscribers
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        Self { tx }
+    }
+
+    /// Send an event, `data` fields are merged next to `type` and `event`
+    pub fn emit(&self, name: &'static str, data: Value) {
+        let mut payload = json!({"type": "event", "event": name});
+        if let (Some(payload), Value::Object(data)) = (payload.as_object_mut(), data) {
+            payload.extend(data);
+        }
+        debug!("Event {}", name);
+        // No subscribers is fine
+        let _ = self.tx.send(Event { name, payload });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_emit() {
+        let bus = EventBus::new();
+        let mut rx = bus.subscribe();
+        bus.emit("osd", json!({"kind": "volume", "value": 40}));
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.name, "osd");
+        assert_eq!(
+            event.payload,
+            json!({"type": "event", "event": "osd", "kind": "volume", "value": 40})
+        );
+    }
+}
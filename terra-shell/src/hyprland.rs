@@ -4,11 +4,19 @@
 
 use std::env;
 use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde_json::{json, Value};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
 use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
 
+use crate::icons::IconResolver;
+use crate::module::{error, ok, Module};
+use crate::state::Version;
+use crate::AppState;
+
 /// Hyprland event types
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(tag = "type")]
@@ -17,6 +25,12 @@ pub enum HyprlandEvent {
     ActiveWindowChanged { class: String, title: String },
     MonitorFocused { name: String },
     FullscreenChanged { fullscreen: bool },
+    LayoutChanged { keyboard: String, layout: String },
+    /// `window` when a single window rather than a monitor is shared
+    ScreencastChanged { active: bool, window: bool },
+    /// Empty `name` when back in the default submap
+    SubmapChanged { name: String },
+    ConfigReloaded,
 }
 
 /// Workspace info
@@ -45,14 +59,312 @@ pub struct ActiveWindow {
     pub fullscreen: i32,
 }
 
-/// Get Hyprland socket path
-fn get_socket_path(socket_type: &str) -> Option<PathBuf> {
+/// Workspace reference inside client and monitor info
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct WorkspaceRef {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Window info from `j/clients`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Client {
+    pub address: String,
+    pub class: String,
+    pub title: String,
+    /// Top left corner in layout coordinates
+    pub at: [i32; 2],
+    pub size: [i32; 2],
+    pub workspace: WorkspaceRef,
+    pub monitor: i32,
+    pub mapped: bool,
+    pub hidden: bool,
+}
+
+/// Monitor info from `j/monitors`
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Monitor {
+    pub id: i32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub scale: f64,
+    pub focused: bool,
+    #[serde(rename = "activeWorkspace")]
+    pub active_workspace: WorkspaceRef,
+}
+
+/// Keyboard info from `j/devices`
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Keyboard {
+    pub name: String,
+    /// Configured layouts, comma separated (e.g. `us,de`)
+    pub layout: String,
+    pub variant: String,
+    /// Human readable name of the active layout
+    pub active_keymap: String,
+    /// Whether this is the keyboard Hyprland treats as primary
+    #[serde(default)]
+    pub main: bool,
+}
+
+/// A keybind from `j/binds`
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Bind {
+    /// e.g. `["SUPER", "SHIFT"]`
+    pub modifiers: Vec<&'static str>,
+    pub key: String,
+    pub dispatcher: String,
+    pub arg: String,
+    /// From `bindd`, empty otherwise
+    pub description: String,
+    /// Submap the bind belongs to, empty for the default one
+    pub submap: String,
+    pub mouse: bool,
+}
+
+/// Hyprland domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HyprlandState {
+    pub active_workspace: i32,
+    pub active_window_title: String,
+    pub active_window_class: String,
+    pub keyboards: Vec<Keyboard>,
+    /// A screencast session is sharing a monitor or window
+    pub screencast: bool,
+    /// Active submap, empty for the default one
+    pub submap: String,
+    pub binds: Vec<Bind>,
+}
+
+impl HyprlandState {
+    /// Binds of `submap`, empty for the default one
+    fn binds_of(&self, submap: &str) -> Vec<&Bind> {
+        self.binds.iter().filter(|b| b.submap == submap).collect()
+    }
+}
+
+/// Workspaces, active window and dispatching
+pub struct HyprlandModule {
+    state: Arc<AppState>,
+    icons: Arc<IconResolver>,
+    events: broadcast::Sender<HyprlandEvent>,
+}
+
+impl HyprlandModule {
+    pub fn new(state: Arc<AppState>, icons: Arc<IconResolver>) -> Self {
+        let (events, _) = broadcast::channel(32);
+        Self { state, icons, events }
+    }
+
+    /// Load the current state, then keep it up to date from events
+    async fn follow(&self, mut rx: broadcast::Receiver<HyprlandEvent>) {
+        // Initialize state with current values
+        let workspace = get_active_workspace().await;
+        let window = get_active_window().await;
+        let keyboards = get_keyboards().await;
+        let binds = get_binds().await;
+        self.state.hyprland.update(|s| {
+            s.binds = binds;
+            if let Some(ws) = workspace {
+                s.active_workspace = ws;
+            }
+            if let Some(win) = window {
+                s.active_window_title = win.title;
+                s.active_window_class = win.class;
+            }
+            s.keyboards = keyboards;
+        });
+
+        while let Ok(event) = rx.recv().await {
+            match event {
+                HyprlandEvent::WorkspaceChanged { id, .. } => {
+                    self.state.hyprland.update(|s| s.active_workspace = id);
+                }
+                HyprlandEvent::ActiveWindowChanged { class, title } => {
+                    self.state.hyprland.update(|s| {
+                        s.active_window_class = class;
+                        s.active_window_title = title;
+                    });
+                }
+                HyprlandEvent::ScreencastChanged { active, .. } => {
+                    self.state.hyprland.update(|s| s.screencast = active);
+                }
+                HyprlandEvent::SubmapChanged { name } => {
+                    self.state.hyprland.update(|s| s.submap = name);
+                }
+                HyprlandEvent::ConfigReloaded => {
+                    let binds = get_binds().await;
+                    self.state.hyprland.update(|s| s.binds = binds);
+                }
+                HyprlandEvent::LayoutChanged { keyboard, layout } => {
+                    let mut known = false;
+                    self.state.hyprland.update(|s| {
+                        if let Some(k) = s.keyboards.iter_mut().find(|k| k.name == keyboard) {
+                            k.active_keymap = layout;
+                            known = true;
+                        }
+                    });
+                    if !known {
+                        // Hotplugged keyboard, re-read the device list
+                        let keyboards = get_keyboards().await;
+                        self.state.hyprland.update(|s| s.keyboards = keyboards);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Module for HyprlandModule {
+    fn name(&self) -> &'static str {
+        "hyprland"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["workspace", "workspaces", "window", "clients", "dispatch", "keyboard", "switch-layout", "submap", "binds"]
+    }
+
+    /// Also bumped by app index changes, which can change window icons
+    fn version(&self) -> Option<Version> {
+        [self.state.hyprland.version(), self.icons.version()]
+            .into_iter()
+            .max_by_key(|v| v.seq)
+    }
+
+    async fn snapshot(&self) -> Value {
+        let s = self.state.hyprland.get();
+        json!({
+            "workspace": s.active_workspace,
+            "window": {
+                "title": s.active_window_title,
+                "class": s.active_window_class,
+                "icon": self.icons.for_class(&s.active_window_class)
+            },
+            "keyboard": {
+                "layout": main_keyboard(&s.keyboards).map(|k| k.active_keymap.as_str()),
+                "keyboards": s.keyboards
+            },
+            // Binds only while in a submap, as a cheat sheet
+            "submap": {
+                "name": (!s.submap.is_empty()).then_some(&s.submap),
+                "binds": if s.submap.is_empty() { Vec::new() } else { s.binds_of(&s.submap) }
+            }
+        })
+    }
+
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        match command {
+            "workspace" | "workspaces" => {
+                let workspaces = get_workspaces().await;
+                json!({
+                    "type": "workspaces",
+                    "active": self.state.hyprland.read(|s| s.active_workspace),
+                    "list": workspaces
+                })
+            }
+            "window" => {
+                let s = self.state.hyprland.get();
+                json!({
+                    "type": "window",
+                    "title": s.active_window_title,
+                    "class": s.active_window_class,
+                    "icon": self.icons.for_class(&s.active_window_class)
+                })
+            }
+            "clients" => {
+                let clients: Vec<Value> = get_clients()
+                    .await
+                    .into_iter()
+                    .map(|client| {
+                        let icon = self.icons.for_class(&client.class);
+                        let mut value = json!(client);
+                        value["icon"] = json!(icon);
+                        value
+                    })
+                    .collect();
+                json!({"type": "clients", "list": clients})
+            }
+            "keyboard" => {
+                let s = self.state.hyprland.get();
+                json!({
+                    "type": "keyboard",
+                    "layout": main_keyboard(&s.keyboards).map(|k| k.active_keymap.as_str()),
+                    "keyboards": s.keyboards
+                })
+            }
+            "submap" => {
+                let s = self.state.hyprland.get();
+                json!({
+                    "type": "submap",
+                    "name": (!s.submap.is_empty()).then_some(&s.submap),
+                    "binds": s.binds_of(&s.submap)
+                })
+            }
+            "binds" => {
+                // All binds, or those of one submap (`default` for the top level)
+                let s = self.state.hyprland.get();
+                let binds: Vec<&Bind> = match args.first() {
+                    Some(&"default") => s.binds_of(""),
+                    Some(submap) => s.binds_of(submap),
+                    None => s.binds.iter().collect(),
+                };
+                json!({"type": "binds", "list": binds})
+            }
+            "switch-layout" => {
+                // next, prev or a layout index, on the main keyboard unless one is named
+                let Some(target) = args.first() else {
+                    return error("usage: switch-layout next|prev|<index> [keyboard]");
+                };
+                if target.parse::<u32>().is_err() && !matches!(*target, "next" | "prev") {
+                    return error(format!("invalid layout: {}", target));
+                }
+                let keyboard = match args.get(1) {
+                    Some(name) => name.to_string(),
+                    None => self.state.hyprland.read(|s| {
+                        main_keyboard(&s.keyboards)
+                            .map(|k| k.name.clone())
+                            .unwrap_or_else(|| "all".to_string())
+                    }),
+                };
+                if !switch_layout(&keyboard, target).await {
+                    return error("switchxkblayout failed");
+                }
+                ok()
+            }
+            "dispatch" => {
+                if args.len() >= 2 && !dispatch(args[0], &args[1..].join(" ")).await {
+                    return error("dispatch failed");
+                }
+                ok()
+            }
+            _ => error("unknown command"),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        // Subscribe before connecting so no early event is missed. The
+        // reader is part of this future, so stopping the module stops it.
+        let rx = self.events.subscribe();
+        tokio::join!(monitor(self.events.clone()), self.follow(rx));
+    }
+}
+
+/// Runtime directory of the running Hyprland instance, where it and its
+/// helpers (e.g. hyprsunset) keep their sockets
+pub fn instance_dir() -> Option<PathBuf> {
     let instance = env::var("HYPRLAND_INSTANCE_SIGNATURE").ok()?;
     let runtime_dir = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| "/tmp".to_string());
-    Some(PathBuf::from(format!(
-        "{}/hypr/{}/.socket{}.sock",
-        runtime_dir, instance, socket_type
-    )))
+    Some(PathBuf::from(runtime_dir).join("hypr").join(instance))
+}
+
+/// Get Hyprland socket path
+fn get_socket_path(socket_type: &str) -> Option<PathBuf> {
+    Some(instance_dir()?.join(format!(".socket{}.sock", socket_type)))
 }
 
 /// Send command to Hyprland and get response
@@ -69,6 +381,130 @@ pub async fn hyprctl(command: &str) -> Option<String> {
     Some(response)
 }
 
+/// Send command to Hyprland and read the whole response
+async fn hyprctl_all(command: &str) -> Option<String> {
+    let socket_path = get_socket_path("")?;
+    
+    let mut stream = UnixStream::connect(&socket_path).await.ok()?;
+    stream.write_all(command.as_bytes()).await.ok()?;
+    
+    let mut response = String::new();
+    tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response)
+        .await
+        .ok()?;
+    
+    Some(response)
+}
+
+/// Get all windows
+pub async fn get_clients() -> Vec<Client> {
+    let Some(response) = hyprctl_all("j/clients").await else {
+        return vec![];
+    };
+    serde_json::from_str(&response).unwrap_or_default()
+}
+
+/// Get all monitors
+pub async fn get_monitors() -> Vec<Monitor> {
+    let Some(response) = hyprctl_all("j/monitors").await else {
+        return vec![];
+    };
+    serde_json::from_str(&response).unwrap_or_default()
+}
+
+/// Get all keyboards with their active layout
+pub async fn get_keyboards() -> Vec<Keyboard> {
+    let Some(response) = hyprctl_all("j/devices").await else {
+        return vec![];
+    };
+    parse_keyboards(&response)
+}
+
+/// Parse the `keyboards` list of `j/devices`
+fn parse_keyboards(json: &str) -> Vec<Keyboard> {
+    #[derive(serde::Deserialize)]
+    struct Devices {
+        #[serde(default)]
+        keyboards: Vec<Keyboard>,
+    }
+    
+    serde_json::from_str::<Devices>(json)
+        .map(|d| d.keyboards)
+        .unwrap_or_default()
+}
+
+/// Get all keybinds
+pub async fn get_binds() -> Vec<Bind> {
+    let Some(response) = hyprctl_all("j/binds").await else {
+        return vec![];
+    };
+    parse_binds(&response)
+}
+
+/// Parse `j/binds`
+fn parse_binds(json: &str) -> Vec<Bind> {
+    #[derive(serde::Deserialize)]
+    struct RawBind {
+        modmask: u32,
+        #[serde(default)]
+        submap: String,
+        key: String,
+        #[serde(default)]
+        mouse: bool,
+        #[serde(default)]
+        description: String,
+        dispatcher: String,
+        #[serde(default)]
+        arg: String,
+    }
+
+    let raw: Vec<RawBind> = serde_json::from_str(json).unwrap_or_default();
+    raw.into_iter()
+        .map(|b| Bind {
+            modifiers: modifiers(b.modmask),
+            key: b.key,
+            dispatcher: b.dispatcher,
+            arg: b.arg,
+            description: b.description,
+            submap: b.submap,
+            mouse: b.mouse,
+        })
+        .collect()
+}
+
+/// Names of the modifiers set in a Hyprland modmask
+fn modifiers(mask: u32) -> Vec<&'static str> {
+    const NAMES: [(u32, &str); 8] = [
+        (64, "SUPER"),
+        (4, "CTRL"),
+        (8, "ALT"),
+        (1, "SHIFT"),
+        (2, "CAPS"),
+        (16, "MOD2"),
+        (32, "MOD3"),
+        (128, "MOD5"),
+    ];
+    NAMES
+        .iter()
+        .filter(|(bit, _)| mask & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// The main keyboard, or the first one if none is flagged
+fn main_keyboard(keyboards: &[Keyboard]) -> Option<&Keyboard> {
+    keyboards.iter().find(|k| k.main).or_else(|| keyboards.first())
+}
+
+/// Switch layout of a keyboard (`next`, `prev` or an index)
+pub async fn switch_layout(keyboard: &str, target: &str) -> bool {
+    let cmd = format!("switchxkblayout {} {}", keyboard, target);
+    
+    hyprctl(&cmd)
+        .await
+        .is_some_and(|response| response.trim() == "ok")
+}
+
 /// Get all workspaces
 pub async fn get_workspaces() -> Vec<Workspace> {
     let socket_path = match get_socket_path("") {
@@ -147,19 +583,12 @@ pub async fn get_active_workspace() -> Option<i32> {
 
 /// Dispatch command to Hyprland
 pub async fn dispatch(command: &str, args: &str) -> bool {
-    let socket_path = match get_socket_path("") {
-        Some(p) => p,
-        None => return false,
-    };
-    
     let cmd = format!("dispatch {} {}", command, args);
     
-    let mut stream = match UnixStream::connect(&socket_path).await {
-        Ok(s) => s,
-        Err(_) => return false,
-    };
-    
-    stream.write_all(cmd.as_bytes()).await.is_ok()
+    // Hyprland answers "ok" on success or an error message
+    hyprctl(&cmd)
+        .await
+        .is_some_and(|response| response.trim() == "ok")
 }
 
 /// Monitor Hyprland events via socket2
@@ -224,6 +653,96 @@ fn parse_event(line: &str) -> Option<HyprlandEvent> {
         "fullscreen" => Some(HyprlandEvent::FullscreenChanged {
             fullscreen: data == "1",
         }),
+        "activelayout" => {
+            let (keyboard, layout) = data.split_once(',')?;
+            Some(HyprlandEvent::LayoutChanged {
+                keyboard: keyboard.to_string(),
+                layout: layout.to_string(),
+            })
+        }
+        "screencast" => {
+            let (state, owner) = data.split_once(',')?;
+            Some(HyprlandEvent::ScreencastChanged {
+                active: state == "1",
+                window: owner == "1",
+            })
+        }
+        "submap" => Some(HyprlandEvent::SubmapChanged {
+            name: data.to_string(),
+        }),
+        "configreloaded" => Some(HyprlandEvent::ConfigReloaded),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        match parse_event("activelayout>>at-translated-set-2-keyboard,German") {
+            Some(HyprlandEvent::LayoutChanged { keyboard, layout }) => {
+                assert_eq!(keyboard, "at-translated-set-2-keyboard");
+                assert_eq!(layout, "German");
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(
+            parse_event("screencast>>1,0"),
+            Some(HyprlandEvent::ScreencastChanged { active: true, window: false })
+        ));
+        match parse_event("submap>>resize") {
+            Some(HyprlandEvent::SubmapChanged { name }) => assert_eq!(name, "resize"),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(
+            parse_event("submap>>"),
+            Some(HyprlandEvent::SubmapChanged { name }) if name.is_empty()
+        ));
+        assert!(parse_event("workspace>>3").is_some());
+        assert!(parse_event("garbage").is_none());
+    }
+
+    #[test]
+    fn test_parse_binds() {
+        let json = r#"[
+            {"locked": false, "mouse": false, "release": false, "repeat": false,
+             "non_consuming": false, "has_description": true, "modmask": 65, "submap": "",
+             "key": "Q", "keycode": 0, "catch_all": false, "description": "Close window",
+             "dispatcher": "killactive", "arg": ""},
+            {"locked": false, "mouse": false, "release": false, "repeat": true,
+             "non_consuming": false, "has_description": false, "modmask": 0,
+             "submap": "resize", "key": "l", "keycode": 0, "catch_all": false,
+             "description": "", "dispatcher": "resizeactive", "arg": "10 0"}
+        ]"#;
+        let binds = parse_binds(json);
+        assert_eq!(binds.len(), 2);
+        assert_eq!(binds[0].modifiers, vec!["SUPER", "SHIFT"]);
+        assert_eq!(binds[0].description, "Close window");
+        assert_eq!(binds[1].submap, "resize");
+        assert!(binds[1].modifiers.is_empty());
+        assert_eq!(binds[1].arg, "10 0");
+        assert!(parse_binds("not json").is_empty());
+    }
+
+    #[test]
+    fn test_parse_keyboards() {
+        let json = r#"{
+            "mice": [],
+            "keyboards": [
+                {"address": "0x1", "name": "power-button", "rules": "", "model": "",
+                 "layout": "us", "variant": "", "options": "",
+                 "active_keymap": "English (US)", "main": false},
+                {"address": "0x2", "name": "at-keyboard", "rules": "", "model": "",
+                 "layout": "us,de", "variant": ",", "options": "",
+                 "active_keymap": "German", "main": true}
+            ]
+        }"#;
+        let keyboards = parse_keyboards(json);
+        assert_eq!(keyboards.len(), 2);
+        let main = main_keyboard(&keyboards).unwrap();
+        assert_eq!(main.name, "at-keyboard");
+        assert_eq!(main.active_keymap, "German");
+    }
+}
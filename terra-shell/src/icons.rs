@@ -0,0 +1,565 @@
+//! Icon lookup
+//!
+//! Resolves icon names to files following the freedesktop icon theme
+//! spec: the configured theme (or GTK's) first, then the themes it
+//! inherits, then `hicolor`, then loose files in the base dirs such as
+//! `/usr/share/pixmaps`. Within a theme an exact size match wins over the
+//! closest one. Window classes are first mapped to a desktop entry, by
+//! `StartupWMClass` or id, using the app index the apps module publishes.
+//!
+//! Lookups and parsed themes are cached, the caches are dropped whenever
+//! the app index changes since that is also when icons get installed.
+//! Such a change bumps the resolver's [`Version`], so modules whose
+//! snapshots contain resolved icons can report it.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::module::{self, Module};
+use crate::state::{Shared, Version};
+
+const EXTENSIONS: [&str; 3] = ["png", "svg", "xpm"];
+
+/// Lookups cached before the cache is dropped, since names and sizes come
+/// from IPC clients
+const CACHE_LIMIT: usize = 4096;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct IconSettings {
+    /// Icon theme, empty to use GTK's
+    theme: String,
+    /// Size in pixels icons are looked up at
+    size: u32,
+    scale: u32,
+}
+
+impl Default for IconSettings {
+    fn default() -> Self {
+        Self {
+            theme: String::new(),
+            size: 48,
+            scale: 1,
+        }
+    }
+}
+
+/// What the app index knows about an app's icon
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppIcon {
+    /// Desktop file id without `.desktop`
+    pub id: String,
+    pub wm_class: String,
+    pub icon: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+/// A subdirectory of a theme, e.g. `48x48/apps`
+#[derive(Debug, Clone)]
+struct ThemeDir {
+    path: String,
+    size: u32,
+    scale: u32,
+    kind: DirType,
+    min: u32,
+    max: u32,
+    threshold: u32,
+}
+
+impl ThemeDir {
+    fn matches(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+        match self.kind {
+            DirType::Fixed => self.size == size,
+            DirType::Scalable => (self.min..=self.max).contains(&size),
+            DirType::Threshold => (self.size.saturating_sub(self.threshold)
+                ..=self.size + self.threshold)
+                .contains(&size),
+        }
+    }
+
+    fn distance(&self, size: u32, scale: u32) -> u32 {
+        let wanted = size * scale;
+        let (min, max) = match self.kind {
+            DirType::Fixed => (self.size, self.size),
+            DirType::Scalable => (self.min, self.max),
+            DirType::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size + self.threshold,
+            ),
+        };
+        let (min, max) = (min * self.scale, max * self.scale);
+        if wanted < min {
+            min - wanted
+        } else {
+            wanted.saturating_sub(max)
+        }
+    }
+}
+
+/// A parsed `index.theme`
+#[derive(Debug, Default)]
+struct IconTheme {
+    /// `<base dir>/<theme>` dirs that exist
+    roots: Vec<PathBuf>,
+    dirs: Vec<ThemeDir>,
+    inherits: Vec<String>,
+}
+
+impl IconTheme {
+    fn load(name: &str, base_dirs: &[PathBuf]) -> Option<Self> {
+        let roots: Vec<PathBuf> = base_dirs
+            .iter()
+            .map(|b| b.join(name))
+            .filter(|r| r.is_dir())
+            .collect();
+        let index = roots
+            .iter()
+            .find_map(|r| fs::read_to_string(r.join("index.theme")).ok())?;
+        let mut theme = parse_index(&index);
+        theme.roots = roots;
+        Some(theme)
+    }
+
+    /// An icon file in one of this theme's dirs, exact size first
+    fn lookup(&self, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        let find = |dir: &ThemeDir| {
+            self.roots.iter().find_map(|root| {
+                EXTENSIONS.iter().find_map(|ext| {
+                    let path = root.join(&dir.path).join(format!("{}.{}", name, ext));
+                    path.is_file().then_some(path)
+                })
+            })
+        };
+
+        if let Some(path) = self
+            .dirs
+            .iter()
+            .filter(|d| d.matches(size, scale))
+            .find_map(find)
+        {
+            return Some(path);
+        }
+        self.dirs
+            .iter()
+            .filter_map(|d| Some((d.distance(size, scale), find(d)?)))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, path)| path)
+    }
+}
+
+/// Parse the `[Icon Theme]` group and the directory groups it lists
+fn parse_index(contents: &str) -> IconTheme {
+    let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut current = String::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name.to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            groups
+                .entry(current.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    let Some(main) = groups.get("Icon Theme") else {
+        return IconTheme::default();
+    };
+    let list = |key: &str| -> Vec<String> {
+        main.get(key)
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut names = list("Directories");
+    names.extend(list("ScaledDirectories"));
+    let dirs = names
+        .into_iter()
+        .filter_map(|path| {
+            let group = groups.get(&path)?;
+            let number = |key: &str| group.get(key).and_then(|v| v.parse::<u32>().ok());
+            let size = number("Size")?;
+            let kind = match group.get("Type").map(String::as_str) {
+                Some("Fixed") => DirType::Fixed,
+                Some("Scalable") => DirType::Scalable,
+                _ => DirType::Threshold,
+            };
+            Some(ThemeDir {
+                size,
+                scale: number("Scale").unwrap_or(1),
+                kind,
+                min: number("MinSize").unwrap_or(size),
+                max: number("MaxSize").unwrap_or(size),
+                threshold: number("Threshold").unwrap_or(2),
+                path,
+            })
+        })
+        .collect();
+
+    IconTheme {
+        roots: Vec::new(),
+        dirs,
+        inherits: list("Inherits"),
+    }
+}
+
+/// Shared icon lookup with caches
+pub struct IconResolver {
+    theme: String,
+    size: u32,
+    scale: u32,
+    base_dirs: Vec<PathBuf>,
+    themes: Mutex<HashMap<String, Option<Arc<IconTheme>>>>,
+    cache: Mutex<HashMap<(String, u32), Option<PathBuf>>>,
+    apps: RwLock<Vec<AppIcon>>,
+    /// Counts app list changes
+    generation: Shared<u64>,
+}
+
+impl IconResolver {
+    pub fn new(config: &Config) -> Self {
+        let settings: IconSettings = config.section("icons");
+        let theme = if settings.theme.is_empty() {
+            gtk_icon_theme().unwrap_or_else(|| "hicolor".to_string())
+        } else {
+            settings.theme
+        };
+        Self::with_dirs(&theme, settings.size, settings.scale, base_dirs())
+    }
+
+    fn with_dirs(theme: &str, size: u32, scale: u32, base_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            theme: theme.to_string(),
+            size,
+            scale: scale.max(1),
+            base_dirs,
+            themes: Mutex::new(HashMap::new()),
+            cache: Mutex::new(HashMap::new()),
+            apps: RwLock::new(Vec::new()),
+            generation: Shared::default(),
+        }
+    }
+
+    /// Changes whenever lookups may resolve differently
+    pub fn version(&self) -> Version {
+        self.generation.version()
+    }
+
+    /// Replace the app list used for window classes and drop the caches
+    pub fn set_apps(&self, apps: Vec<AppIcon>) {
+        let mut current = self.apps.write().unwrap();
+        if *current != apps {
+            *current = apps;
+            self.themes.lock().unwrap().clear();
+            self.cache.lock().unwrap().clear();
+            self.generation.update(|g| *g += 1);
+        }
+    }
+
+    /// File for an icon name or path at the default size
+    pub fn lookup(&self, icon: &str) -> Option<PathBuf> {
+        self.lookup_sized(icon, self.size)
+    }
+
+    pub fn lookup_sized(&self, icon: &str, size: u32) -> Option<PathBuf> {
+        if icon.is_empty() {
+            return None;
+        }
+        let key = (icon.to_string(), size);
+        if let Some(path) = self.cache.lock().unwrap().get(&key) {
+            return path.clone();
+        }
+        let path = self.find(icon, size);
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(key, path.clone());
+        path
+    }
+
+    /// Icon of the app a window class belongs to
+    pub fn for_class(&self, class: &str) -> Option<PathBuf> {
+        if class.is_empty() {
+            return None;
+        }
+        let icon = {
+            let apps = self.apps.read().unwrap();
+            let class = class.to_lowercase();
+            apps.iter()
+                .find(|a| a.wm_class.to_lowercase() == class)
+                .or_else(|| apps.iter().find(|a| a.id.to_lowercase() == class))
+                // org.gnome.Nautilus for a class of nautilus
+                .or_else(|| {
+                    apps.iter().find(|a| {
+                        a.id.rsplit('.')
+                            .next()
+                            .is_some_and(|last| last.to_lowercase() == class)
+                    })
+                })
+                .map(|a| a.icon.clone())
+        };
+        match icon {
+            Some(icon) => self.lookup(&icon),
+            // Plenty of apps use their class as icon name
+            None => self
+                .lookup(class)
+                .or_else(|| self.lookup(&class.to_lowercase())),
+        }
+    }
+
+    fn theme(&self, name: &str) -> Option<Arc<IconTheme>> {
+        let mut themes = self.themes.lock().unwrap();
+        themes
+            .entry(name.to_string())
+            .or_insert_with(|| IconTheme::load(name, &self.base_dirs).map(Arc::new))
+            .clone()
+    }
+
+    fn find(&self, icon: &str, size: u32) -> Option<PathBuf> {
+        let path = Path::new(icon);
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_path_buf());
+        }
+
+        // The theme, its parents depth first, hicolor last
+        let mut order = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![self.theme.clone()];
+        while let Some(name) = stack.pop() {
+            if name == "hicolor" || !seen.insert(name.clone()) {
+                continue;
+            }
+            if let Some(theme) = self.theme(&name) {
+                stack.extend(theme.inherits.iter().rev().cloned());
+                order.push(theme);
+            }
+        }
+        order.extend(self.theme("hicolor"));
+
+        order
+            .iter()
+            .find_map(|theme| theme.lookup(icon, size, self.scale))
+            .or_else(|| {
+                // Unthemed icons directly in a base dir, e.g. /usr/share/pixmaps
+                self.base_dirs.iter().find_map(|dir| {
+                    EXTENSIONS.iter().find_map(|ext| {
+                        let path = dir.join(format!("{}.{}", icon, ext));
+                        path.is_file().then_some(path)
+                    })
+                })
+            })
+    }
+}
+
+/// `~/.icons`, then `icons` in the XDG data dirs, then the pixmaps dirs
+fn base_dirs() -> Vec<PathBuf> {
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    let data_dirs: Vec<PathBuf> = dirs::data_dir()
+        .into_iter()
+        .chain(
+            data_dirs
+                .split(':')
+                .filter(|d| !d.is_empty())
+                .map(PathBuf::from),
+        )
+        .collect();
+
+    dirs::home_dir()
+        .map(|h| h.join(".icons"))
+        .into_iter()
+        .chain(data_dirs.iter().map(|d| d.join("icons")))
+        .chain(data_dirs.iter().map(|d| d.join("pixmaps")))
+        .collect()
+}
+
+/// `gtk-icon-theme-name` from GTK's settings.ini
+fn gtk_icon_theme() -> Option<String> {
+    let config = dirs::config_dir()?;
+    ["gtk-4.0", "gtk-3.0"].iter().find_map(|gtk| {
+        let settings = fs::read_to_string(config.join(gtk).join("settings.ini")).ok()?;
+        settings.lines().find_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            (key.trim() == "gtk-icon-theme-name")
+                .then(|| value.trim().trim_matches('"').to_string())
+        })
+    })
+}
+
+/// `icon <name>` lookups for widgets that only have an icon name
+pub struct IconsModule {
+    icons: Arc<IconResolver>,
+}
+
+impl IconsModule {
+    pub fn new(icons: Arc<IconResolver>) -> Self {
+        Self { icons }
+    }
+}
+
+#[async_trait]
+impl Module for IconsModule {
+    fn name(&self) -> &'static str {
+        "icons"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["icon"]
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({})
+    }
+
+    /// `icon <name> [size]`, `icon class <window class>`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        match args {
+            ["class", class] => {
+                json!({"type": "icon", "class": class, "path": self.icons.for_class(class)})
+            }
+            [name] => json!({"type": "icon", "name": name, "path": self.icons.lookup(name)}),
+            [name, size] => match size.parse() {
+                Ok(size) => {
+                    json!({"type": "icon", "name": name, "path": self.icons.lookup_sized(name, size)})
+                }
+                Err(_) => module::error(format!("invalid size: {}", size)),
+            },
+            _ => module::error("usage: icon <name> [size] | icon class <class>"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn test_theme_dir_matching() {
+        let theme = parse_index(
+            "[Icon Theme]\nName=T\nInherits=Parent, hicolor\nDirectories=16x16/apps,scalable/apps\n\
+             ScaledDirectories=16x16@2/apps\n\n\
+             [16x16/apps]\nSize=16\nType=Fixed\n\n\
+             [16x16@2/apps]\nSize=16\nScale=2\nType=Fixed\n\n\
+             [scalable/apps]\nSize=64\nMinSize=8\nMaxSize=512\nType=Scalable\n",
+        );
+        assert_eq!(theme.inherits, vec!["Parent", "hicolor"]);
+        assert_eq!(theme.dirs.len(), 3);
+        assert!(theme.dirs[0].matches(16, 1));
+        assert!(!theme.dirs[0].matches(16, 2));
+        assert!(theme.dirs[2].matches(16, 2));
+        assert!(theme.dirs[1].matches(48, 1));
+        assert_eq!(theme.dirs[0].distance(48, 1), 32);
+        assert_eq!(theme.dirs[1].distance(1024, 1), 512);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let root = std::env::temp_dir().join(format!("terra-icons-{}", std::process::id()));
+        let icons = root.join("icons");
+        let pixmaps = root.join("pixmaps");
+        let index = |inherits: &str| {
+            format!(
+                "[Icon Theme]\nInherits={}\nDirectories=16x16/apps,48x48/apps,scalable/apps\n\n\
+                 [16x16/apps]\nSize=16\nType=Fixed\n\n[48x48/apps]\nSize=48\nType=Fixed\n\n\
+                 [scalable/apps]\nSize=64\nMinSize=8\nMaxSize=512\nType=Scalable\n",
+                inherits
+            )
+        };
+        fs::create_dir_all(icons.join("Theme")).unwrap();
+        fs::create_dir_all(icons.join("Parent")).unwrap();
+        fs::create_dir_all(icons.join("hicolor")).unwrap();
+        fs::write(icons.join("Theme/index.theme"), index("Parent")).unwrap();
+        fs::write(icons.join("Parent/index.theme"), index("")).unwrap();
+        fs::write(icons.join("hicolor/index.theme"), index("")).unwrap();
+
+        touch(&icons.join("Theme/16x16/apps/firefox.png"));
+        touch(&icons.join("Parent/48x48/apps/firefox.png"));
+        touch(&icons.join("Parent/scalable/apps/files.svg"));
+        touch(&icons.join("hicolor/48x48/apps/steam.png"));
+        touch(&pixmaps.join("xterm.xpm"));
+
+        let resolver =
+            IconResolver::with_dirs("Theme", 48, 1, vec![icons.clone(), pixmaps.clone()]);
+        // The theme itself wins even at the wrong size
+        assert_eq!(
+            resolver.lookup("firefox"),
+            Some(icons.join("Theme/16x16/apps/firefox.png"))
+        );
+        assert_eq!(
+            resolver.lookup("files"),
+            Some(icons.join("Parent/scalable/apps/files.svg"))
+        );
+        assert_eq!(
+            resolver.lookup("steam"),
+            Some(icons.join("hicolor/48x48/apps/steam.png"))
+        );
+        assert_eq!(resolver.lookup("xterm"), Some(pixmaps.join("xterm.xpm")));
+        assert_eq!(resolver.lookup("missing"), None);
+
+        let absolute = pixmaps.join("xterm.xpm");
+        assert_eq!(
+            resolver.lookup(absolute.to_str().unwrap()),
+            Some(absolute.clone())
+        );
+
+        // Cached until the app list changes
+        touch(&icons.join("Theme/48x48/apps/steam.png"));
+        assert_eq!(
+            resolver.lookup("steam"),
+            Some(icons.join("hicolor/48x48/apps/steam.png"))
+        );
+        let before = resolver.version();
+        resolver.set_apps(vec![AppIcon {
+            id: "org.gnome.Nautilus".to_string(),
+            wm_class: String::new(),
+            icon: "files".to_string(),
+        }]);
+        assert!(resolver.version().seq > before.seq);
+        assert_eq!(
+            resolver.lookup("steam"),
+            Some(icons.join("Theme/48x48/apps/steam.png"))
+        );
+
+        assert_eq!(
+            resolver.for_class("org.gnome.Nautilus"),
+            resolver.lookup("files")
+        );
+        assert_eq!(resolver.for_class("nautilus"), resolver.lookup("files"));
+        assert_eq!(resolver.for_class("Steam"), resolver.lookup("steam"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,198 +1,154 @@
 //! IPC handler for Quickshell communication
 
 use std::sync::Arc;
+use serde_json::{json, Value};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
-use tokio::sync::RwLock;
-use tracing::{debug, error};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, warn};
 
-use crate::{audio, brightness, hyprland, media, AppState};
+use crate::module::{self, ok, ModuleRegistry};
+
+/// Event names a client subscribed to, empty for all
+type Subscription = Option<Vec<String>>;
 
 /// Handle a connected client (Quickshell)
-pub async fn handle_client(stream: UnixStream, state: Arc<RwLock<AppState>>) {
+pub async fn handle_client(stream: UnixStream, registry: Arc<ModuleRegistry>) {
     debug!("New client connected");
     
     let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
-    let mut line = String::new();
+    let mut lines = BufReader::new(reader).lines();
+    let mut events = registry.events().subscribe();
+    let mut subscription: Subscription = None;
     
     loop {
-        line.clear();
-        
-        match reader.read_line(&mut line).await {
-            Ok(0) => {
-                debug!("Client disconnected");
-                break;
-            }
-            Ok(_) => {
-                let response = handle_message(line.trim(), &state).await;
-                if let Err(e) = writer.write_all(response.as_bytes()).await {
-                    error!("Failed to write response: {}", e);
+        let response = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    match subscribe_command(line.trim(), &mut subscription) {
+                        Some(reply) => {
+                            // Drop whatever was emitted while not subscribed
+                            events = events.resubscribe();
+                            reply.to_string()
+                        }
+                        None => handle_message(line.trim(), &registry).await,
+                    }
+                }
+                Ok(None) => {
+                    debug!("Client disconnected");
                     break;
                 }
-                if let Err(e) = writer.write_all(b"\n").await {
-                    error!("Failed to write newline: {}", e);
+                Err(e) => {
+                    error!("Failed to read from client: {}", e);
                     break;
                 }
-            }
-            Err(e) => {
-                error!("Failed to read from client: {}", e);
-                break;
-            }
+            },
+            event = events.recv(), if subscription.is_some() => match event {
+                Ok(event) if wants(&subscription, event.name) => event.payload.to_string(),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Client missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        
+        if let Err(e) = writer.write_all(response.as_bytes()).await {
+            error!("Failed to write response: {}", e);
+            break;
+        }
+        if let Err(e) = writer.write_all(b"\n").await {
+            error!("Failed to write newline: {}", e);
+            break;
         }
     }
 }
 
+/// `subscribe [event...]` and `unsubscribe`, which only affect this connection
+fn subscribe_command(message: &str, subscription: &mut Subscription) -> Option<Value> {
+    let mut parts = message.split_whitespace();
+    match parts.next()? {
+        "subscribe" => {
+            let names: Vec<String> = parts.map(str::to_string).collect();
+            let reply = json!({"type": "subscribed", "events": names});
+            *subscription = Some(names);
+            Some(reply)
+        }
+        "unsubscribe" => {
+            *subscription = None;
+            Some(ok())
+        }
+        _ => None,
+    }
+}
+
+/// Whether a subscription includes an event
+fn wants(subscription: &Subscription, event: &str) -> bool {
+    subscription
+        .as_ref()
+        .is_some_and(|names| names.is_empty() || names.iter().any(|n| n == event))
+}
+
 /// Handle incoming message and return response
-async fn handle_message(message: &str, state: &Arc<RwLock<AppState>>) -> String {
+async fn handle_message(message: &str, registry: &ModuleRegistry) -> String {
     let parts: Vec<&str> = message.split_whitespace().collect();
     
     if parts.is_empty() {
-        return r#"{"error": "empty command"}"#.to_string();
+        return module::error("empty command").to_string();
     }
     
-    match parts[0] {
+    let reply = match parts[0] {
         // === STATE QUERIES ===
         
-        "state" | "all" => {
-            // Return complete state
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "state",
-                "workspace": s.active_workspace,
-                "window": {
-                    "title": s.active_window_title,
-                    "class": s.active_window_class
-                },
-                "battery": {
-                    "level": s.battery_level,
-                    "charging": s.battery_charging
-                },
-                "audio": {
-                    "volume": s.volume,
-                    "muted": s.volume_muted
-                },
-                "network": {
-                    "connected": s.wifi_connected,
-                    "ssid": s.wifi_ssid
-                },
-                "media": {
-                    "title": s.media_title,
-                    "artist": s.media_artist,
-                    "playing": s.media_playing
-                }
-            }).to_string()
-        }
-        
-        "battery" => {
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "battery",
-                "level": s.battery_level,
-                "charging": s.battery_charging
-            }).to_string()
-        }
-        
-        "audio" => {
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "audio",
-                "volume": s.volume,
-                "muted": s.volume_muted
-            }).to_string()
-        }
-        
-        "workspace" | "workspaces" => {
-            let workspaces = hyprland::get_workspaces().await;
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "workspaces",
-                "active": s.active_workspace,
-                "list": workspaces
-            }).to_string()
-        }
-        
-        "window" => {
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "window",
-                "title": s.active_window_title,
-                "class": s.active_window_class
-            }).to_string()
-        }
-        
-        "media" => {
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "media",
-                "title": s.media_title,
-                "artist": s.media_artist,
-                "playing": s.media_playing
-            }).to_string()
-        }
-        
-        "network" => {
-            let s = state.read().await;
-            serde_json::json!({
-                "type": "network",
-                "connected": s.wifi_connected,
-                "ssid": s.wifi_ssid
-            }).to_string()
-        }
-        
-        // === CONTROL COMMANDS ===
-        
-        "volume" => {
-            if parts.len() >= 2 {
-                if let Ok(level) = parts[1].parse::<u8>() {
-                    audio::set_volume(level);
-                    let mut s = state.write().await;
-                    s.volume = level;
-                }
-            }
-            r#"{"ok": true}"#.to_string()
-        }
+        "state" | "all" => match (parts.get(1).copied(), parts.get(2)) {
+            (None, _) => registry.state(None).await,
+            (Some("since"), Some(seq)) => match seq.parse() {
+                Ok(seq) => registry.state(Some(seq)).await,
+                Err(_) => module::error(format!("invalid sequence: {}", seq)),
+            },
+            _ => module::error("usage: state [since <seq>]"),
+        },
         
-        "mute" => {
-            audio::toggle_mute();
-            let mut s = state.write().await;
-            s.volume_muted = !s.volume_muted;
-            r#"{"ok": true}"#.to_string()
-        }
+        "modules" => registry.list().await,
         
-        "brightness" => {
-            if parts.len() >= 2 {
-                if let Ok(level) = parts[1].parse::<u8>() {
-                    brightness::set_brightness(level);
-                }
-            }
-            r#"{"ok": true}"#.to_string()
-        }
+        // === MODULE CONTROL ===
         
-        "media-toggle" | "play-pause" => {
-            media::play_pause();
-            r#"{"ok": true}"#.to_string()
-        }
+        "module" => match (parts.get(1).copied(), parts.get(2)) {
+            (Some("start"), Some(name)) if registry.start(name).await => ok(),
+            (Some("stop"), Some(name)) if registry.stop(name).await => ok(),
+            _ => module::error("usage: module start|stop <name>"),
+        },
         
-        "media-next" | "next" => {
-            media::next();
-            r#"{"ok": true}"#.to_string()
-        }
+        // === MODULE COMMANDS ===
         
-        "media-prev" | "prev" => {
-            media::previous();
-            r#"{"ok": true}"#.to_string()
-        }
-        
-        "dispatch" => {
-            if parts.len() >= 3 {
-                hyprland::dispatch(parts[1], parts[2]).await;
-            }
-            r#"{"ok": true}"#.to_string()
-        }
-        
-        _ => {
-            r#"{"error": "unknown command"}"#.to_string()
-        }
+        command => match registry.handle(command, &parts[1..]).await {
+            Some(reply) => reply,
+            None => module::error("unknown command"),
+        },
+    };
+    
+    reply.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe() {
+        let mut subscription = None;
+        assert!(subscribe_command("state", &mut subscription).is_none());
+        assert!(!wants(&subscription, "osd"));
+
+        subscribe_command("subscribe osd", &mut subscription).unwrap();
+        assert!(wants(&subscription, "osd"));
+        assert!(!wants(&subscription, "theme-changed"));
+
+        subscribe_command("subscribe", &mut subscription).unwrap();
+        assert!(wants(&subscription, "theme-changed"));
+
+        subscribe_command("unsubscribe", &mut subscription).unwrap();
+        assert!(!wants(&subscription, "osd"));
     }
 }
@@ -0,0 +1,237 @@
+//! Keyboard backlight and lock key LEDs
+//!
+//! Reads the LED class devices in `/sys/class/leds`: `*kbd_backlight*` for
+//! the keyboard backlight and `*::capslock` / `*::numlock` for the lock
+//! keys (on if any keyboard's LED is lit). Setting the backlight writes
+//! sysfs directly and falls back to brightnessctl, which goes through
+//! logind when the file isn't writable.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::time::{interval, Duration};
+use tracing::debug;
+
+use crate::module::{self, error, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// LED domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LedState {
+    pub kbd_backlight: u32,
+    pub kbd_backlight_max: u32,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// LED readings at one point in time
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Leds {
+    /// Keyboard backlight device name and max level
+    backlight: Option<(String, u32)>,
+    level: u32,
+    caps_lock: bool,
+    num_lock: bool,
+}
+
+/// Keyboard backlight control and caps/num lock state
+pub struct LedsModule {
+    state: Arc<AppState>,
+    root: PathBuf,
+}
+
+impl LedsModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self {
+            state,
+            root: module::sysfs("class/leds"),
+        }
+    }
+
+    fn refresh(&self) {
+        let leds = read_leds(&self.root);
+        let changed = self.state.leds.set(LedState {
+            kbd_backlight: leds.level,
+            kbd_backlight_max: leds.backlight.map_or(0, |(_, max)| max),
+            caps_lock: leds.caps_lock,
+            num_lock: leds.num_lock,
+        });
+        if changed {
+            debug!("LEDs: {:?}", self.state.leds.get());
+        }
+    }
+
+    fn reply(&self) -> Value {
+        let s = self.state.leds.get();
+        json!({
+            "type": "kbd-backlight",
+            "level": s.kbd_backlight,
+            "max": s.kbd_backlight_max
+        })
+    }
+}
+
+#[async_trait]
+impl Module for LedsModule {
+    fn name(&self) -> &'static str {
+        "leds"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["kbd-backlight", "locks"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.leds.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        let s = self.state.leds.get();
+        json!({
+            "keyboard_backlight": {
+                "level": s.kbd_backlight,
+                "max": s.kbd_backlight_max
+            },
+            "locks": {
+                "caps": s.caps_lock,
+                "num": s.num_lock
+            }
+        })
+    }
+
+    /// `kbd-backlight` queries, `kbd-backlight 2` sets, `+1` / `-1` steps,
+    /// `locks` reports caps/num lock
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        if command == "locks" {
+            let s = self.state.leds.get();
+            return json!({"type": "locks", "caps": s.caps_lock, "num": s.num_lock});
+        }
+
+        let Some(arg) = args.first() else {
+            return self.reply();
+        };
+        let Some((device, max)) = read_leds(&self.root).backlight else {
+            return error("no keyboard backlight");
+        };
+        let current = read_u32(&self.root.join(&device).join("brightness")).unwrap_or(0);
+
+        let level = if let Some(amount) = arg.strip_prefix('+') {
+            amount.parse::<u32>().ok().map(|a| current.saturating_add(a))
+        } else if let Some(amount) = arg.strip_prefix('-') {
+            amount.parse::<u32>().ok().map(|a| current.saturating_sub(a))
+        } else {
+            arg.parse::<u32>().ok()
+        };
+        let Some(level) = level else {
+            return error(format!("invalid level: {}", arg));
+        };
+
+        if !set_level(&self.root, &device, level.min(max)) {
+            return error("keyboard backlight change failed");
+        }
+        self.refresh();
+        self.reply()
+    }
+
+    async fn run(self: Arc<Self>) {
+        // Lock keys change often and should reach the OSD promptly
+        let mut interval = interval(Duration::from_millis(500));
+
+        loop {
+            interval.tick().await;
+            self.refresh();
+        }
+    }
+}
+
+fn read_u32(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Scan the LED class directory
+fn read_leds(root: &Path) -> Leds {
+    let mut leds = Leds::default();
+    let Ok(entries) = fs::read_dir(root) else {
+        return leds;
+    };
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+
+    for name in names {
+        let dir = root.join(&name);
+        let lit = read_u32(&dir.join("brightness")).unwrap_or(0);
+        if name.contains("kbd_backlight") {
+            // First device wins, laptops have one
+            if leds.backlight.is_none() {
+                let max = read_u32(&dir.join("max_brightness")).unwrap_or(0);
+                leds.backlight = Some((name, max));
+                leds.level = lit;
+            }
+        } else if name.ends_with("::capslock") {
+            leds.caps_lock |= lit > 0;
+        } else if name.ends_with("::numlock") {
+            leds.num_lock |= lit > 0;
+        }
+    }
+    leds
+}
+
+/// Write the backlight level, via brightnessctl if sysfs isn't writable
+fn set_level(root: &Path, device: &str, level: u32) -> bool {
+    if fs::write(root.join(device).join("brightness"), level.to_string()).is_ok() {
+        return true;
+    }
+    Command::new("brightnessctl")
+        .args(["--device", device, "set", &level.to_string()])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn led(root: &Path, name: &str, brightness: u32, max: u32) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("brightness"), brightness.to_string()).unwrap();
+        fs::write(dir.join("max_brightness"), max.to_string()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_leds() {
+        let root = std::env::temp_dir().join(format!("terra-leds-{}", std::process::id()));
+        led(&root, "tpacpi::kbd_backlight", 1, 2);
+        led(&root, "input3::capslock", 0, 1);
+        led(&root, "input3::numlock", 1, 1);
+        led(&root, "input7::capslock", 1, 1);
+
+        let leds = read_leds(&root);
+        assert_eq!(leds.backlight, Some(("tpacpi::kbd_backlight".to_string(), 2)));
+        assert_eq!(leds.level, 1);
+        assert!(leds.caps_lock);
+        assert!(leds.num_lock);
+
+        let module = LedsModule {
+            state: Arc::new(AppState::default()),
+            root: root.clone(),
+        };
+        let reply = module.handle("kbd-backlight", &["+5"]).await;
+        assert_eq!(reply["level"], 2);
+        let reply = module.handle("kbd-backlight", &["0"]).await;
+        assert_eq!(reply["level"], 0);
+        assert_eq!(module.handle("locks", &[]).await["caps"], true);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
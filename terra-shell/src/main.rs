@@ -3,39 +3,113 @@
 //! Powers Quickshell widgets with real-time system data.
 //! Communicates via Unix socket IPC.
 
+mod apps;
 mod audio;
 mod battery;
+mod bluetooth;
 mod brightness;
+mod capture;
+mod clipboard;
+mod clock;
+mod config;
+mod dbus;
+mod drives;
+mod events;
 mod hyprland;
+mod icons;
 mod ipc;
+mod leds;
 mod media;
+mod module;
 mod network;
-
+mod nightlight;
+mod notifications;
+mod osd;
+mod power;
+mod privacy;
+mod script;
+mod session;
+mod state;
+mod system;
+mod theme;
+mod tray;
+mod units;
+mod updates;
+
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::UnixListener;
-use tokio::sync::{broadcast, RwLock};
+use tokio::signal::unix::{signal, SignalKind};
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
+use config::Config;
+use module::ModuleRegistry;
+use state::Shared;
+
 const SOCKET_PATH: &str = "/tmp/terra-shell.sock";
 
-/// Shared state accessible by all handlers
+/// Shared state, one versioned watch channel per domain
 #[derive(Debug, Default)]
 pub struct AppState {
-    pub active_workspace: i32,
-    pub active_window_title: String,
-    pub active_window_class: String,
-    pub battery_level: u8,
-    pub battery_charging: bool,
-    pub volume: u8,
-    pub volume_muted: bool,
-    pub brightness: u8,
-    pub wifi_ssid: Option<String>,
-    pub wifi_connected: bool,
-    pub media_title: String,
-    pub media_artist: String,
-    pub media_playing: bool,
+    pub hyprland: Shared<hyprland::HyprlandState>,
+    pub battery: Shared<battery::BatteryState>,
+    pub audio: Shared<audio::AudioState>,
+    pub brightness: Shared<u8>,
+    pub leds: Shared<leds::LedState>,
+    pub network: Shared<network::NetworkState>,
+    pub media: Shared<media::MediaState>,
+    pub power: Shared<power::PowerState>,
+    pub recording: Shared<Option<capture::Recording>>,
+    pub privacy: Shared<privacy::PrivacyState>,
+    pub nightlight: Shared<nightlight::NightLightState>,
+    pub theme: Shared<theme::ThemeState>,
+    pub drives: Shared<Vec<drives::Drive>>,
+    pub units: Shared<Vec<units::UnitStatus>>,
+    pub updates: Shared<updates::UpdatesState>,
+    pub system: Shared<system::SystemStats>,
+    /// Latest output per custom script
+    pub custom: Shared<HashMap<String, script::ScriptOutput>>,
+    pub bluetooth: Shared<bluetooth::BluetoothState>,
+    pub tray: Shared<Vec<tray::TrayItem>>,
+    pub notifications: Shared<notifications::NotificationStore>,
+    pub clipboard: Shared<clipboard::ClipboardState>,
+    pub clock: Shared<clock::ClockState>,
+}
+
+/// Build the registry of all built-in modules enabled by the config
+fn build_registry(config: &Config, state: &Arc<AppState>) -> ModuleRegistry {
+    let mut registry = ModuleRegistry::new();
+    let events = registry.events().clone();
+    let icons = Arc::new(icons::IconResolver::new(config));
+    registry.register(Arc::new(hyprland::HyprlandModule::new(state.clone(), icons.clone())), config);
+    registry.register(Arc::new(battery::BatteryModule::new(state.clone())), config);
+    registry.register(Arc::new(audio::AudioModule::new(state.clone())), config);
+    registry.register(Arc::new(brightness::BrightnessModule::new(state.clone())), config);
+    registry.register(Arc::new(nightlight::NightLightModule::new(state.clone(), config)), config);
+    registry.register(Arc::new(leds::LedsModule::new(state.clone())), config);
+    registry.register(Arc::new(network::NetworkModule::new(state.clone())), config);
+    registry.register(Arc::new(media::MediaModule::new(state.clone())), config);
+    registry.register(Arc::new(osd::OsdModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(power::PowerModule::new(state.clone())), config);
+    registry.register(Arc::new(session::SessionModule::new()), config);
+    registry.register(Arc::new(system::SystemModule::new(state.clone(), config)), config);
+    registry.register(Arc::new(notifications::NotificationModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(capture::CaptureModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(privacy::PrivacyModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(updates::UpdatesModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(clipboard::ClipboardModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(clock::ClockModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(apps::AppsModule::new(events.clone(), icons.clone(), config)), config);
+    registry.register(Arc::new(theme::ThemeModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(icons::IconsModule::new(icons)), config);
+    registry.register(Arc::new(tray::TrayModule::new(state.clone())), config);
+    registry.register(Arc::new(bluetooth::BluetoothModule::new(state.clone())), config);
+    registry.register(Arc::new(drives::DrivesModule::new(state.clone(), events.clone())), config);
+    registry.register(Arc::new(units::UnitsModule::new(state.clone(), events.clone(), config)), config);
+    registry.register(Arc::new(script::ScriptModule::new(state.clone(), config)), config);
+    registry
 }
 
 #[tokio::main]
@@ -49,87 +123,45 @@ async fn main() -> anyhow::Result<()> {
     info!("Terra Shell v{} starting...", env!("CARGO_PKG_VERSION"));
 
     // Remove stale socket if exists
-    let socket_path = PathBuf::from(SOCKET_PATH);
+    let socket_path = std::env::var_os("TERRA_SHELL_SOCKET")
+        .map_or_else(|| PathBuf::from(SOCKET_PATH), PathBuf::from);
     if socket_path.exists() {
         std::fs::remove_file(&socket_path)?;
     }
 
-    // Create shared state
-    let state = Arc::new(RwLock::new(AppState::default()));
-    
-    // Create broadcast channel for Hyprland events
-    let (hypr_tx, _) = broadcast::channel::<hyprland::HyprlandEvent>(32);
+    // Create shared state and modules
+    let config = Config::load();
+    let state = Arc::new(AppState::default());
+    let registry = Arc::new(build_registry(&config, &state));
 
     // Create Unix socket listener
     let listener = UnixListener::bind(&socket_path)?;
-    info!("Listening on {}", SOCKET_PATH);
+    info!("Listening on {}", socket_path.display());
 
     // Start system monitors
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        battery::monitor(state_clone).await;
-    });
+    registry.start_all().await;
 
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        audio::monitor(state_clone).await;
-    });
+    // systemd and session managers stop us with SIGTERM
+    let mut terminate = signal(SignalKind::terminate())?;
 
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        network::monitor(state_clone).await;
-    });
-
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        media::monitor(state_clone).await;
-    });
-
-    // Start Hyprland event monitor
-    let hypr_tx_clone = hypr_tx.clone();
-    let state_clone = state.clone();
-    tokio::spawn(async move {
-        // Subscribe to events and update state
-        let mut rx = hypr_tx_clone.subscribe();
-        tokio::spawn(hyprland::monitor(hypr_tx_clone));
-        
-        while let Ok(event) = rx.recv().await {
-            let mut s = state_clone.write().await;
-            match event {
-                hyprland::HyprlandEvent::WorkspaceChanged { id, .. } => {
-                    s.active_workspace = id;
+    // Accept client connections (Quickshell)
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(ipc::handle_client(stream, registry.clone()));
                 }
-                hyprland::HyprlandEvent::ActiveWindowChanged { class, title } => {
-                    s.active_window_class = class;
-                    s.active_window_title = title;
+                Err(e) => {
+                    tracing::error!("Failed to accept connection: {}", e);
                 }
-                _ => {}
-            }
-        }
-    });
-
-    // Initialize state with current values
-    {
-        let mut s = state.write().await;
-        if let Some(ws) = hyprland::get_active_workspace().await {
-            s.active_workspace = ws;
-        }
-        if let Some(win) = hyprland::get_active_window().await {
-            s.active_window_title = win.title;
-            s.active_window_class = win.class;
+            },
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
         }
     }
 
-    // Accept client connections (Quickshell)
-    loop {
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                let state_clone = state.clone();
-                tokio::spawn(ipc::handle_client(stream, state_clone));
-            }
-            Err(e) => {
-                tracing::error!("Failed to accept connection: {}", e);
-            }
-        }
-    }
+    info!("Shutting down");
+    registry.stop_all().await;
+    let _ = std::fs::remove_file(&socket_path);
+    Ok(())
 }
@@ -2,26 +2,102 @@
 
 use std::process::Command;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
 use tokio::time::{interval, Duration};
 use tracing::debug;
 
+use crate::module::{ok, Module};
+use crate::state::Version;
 use crate::AppState;
 
+/// Media domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MediaState {
+    pub title: String,
+    pub artist: String,
+    pub playing: bool,
+}
+
+/// Now playing info and playback controls
+pub struct MediaModule {
+    state: Arc<AppState>,
+}
+
+impl MediaModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl Module for MediaModule {
+    fn name(&self) -> &'static str {
+        "media"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &[
+            "media",
+            "media-toggle",
+            "play-pause",
+            "media-next",
+            "next",
+            "media-prev",
+            "prev",
+        ]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.media.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"media": self.state.media.get()})
+    }
+
+    async fn handle(&self, command: &str, _args: &[&str]) -> Value {
+        match command {
+            "media-toggle" | "play-pause" => {
+                play_pause();
+                ok()
+            }
+            "media-next" | "next" => {
+                next();
+                ok()
+            }
+            "media-prev" | "prev" => {
+                previous();
+                ok()
+            }
+            _ => {
+                let s = self.state.media.get();
+                json!({
+                    "type": "media",
+                    "title": s.title,
+                    "artist": s.artist,
+                    "playing": s.playing
+                })
+            }
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        monitor(self.state.clone()).await;
+    }
+}
+
 /// Monitor media players
-pub async fn monitor(state: Arc<RwLock<AppState>>) {
+async fn monitor(state: Arc<AppState>) {
     let mut interval = interval(Duration::from_secs(1));
     
     loop {
         interval.tick().await;
         
         let (title, artist, playing) = get_media_info();
-        let mut s = state.write().await;
-        if s.media_title != title || s.media_artist != artist || s.media_playing != playing {
-            debug!("Media: {} - {} (playing: {})", artist, title, playing);
-            s.media_title = title;
-            s.media_artist = artist;
-            s.media_playing = playing;
+        if state.media.set(MediaState { title, artist, playing }) {
+            debug!("Media: {:?}", state.media.get());
         }
     }
 }
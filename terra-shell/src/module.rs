@@ -0,0 +1,366 @@
+//! Pluggable monitor modules
+//!
+//! Every data source (battery, audio, Hyprland, ...) implements [`Module`]
+//! and is registered in [`ModuleRegistry`], which owns their background
+//! tasks and routes IPC commands to them.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::state::{self, Version};
+
+/// A data source or control surface exposed over IPC
+#[async_trait]
+pub trait Module: Send + Sync {
+    /// Unique module name, used in the config file
+    fn name(&self) -> &'static str;
+
+    /// IPC commands handled by this module
+    fn commands(&self) -> &'static [&'static str];
+
+    /// Whether the module runs when the config doesn't mention it
+    fn enabled_by_default(&self) -> bool {
+        true
+    }
+
+    /// Version of the state behind [`Module::snapshot`], `None` when the
+    /// module doesn't track one and is always included in `state since`
+    fn version(&self) -> Option<Version> {
+        None
+    }
+
+    /// Current state as a JSON object, merged into the `state` reply
+    async fn snapshot(&self) -> Value;
+
+    /// Handle one of [`Module::commands`], `args` excludes the command itself
+    async fn handle(&self, command: &str, args: &[&str]) -> Value;
+
+    /// Background monitor loop, runs until the module is stopped
+    async fn run(self: Arc<Self>) {}
+
+    /// Release resources held by the module after its task is stopped
+    async fn stop(&self) {}
+}
+
+/// Successful reply for control commands
+pub fn ok() -> Value {
+    json!({"ok": true})
+}
+
+/// Error reply with a message
+pub fn error(message: impl AsRef<str>) -> Value {
+    json!({"error": message.as_ref()})
+}
+
+/// Current Unix timestamp in seconds
+pub fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Current Unix timestamp in milliseconds
+pub fn current_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Path under `/sys`, or under `$TERRA_SHELL_SYSFS` when set (test trees)
+pub fn sysfs(path: &str) -> PathBuf {
+    env::var_os("TERRA_SHELL_SYSFS")
+        .map_or_else(|| PathBuf::from("/sys"), PathBuf::from)
+        .join(path)
+}
+
+/// `terra-shell` in the XDG data dir, created if missing
+pub fn data_dir() -> Option<PathBuf> {
+    let dir = dirs::data_dir()?.join("terra-shell");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+/// JSON file in [`data_dir`] for module state that persists across restarts
+///
+/// Saves are written on the blocking pool, so they can be made while
+/// holding an async lock. Writes happen in order, and one still queued is
+/// replaced by the newer contents.
+#[derive(Clone)]
+pub struct JsonFile {
+    path: Option<PathBuf>,
+    /// Contents waiting to be written
+    pending: Arc<StdMutex<Option<Vec<u8>>>>,
+    /// Held while writing, so writes don't overtake each other
+    writing: Arc<StdMutex<()>>,
+}
+
+impl JsonFile {
+    /// `name` in the data dir, e.g. `clock.json`
+    pub fn new(name: &str) -> Self {
+        Self::at(data_dir().map(|dir| dir.join(name)))
+    }
+
+    fn at(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            pending: Arc::default(),
+            writing: Arc::default(),
+        }
+    }
+
+    /// Read the file, the default value if it is missing or invalid
+    pub fn load<T: DeserializeOwned + Default>(&self) -> T {
+        let Some(file) = self.path.as_ref().and_then(|p| File::open(p).ok()) else {
+            return T::default();
+        };
+        serde_json::from_reader(BufReader::new(file)).unwrap_or_default()
+    }
+
+    /// Serialize `value` now and write it in the background
+    pub fn save<T: Serialize>(&self, value: &T) {
+        let Some(path) = self.path.clone() else {
+            return;
+        };
+        match serde_json::to_vec(value) {
+            Ok(data) => *self.pending.lock().unwrap() = Some(data),
+            Err(e) => {
+                warn!("Failed to serialize {}: {}", path.display(), e);
+                return;
+            }
+        }
+        let (pending, writing) = (self.pending.clone(), self.writing.clone());
+        tokio::task::spawn_blocking(move || {
+            let _writing = writing.lock().unwrap();
+            let Some(data) = pending.lock().unwrap().take() else {
+                return;
+            };
+            if let Err(e) = fs::write(&path, data) {
+                warn!("Failed to save {}: {}", path.display(), e);
+            }
+        });
+    }
+}
+
+/// Owns all enabled modules and their background tasks
+#[derive(Default)]
+pub struct ModuleRegistry {
+    modules: Vec<Arc<dyn Module>>,
+    commands: HashMap<&'static str, usize>,
+    tasks: Mutex<HashMap<&'static str, JoinHandle<()>>>,
+    events: EventBus,
+}
+
+impl ModuleRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Event bus shared by modules and IPC subscribers
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Register a module if the config enables it
+    pub fn register(&mut self, module: Arc<dyn Module>, config: &Config) {
+        let name = module.name();
+        if !config.is_enabled(name, module.enabled_by_default()) {
+            info!("Module {} disabled", name);
+            return;
+        }
+
+        let index = self.modules.len();
+        for command in module.commands() {
+            if let Some(&other) = self.commands.get(command) {
+                warn!(
+                    "Command {} of module {} already handled by {}",
+                    command,
+                    name,
+                    self.modules[other].name()
+                );
+                continue;
+            }
+            self.commands.insert(command, index);
+        }
+        self.modules.push(module);
+    }
+
+    /// Spawn the background task of every registered module
+    pub async fn start_all(&self) {
+        for module in &self.modules {
+            self.start(module.name()).await;
+        }
+    }
+
+    /// Spawn a module's background task unless it is already running
+    pub async fn start(&self, name: &str) -> bool {
+        let Some(module) = self.get(name) else {
+            return false;
+        };
+        let mut tasks = self.tasks.lock().await;
+        if tasks.get(name).is_some_and(|task| !task.is_finished()) {
+            return true;
+        }
+        info!("Starting module {}", name);
+        tasks.insert(module.name(), tokio::spawn(module.run()));
+        true
+    }
+
+    /// Stop a module's background task
+    pub async fn stop(&self, name: &str) -> bool {
+        let Some(module) = self.get(name) else {
+            return false;
+        };
+        if let Some(task) = self.tasks.lock().await.remove(module.name()) {
+            task.abort();
+        }
+        module.stop().await;
+        info!("Stopped module {}", name);
+        true
+    }
+
+    /// Stop every module, e.g. on shutdown
+    pub async fn stop_all(&self) {
+        for module in &self.modules {
+            self.stop(module.name()).await;
+        }
+    }
+
+    /// Look up a registered module by name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Module>> {
+        self.modules.iter().find(|m| m.name() == name).cloned()
+    }
+
+    /// Merge module snapshots into the state reply, with `since` only
+    /// those that changed after that sequence number
+    pub async fn state(&self, since: Option<u64>) -> Value {
+        // Taken first so a change made while building the reply is sent again
+        let seq = state::current_seq();
+        let mut state = serde_json::Map::new();
+        let mut versions = serde_json::Map::new();
+        state.insert("type".to_string(), json!("state"));
+        for module in &self.modules {
+            let version = module.version();
+            if let (Some(since), Some(version)) = (since, version) {
+                if version.seq <= since {
+                    continue;
+                }
+            }
+            if let Some(version) = version {
+                versions.insert(module.name().to_string(), json!(version));
+            }
+            if let Value::Object(fields) = module.snapshot().await {
+                state.extend(fields);
+            }
+        }
+        state.insert("seq".to_string(), json!(seq));
+        state.insert("versions".to_string(), Value::Object(versions));
+        Value::Object(state)
+    }
+
+    /// List modules and whether their task is running
+    pub async fn list(&self) -> Value {
+        let tasks = self.tasks.lock().await;
+        let modules: Vec<Value> = self
+            .modules
+            .iter()
+            .map(|m| {
+                json!({
+                    "name": m.name(),
+                    "running": tasks.get(m.name()).is_some_and(|t| !t.is_finished()),
+                    "commands": m.commands(),
+                })
+            })
+            .collect();
+        json!({"type": "modules", "list": modules})
+    }
+
+    /// Route a command to the module that handles it
+    pub async fn handle(&self, command: &str, args: &[&str]) -> Option<Value> {
+        let module = self.modules.get(*self.commands.get(command)?)?;
+        Some(module.handle(command, args).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    #[async_trait]
+    impl Module for Echo {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn commands(&self) -> &'static [&'static str] {
+            &["echo"]
+        }
+
+        async fn snapshot(&self) -> Value {
+            json!({"echo": {"ready": true}})
+        }
+
+        async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+            json!({"type": "echo", "args": args})
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_routes_commands() {
+        let mut registry = ModuleRegistry::new();
+        registry.register(Arc::new(Echo), &Config::default());
+
+        let reply = registry.handle("echo", &["a", "b"]).await.unwrap();
+        assert_eq!(reply["args"], json!(["a", "b"]));
+        assert!(registry.handle("missing", &[]).await.is_none());
+
+        let state = registry.state(None).await;
+        assert_eq!(state["type"], "state");
+        assert_eq!(state["echo"]["ready"], true);
+    }
+
+    #[tokio::test]
+    async fn test_json_file() {
+        let path = env::temp_dir().join(format!("terra-json-{}.json", std::process::id()));
+        let file = JsonFile::at(Some(path.clone()));
+        assert_eq!(file.load::<Vec<u32>>(), Vec::<u32>::new());
+
+        file.save(&vec![1]);
+        file.save(&vec![1, 2]);
+        for _ in 0..100 {
+            if file.load::<Vec<u32>>() == vec![1, 2] {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(file.load::<Vec<u32>>(), vec![1, 2]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_registry_respects_config() {
+        let config: Config =
+            serde_json::from_str(r#"{"modules": {"echo": {"enabled": false}}}"#).unwrap();
+        let mut registry = ModuleRegistry::new();
+        registry.register(Arc::new(Echo), &config);
+        assert!(registry.get("echo").is_none());
+    }
+}
@@ -2,25 +2,76 @@
 
 use std::process::Command;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value};
 use tokio::time::{interval, Duration};
 use tracing::debug;
 
+use crate::module::Module;
+use crate::state::Version;
 use crate::AppState;
 
+/// Network domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NetworkState {
+    pub connected: bool,
+    pub ssid: Option<String>,
+}
+
+/// WiFi connection state
+pub struct NetworkModule {
+    state: Arc<AppState>,
+}
+
+impl NetworkModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+#[async_trait]
+impl Module for NetworkModule {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["network"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.network.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"network": self.state.network.get()})
+    }
+
+    async fn handle(&self, _command: &str, _args: &[&str]) -> Value {
+        let s = self.state.network.get();
+        json!({
+            "type": "network",
+            "connected": s.connected,
+            "ssid": s.ssid
+        })
+    }
+
+    async fn run(self: Arc<Self>) {
+        monitor(self.state.clone()).await;
+    }
+}
+
 /// Monitor network status
-pub async fn monitor(state: Arc<RwLock<AppState>>) {
+async fn monitor(state: Arc<AppState>) {
     let mut interval = interval(Duration::from_secs(5));
     
     loop {
         interval.tick().await;
         
         let (connected, ssid) = get_wifi_info();
-        let mut s = state.write().await;
-        if s.wifi_connected != connected || s.wifi_ssid != ssid {
-            debug!("Network: connected={}, ssid={:?}", connected, ssid);
-            s.wifi_connected = connected;
-            s.wifi_ssid = ssid;
+        if state.network.set(NetworkState { connected, ssid }) {
+            debug!("Network: {:?}", state.network.get());
         }
     }
 }
@@ -0,0 +1,548 @@
+//! Night light
+//!
+//! Warms the screen colour temperature on a schedule: either fixed local
+//! times or sunset to sunrise, computed locally from the configured
+//! coordinates with the NOAA sunrise equation (no location service or
+//! network needed). Changes ramp over `transition` minutes at schedule
+//! boundaries and at most `speed` kelvin per second otherwise, so toggling
+//! does not flash the screen.
+//!
+//! The temperature is applied through hyprsunset's IPC socket, starting
+//! hyprsunset if it isn't running, or by running `gammastep -O` on
+//! compositors without it. gammastep has to be restarted for every change,
+//! which flashes the screen, so it gets the target in one step instead.
+//! `nightlight set` pins a temperature until `nightlight auto`,
+//! `nightlight disable [duration]` pauses it.
+
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use jiff::civil::{Date, Time};
+use jiff::tz::TimeZone;
+use jiff::{Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+use tokio::sync::Mutex;
+use tokio::time::{interval, sleep, timeout, Duration};
+use tracing::{debug, info, warn};
+
+use crate::clock;
+use crate::config::Config;
+use crate::hyprland;
+use crate::module::{self, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// Applied changes smaller than this are skipped, except to reach the target
+const QUANTUM: u32 = 50;
+
+/// Default `nightlight disable` length
+const DISABLE_MS: u64 = 60 * 60 * 1000;
+
+/// How long to wait for hyprsunset to answer a command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct NightLightSettings {
+    /// `hyprsunset` or `gammastep`
+    backend: String,
+    /// `fixed` (start/end) or `sun` (latitude/longitude)
+    schedule: String,
+    /// Local time the night starts, for `fixed`
+    start: String,
+    /// Local time the night ends, for `fixed`
+    end: String,
+    latitude: Option<f64>,
+    /// Degrees east
+    longitude: Option<f64>,
+    /// Kelvin at night
+    night_temperature: u32,
+    /// Kelvin during the day, shown as the unmodified screen
+    day_temperature: u32,
+    /// Minutes to ramp at the start and end of the night
+    transition: u32,
+    /// Kelvin per second for manual changes, 0 for instant
+    speed: u32,
+}
+
+impl Default for NightLightSettings {
+    fn default() -> Self {
+        Self {
+            backend: "hyprsunset".to_string(),
+            schedule: "fixed".to_string(),
+            start: "20:00".to_string(),
+            end: "07:00".to_string(),
+            latitude: None,
+            longitude: None,
+            night_temperature: 4000,
+            day_temperature: 6500,
+            transition: 30,
+            speed: 250,
+        }
+    }
+}
+
+/// Night light domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NightLightState {
+    pub enabled: bool,
+    /// Kelvin currently applied
+    pub temperature: u32,
+    /// Kelvin being ramped towards
+    pub target: u32,
+    /// The schedule says it is night (including the ramps)
+    pub night: bool,
+    /// Temperature pinned by `nightlight set`
+    pub manual: Option<u32>,
+    /// Unix seconds a `nightlight disable` ends
+    pub disabled_until: Option<u64>,
+    /// Today's night start and end, `HH:MM` local time
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Sun events of one day
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SunTimes {
+    Normal { rise: Timestamp, set: Timestamp },
+    PolarNight,
+    MidnightSun,
+}
+
+/// Colour temperature scheduler
+pub struct NightLightModule {
+    state: Arc<AppState>,
+    settings: NightLightSettings,
+    /// Running gammastep, whose exit resets the gamma ramps
+    gammastep: Mutex<Option<Child>>,
+}
+
+impl NightLightModule {
+    pub fn new(state: Arc<AppState>, config: &Config) -> Self {
+        let settings: NightLightSettings = config.section("nightlight");
+        state.nightlight.set(NightLightState {
+            enabled: true,
+            temperature: settings.day_temperature,
+            target: settings.day_temperature,
+            ..Default::default()
+        });
+        Self {
+            state,
+            settings,
+            gammastep: Mutex::new(None),
+        }
+    }
+
+    /// Whether the backend can change smoothly, see the module docs
+    fn ramps(&self) -> bool {
+        self.settings.backend != "gammastep"
+    }
+
+    /// Night start and end for `now`'s date in minutes after local
+    /// midnight, `Err(night)` when the sun doesn't rise or set
+    fn window(&self, now: &Zoned) -> Result<(f64, f64), bool> {
+        if self.settings.schedule == "sun" {
+            let (Some(lat), Some(lon)) = (self.settings.latitude, self.settings.longitude) else {
+                return Err(false);
+            };
+            return match sun_times(now.date(), lat, lon) {
+                SunTimes::Normal { rise, set } => {
+                    let tz = now.time_zone();
+                    Ok((
+                        minutes(set.to_zoned(tz.clone()).time()),
+                        minutes(rise.to_zoned(tz.clone()).time()),
+                    ))
+                }
+                SunTimes::PolarNight => Err(true),
+                SunTimes::MidnightSun => Err(false),
+            };
+        }
+        match (
+            self.settings.start.parse::<Time>(),
+            self.settings.end.parse::<Time>(),
+        ) {
+            (Ok(start), Ok(end)) => Ok((minutes(start), minutes(end))),
+            _ => Err(false),
+        }
+    }
+
+    /// Temperature the schedule and overrides ask for right now
+    fn target(&self, now: &Zoned) -> u32 {
+        let (day, night) = (
+            self.settings.day_temperature,
+            self.settings.night_temperature,
+        );
+        let window = self.window(now);
+        let transition = if self.ramps() {
+            self.settings.transition as f64
+        } else {
+            0.0
+        };
+        let factor = match window {
+            Ok((start, end)) => night_factor(minutes(now.time()), start, end, transition),
+            Err(true) => 1.0,
+            Err(false) => 0.0,
+        };
+        let scheduled = day as f64 - factor * (day as f64 - night as f64);
+
+        let unix = now.timestamp().as_second().max(0) as u64;
+        let hhmm = |m: f64| format!("{:02}:{:02}", (m as u32 / 60) % 24, m as u32 % 60);
+        let (window_start, window_end) = match window {
+            Ok((start, end)) => (Some(hhmm(start)), Some(hhmm(end))),
+            Err(_) => (None, None),
+        };
+        let mut target = day;
+        self.state.nightlight.update(|s| {
+            if s.disabled_until.is_some_and(|until| until <= unix) {
+                s.disabled_until = None;
+            }
+            s.night = factor > 0.0;
+            s.start = window_start;
+            s.end = window_end;
+            if s.enabled && s.disabled_until.is_none() {
+                target = s.manual.unwrap_or(scheduled.round() as u32);
+            }
+            s.target = target;
+        });
+        target
+    }
+
+    /// Move one tick of `seconds` towards the target and apply it
+    async fn tick(&self, seconds: f64) {
+        let target = self.target(&Zoned::now());
+        let current = self.state.nightlight.read(|s| s.temperature);
+        let next = if self.ramps() {
+            approach(current, target, self.settings.speed as f64 * seconds)
+        } else {
+            target
+        };
+        if next == current || (next.abs_diff(current) < QUANTUM && next != target) {
+            return;
+        }
+
+        // `nightlight set` may go above the day temperature
+        let identity = next == self.settings.day_temperature;
+        let result = self.apply(next, identity).await;
+        if let Err(e) = &result {
+            if self.state.nightlight.read(|s| s.error.as_ref() != Some(e)) {
+                warn!("Night light: {}", e);
+            }
+        }
+        // Recorded even on failure, so a missing backend isn't retried every tick
+        self.state.nightlight.update(|s| {
+            s.temperature = next;
+            s.error = result.err();
+        });
+    }
+
+    async fn apply(&self, temperature: u32, identity: bool) -> Result<(), String> {
+        debug!("Night light at {}K", temperature);
+        match self.settings.backend.as_str() {
+            "gammastep" => self.gammastep(temperature, identity).await,
+            _ => {
+                let command = if identity {
+                    "identity".to_string()
+                } else {
+                    format!("temperature {}", temperature)
+                };
+                hyprsunset(&command).await
+            }
+        }
+    }
+
+    async fn gammastep(&self, temperature: u32, identity: bool) -> Result<(), String> {
+        let mut running = self.gammastep.lock().await;
+        if let Some(mut child) = running.take() {
+            let _ = child.kill().await;
+        }
+        if identity {
+            return Ok(());
+        }
+        let child = Command::new("gammastep")
+            .args(["-P", "-O", &temperature.to_string()])
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("gammastep failed: {}", e))?;
+        *running = Some(child);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Module for NightLightModule {
+    fn name(&self) -> &'static str {
+        "nightlight"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["nightlight"]
+    }
+
+    /// Changes the screen, so only runs when asked for
+    fn enabled_by_default(&self) -> bool {
+        false
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.nightlight.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"nightlight": self.state.nightlight.get()})
+    }
+
+    /// `nightlight [on|off|toggle|auto|set <kelvin>|disable [duration]]`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        let now = module::current_timestamp();
+        match args {
+            [] => false,
+            ["on"] => self.state.nightlight.update(|s| s.enabled = true),
+            ["off"] => self.state.nightlight.update(|s| s.enabled = false),
+            ["toggle"] => self.state.nightlight.update(|s| s.enabled = !s.enabled),
+            ["auto"] => self.state.nightlight.update(|s| {
+                s.manual = None;
+                s.disabled_until = None;
+                s.enabled = true;
+            }),
+            ["set", kelvin] => match kelvin.trim_end_matches(['K', 'k']).parse::<u32>() {
+                Ok(kelvin @ 1000..=20000) => self.state.nightlight.update(|s| {
+                    s.manual = Some(kelvin);
+                    s.enabled = true;
+                }),
+                _ => return module::error(format!("invalid temperature: {}", kelvin)),
+            },
+            ["disable", rest @ ..] => {
+                let ms = match rest.first() {
+                    Some(duration) => match clock::parse_duration(duration) {
+                        Some(ms) => ms,
+                        None => return module::error(format!("invalid duration: {}", duration)),
+                    },
+                    None => DISABLE_MS,
+                };
+                self.state
+                    .nightlight
+                    .update(|s| s.disabled_until = Some(now + ms.div_ceil(1000)))
+            }
+            _ => {
+                return module::error(
+                    "usage: nightlight [on|off|toggle|auto|set <kelvin>|disable [duration]]",
+                )
+            }
+        };
+        self.target(&Zoned::now());
+        let mut reply = json!(self.state.nightlight.get());
+        reply["type"] = json!("nightlight");
+        reply
+    }
+
+    async fn run(self: Arc<Self>) {
+        info!("Night light using {}", self.settings.backend);
+        let mut interval = interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            self.tick(1.0).await;
+        }
+    }
+
+    async fn stop(&self) {
+        // Leave the screen as it was found
+        if self.state.nightlight.read(|s| s.temperature) != self.settings.day_temperature {
+            let _ = self.apply(self.settings.day_temperature, true).await;
+            self.state
+                .nightlight
+                .update(|s| s.temperature = self.settings.day_temperature);
+        }
+    }
+}
+
+/// Send one command to hyprsunset, starting it first if needed
+async fn hyprsunset(command: &str) -> Result<(), String> {
+    let socket = hyprland::instance_dir()
+        .ok_or("Hyprland is not running")?
+        .join(".hyprsunset.sock");
+    let mut stream = match UnixStream::connect(&socket).await {
+        Ok(stream) => stream,
+        Err(_) => {
+            Command::new("hyprsunset")
+                .spawn()
+                .map_err(|e| format!("hyprsunset failed: {}", e))?;
+            connect_retrying(&socket).await?
+        }
+    };
+    stream
+        .write_all(command.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut reply = String::new();
+    timeout(REPLY_TIMEOUT, stream.read_to_string(&mut reply))
+        .await
+        .map_err(|_| "hyprsunset did not reply".to_string())?
+        .map_err(|e| e.to_string())?;
+    match reply.trim() {
+        "ok" | "" => Ok(()),
+        other => Err(format!("hyprsunset: {}", other)),
+    }
+}
+
+async fn connect_retrying(socket: &PathBuf) -> Result<UnixStream, String> {
+    for _ in 0..20 {
+        sleep(Duration::from_millis(100)).await;
+        if let Ok(stream) = UnixStream::connect(socket).await {
+            return Ok(stream);
+        }
+    }
+    Err("hyprsunset did not start".to_string())
+}
+
+fn minutes(time: Time) -> f64 {
+    time.hour() as f64 * 60.0 + time.minute() as f64 + time.second() as f64 / 60.0
+}
+
+/// How far into night it is at `now`, from 0 (day) to 1 (full night),
+/// ramping up over `transition` minutes from `start` and down from `end`.
+/// All times are minutes after midnight and may wrap around it.
+fn night_factor(now: f64, start: f64, end: f64, transition: f64) -> f64 {
+    let since = |t: f64| (now - t).rem_euclid(1440.0);
+    let length = (end - start).rem_euclid(1440.0);
+    if length == 0.0 {
+        return 0.0;
+    }
+    let ramp = |elapsed: f64| {
+        if transition <= 0.0 {
+            1.0
+        } else {
+            (elapsed / transition).min(1.0)
+        }
+    };
+    if since(start) < length {
+        ramp(since(start))
+    } else if since(end) < transition {
+        1.0 - ramp(since(end))
+    } else {
+        0.0
+    }
+}
+
+/// Step `current` towards `target` by at most `max` (0 for no limit)
+fn approach(current: u32, target: u32, max: f64) -> u32 {
+    if max <= 0.0 {
+        return target;
+    }
+    let max = max.round().max(1.0) as u32;
+    if current < target {
+        target.min(current + max)
+    } else {
+        target.max(current.saturating_sub(max))
+    }
+}
+
+/// Sunrise and sunset on `date` at latitude `lat` and longitude `lon`
+/// (degrees east), following the sunrise equation as used by NOAA
+fn sun_times(date: Date, lat: f64, lon: f64) -> SunTimes {
+    let rad = |deg: f64| deg * PI / 180.0;
+    let deg = |rad: f64| rad * 180.0 / PI;
+
+    // Julian day number of the date's UTC midnight
+    let days = date
+        .to_zoned(TimeZone::UTC)
+        .map_or(0, |z| z.timestamp().as_second().div_euclid(86400));
+    let julian = days as f64 + 2440587.5;
+    let n = (julian - 2451545.0 + 0.0008).ceil();
+
+    let mean_solar_noon = n - lon / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_solar_noon).rem_euclid(360.0);
+    let center = 1.9148 * rad(anomaly).sin()
+        + 0.02 * rad(2.0 * anomaly).sin()
+        + 0.0003 * rad(3.0 * anomaly).sin();
+    let longitude = (anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+    let transit = 2451545.0 + mean_solar_noon + 0.0053 * rad(anomaly).sin()
+        - 0.0069 * rad(2.0 * longitude).sin();
+    let declination = (rad(longitude).sin() * rad(23.4397).sin()).asin();
+    // -0.833° accounts for refraction and the sun's radius
+    let cos_hour_angle = (rad(-0.833).sin() - rad(lat).sin() * declination.sin())
+        / (rad(lat).cos() * declination.cos());
+    if cos_hour_angle > 1.0 {
+        return SunTimes::PolarNight;
+    }
+    if cos_hour_angle < -1.0 {
+        return SunTimes::MidnightSun;
+    }
+
+    let hour_angle = deg(cos_hour_angle.acos());
+    let timestamp = |julian: f64| {
+        Timestamp::from_second(((julian - 2440587.5) * 86400.0).round() as i64).unwrap_or_default()
+    };
+    SunTimes::Normal {
+        rise: timestamp(transit - hour_angle / 360.0),
+        set: timestamp(transit + hour_angle / 360.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_night_factor() {
+        // 20:00 to 07:00 with 30 minute ramps
+        let (start, end) = (1200.0, 420.0);
+        assert_eq!(night_factor(720.0, start, end, 30.0), 0.0);
+        assert_eq!(night_factor(1215.0, start, end, 30.0), 0.5);
+        assert_eq!(night_factor(1380.0, start, end, 30.0), 1.0);
+        assert_eq!(night_factor(60.0, start, end, 30.0), 1.0);
+        assert_eq!(night_factor(426.0, start, end, 30.0), 0.8);
+        assert_eq!(night_factor(450.0, start, end, 30.0), 0.0);
+        // A night that doesn't cross midnight
+        assert_eq!(night_factor(90.0, 60.0, 120.0, 0.0), 1.0);
+        assert_eq!(night_factor(130.0, 60.0, 120.0, 0.0), 0.0);
+        // No night at all
+        assert_eq!(night_factor(10.0, 0.0, 0.0, 30.0), 0.0);
+    }
+
+    #[test]
+    fn test_approach() {
+        assert_eq!(approach(6500, 4000, 250.0), 6250);
+        assert_eq!(approach(4100, 4000, 250.0), 4000);
+        assert_eq!(approach(4000, 6500, 250.0), 4250);
+        assert_eq!(approach(6500, 4000, 0.0), 4000);
+    }
+
+    #[test]
+    fn test_sun_times() {
+        let utc = |s: &str| s.parse::<Timestamp>().unwrap();
+        let close = |a: Timestamp, b: Timestamp| (a.as_second() - b.as_second()).abs() < 5 * 60;
+
+        // Berlin at midsummer
+        let SunTimes::Normal { rise, set } = sun_times(Date::constant(2024, 6, 21), 52.52, 13.405)
+        else {
+            panic!("no sunrise in Berlin");
+        };
+        assert!(close(rise, utc("2024-06-21T02:43:00Z")), "{}", rise);
+        assert!(close(set, utc("2024-06-21T19:33:00Z")), "{}", set);
+
+        // West of Greenwich and south of the equator
+        let SunTimes::Normal { rise, set } = sun_times(Date::constant(2024, 1, 15), -33.45, -70.67)
+        else {
+            panic!("no sunrise in Santiago");
+        };
+        assert!(close(rise, utc("2024-01-15T09:46:00Z")), "{}", rise);
+        assert!(close(set, utc("2024-01-15T23:54:00Z")), "{}", set);
+
+        // Tromsø
+        assert_eq!(
+            sun_times(Date::constant(2024, 6, 21), 69.65, 18.96),
+            SunTimes::MidnightSun
+        );
+        assert_eq!(
+            sun_times(Date::constant(2024, 12, 21), 69.65, 18.96),
+            SunTimes::PolarNight
+        );
+    }
+}
@@ -0,0 +1,530 @@
+//! Notification daemon
+//!
+//! Optionally owns `org.freedesktop.Notifications` on the session bus,
+//! replacing dunst. Notifications currently on screen are kept in
+//! `active` until they expire or are dismissed; every notification is
+//! also recorded in a history that persists across restarts.
+//!
+//! New notifications are announced with a `notification` event and
+//! popups going away with `notification-closed`, carrying the reason.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration, Instant};
+use tracing::{debug, info, warn};
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::OwnedValue;
+use zbus::{interface, Connection};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::{self, current_timestamp, ok, JsonFile, Module};
+use crate::state::Version;
+use crate::AppState;
+
+const BUS_NAME: &str = "org.freedesktop.Notifications";
+const OBJECT_PATH: &str = "/org/freedesktop/Notifications";
+
+/// Reasons for the `NotificationClosed` signal
+const CLOSED_EXPIRED: u32 = 1;
+const CLOSED_DISMISSED: u32 = 2;
+const CLOSED_BY_CALL: u32 = 3;
+
+/// Urgency levels from the `urgency` hint
+const URGENCY_NORMAL: u8 = 1;
+const URGENCY_CRITICAL: u8 = 2;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct NotificationSettings {
+    /// Maximum notifications kept in history
+    history_size: usize,
+    /// Timeout in milliseconds when the sender asks for the server default
+    default_timeout: u32,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            history_size: 100,
+            default_timeout: 5000,
+        }
+    }
+}
+
+/// An invokable notification action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Action {
+    pub key: String,
+    pub label: String,
+}
+
+/// A received notification
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: u32,
+    pub app_name: String,
+    /// Icon name or path, from `app_icon` or the `image-path` hint
+    pub icon: String,
+    pub summary: String,
+    pub body: String,
+    pub urgency: u8,
+    pub actions: Vec<Action>,
+    /// Requested timeout in milliseconds, -1 for server default, 0 for never
+    pub timeout: i32,
+    /// Unix timestamp when received
+    pub timestamp: u64,
+    #[serde(skip)]
+    expires_at: Option<Instant>,
+}
+
+/// Persistent notification state, the notifications domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationStore {
+    /// Notifications currently shown as popups
+    #[serde(skip)]
+    pub active: Vec<Notification>,
+    /// Past notifications (newest first)
+    pub history: Vec<Notification>,
+    /// Do not disturb: record notifications without showing popups
+    pub dnd: bool,
+    next_id: u32,
+}
+
+impl NotificationStore {
+    /// Add or replace a notification, returning its id
+    fn add(&mut self, mut notification: Notification, replaces_id: u32, history_size: usize) -> u32 {
+        if replaces_id != 0 && self.history.iter().any(|n| n.id == replaces_id) {
+            notification.id = replaces_id;
+            self.active.retain(|n| n.id != replaces_id);
+            self.history.retain(|n| n.id != replaces_id);
+        } else {
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+            notification.id = self.next_id;
+        }
+
+        let id = notification.id;
+        if !self.dnd || notification.urgency >= URGENCY_CRITICAL {
+            self.active.push(notification.clone());
+        }
+        self.history.insert(0, notification);
+        self.history.truncate(history_size);
+        id
+    }
+
+    /// Remove a popup, returning whether it was shown
+    fn close(&mut self, id: u32) -> bool {
+        let before = self.active.len();
+        self.active.retain(|n| n.id != id);
+        self.active.len() != before
+    }
+
+    /// Remove and return popups whose timeout elapsed
+    fn expire(&mut self, now: Instant) -> Vec<u32> {
+        let expired: Vec<u32> = self
+            .active
+            .iter()
+            .filter(|n| n.expires_at.is_some_and(|at| at <= now))
+            .map(|n| n.id)
+            .collect();
+        self.active.retain(|n| !expired.contains(&n.id));
+        expired
+    }
+}
+
+/// `org.freedesktop.Notifications` server
+struct NotificationServer {
+    state: Arc<AppState>,
+    file: JsonFile,
+    events: EventBus,
+    default_timeout: u32,
+    history_size: usize,
+}
+
+#[interface(name = "org.freedesktop.Notifications")]
+impl NotificationServer {
+    fn get_capabilities(&self) -> Vec<&str> {
+        vec!["actions", "body", "icon-static", "persistence"]
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn notify(
+        &self,
+        app_name: String,
+        replaces_id: u32,
+        app_icon: String,
+        summary: String,
+        body: String,
+        actions: Vec<String>,
+        hints: HashMap<String, OwnedValue>,
+        expire_timeout: i32,
+    ) -> u32 {
+        let urgency = hints
+            .get("urgency")
+            .and_then(|v| v.downcast_ref::<u8>().ok())
+            .unwrap_or(URGENCY_NORMAL);
+        let icon = if app_icon.is_empty() {
+            hints
+                .get("image-path")
+                .and_then(|v| v.downcast_ref::<String>().ok())
+                .unwrap_or_default()
+        } else {
+            app_icon
+        };
+
+        let timeout_ms = match expire_timeout {
+            _ if urgency >= URGENCY_CRITICAL => None,
+            0 => None,
+            t if t < 0 => Some(self.default_timeout as u64),
+            t => Some(t as u64),
+        };
+
+        let notification = Notification {
+            id: 0,
+            app_name,
+            icon,
+            summary,
+            body,
+            urgency,
+            actions: parse_actions(&actions),
+            timeout: expire_timeout,
+            timestamp: current_timestamp(),
+            expires_at: timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms)),
+        };
+
+        let app_name = notification.app_name.clone();
+        let mut event = json!(notification);
+        let (mut id, mut shown) = (0, false);
+        self.state.notifications.update(|store| {
+            id = store.add(notification, replaces_id, self.history_size);
+            shown = store.active.iter().any(|n| n.id == id);
+            self.file.save(store);
+        });
+        debug!("Notification {} from {}", id, app_name);
+
+        event["id"] = json!(id);
+        event["popup"] = json!(shown);
+        self.events.emit("notification", event);
+        id
+    }
+
+    async fn close_notification(
+        &self,
+        id: u32,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        if close(&self.state, id) {
+            closed_event(&self.events, id, CLOSED_BY_CALL);
+            Self::notification_closed(&emitter, id, CLOSED_BY_CALL).await?;
+        }
+        Ok(())
+    }
+
+    fn get_server_information(&self) -> (&str, &str, &str, &str) {
+        ("terra-shell", "TerraFlow", env!("CARGO_PKG_VERSION"), "1.2")
+    }
+
+    #[zbus(signal)]
+    async fn notification_closed(emitter: &SignalEmitter<'_>, id: u32, reason: u32)
+        -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn action_invoked(emitter: &SignalEmitter<'_>, id: u32, action_key: &str)
+        -> zbus::Result<()>;
+}
+
+/// Notification daemon module
+pub struct NotificationModule {
+    events: EventBus,
+    settings: NotificationSettings,
+    state: Arc<AppState>,
+    file: JsonFile,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl NotificationModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        let file = JsonFile::new("notifications.json");
+        state.notifications.set(file.load());
+        Self {
+            events,
+            settings: config.section("notifications"),
+            state,
+            file,
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// Signal emitter of the served interface, if the bus name is owned
+    async fn emitter(&self) -> Option<SignalEmitter<'static>> {
+        let connection = self.connection.lock().await.clone()?;
+        SignalEmitter::new(&connection, OBJECT_PATH).ok()
+    }
+
+    /// Close popups and tell their senders why
+    async fn close(&self, ids: &[u32], reason: u32) {
+        let emitter = self.emitter().await;
+        for &id in ids {
+            closed_event(&self.events, id, reason);
+            if let Some(emitter) = &emitter {
+                let _ = NotificationServer::notification_closed(emitter, id, reason).await;
+            }
+        }
+    }
+
+    async fn connect(&self) -> zbus::Result<Connection> {
+        let server = NotificationServer {
+            state: self.state.clone(),
+            file: self.file.clone(),
+            events: self.events.clone(),
+            default_timeout: self.settings.default_timeout,
+            // The notification being added is always kept
+            history_size: self.settings.history_size.max(1),
+        };
+        zbus::connection::Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, server)?
+            .build()
+            .await
+    }
+}
+
+#[async_trait]
+impl Module for NotificationModule {
+    fn name(&self) -> &'static str {
+        "notifications"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["notifications", "dnd"]
+    }
+
+    /// Opt-in, since it conflicts with a running dunst
+    fn enabled_by_default(&self) -> bool {
+        false
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.notifications.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        self.state.notifications.read(|store| {
+            json!({
+                "notifications": {
+                    "count": store.history.len(),
+                    "active": store.active.len(),
+                    "dnd": store.dnd
+                }
+            })
+        })
+    }
+
+    /// `notifications [list|dismiss <id|all>|invoke <id> [action]|clear]`, `dnd [on|off|toggle]`
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        if command == "dnd" {
+            let current = self.state.notifications.read(|store| store.dnd);
+            let dnd = match args.first().copied() {
+                Some("on") => true,
+                Some("off") => false,
+                Some("toggle") => !current,
+                None => return json!({"type": "dnd", "enabled": current}),
+                Some(other) => return module::error(format!("unknown dnd mode: {}", other)),
+            };
+            self.state.notifications.update(|store| {
+                store.dnd = dnd;
+                self.file.save(store);
+            });
+            return json!({"type": "dnd", "enabled": dnd});
+        }
+
+        match args.first().copied().unwrap_or("list") {
+            "list" => self.state.notifications.read(|store| {
+                json!({
+                    "type": "notifications",
+                    "dnd": store.dnd,
+                    "active": store.active,
+                    "history": store.history
+                })
+            }),
+            "dismiss" => {
+                let ids: Vec<u32> = match args.get(1).copied() {
+                    Some("all") => {
+                        let mut ids = Vec::new();
+                        self.state.notifications.update(|store| {
+                            ids = store.active.drain(..).map(|n| n.id).collect();
+                        });
+                        ids
+                    }
+                    Some(id) => match id.parse() {
+                        Ok(id) if close(&self.state, id) => vec![id],
+                        _ => return module::error(format!("no active notification {}", id)),
+                    },
+                    None => return module::error("usage: notifications dismiss <id|all>"),
+                };
+                self.close(&ids, CLOSED_DISMISSED).await;
+                ok()
+            }
+            "invoke" => {
+                let Some(id) = args.get(1).and_then(|id| id.parse::<u32>().ok()) else {
+                    return module::error("usage: notifications invoke <id> [action]");
+                };
+                let key = args.get(2).copied().unwrap_or("default");
+                let known = self.state.notifications.read(|store| {
+                    store
+                        .history
+                        .iter()
+                        .find(|n| n.id == id)
+                        .is_some_and(|n| n.actions.iter().any(|a| a.key == key))
+                });
+                if !known {
+                    return module::error(format!("notification {} has no action {}", id, key));
+                }
+                let Some(emitter) = self.emitter().await else {
+                    return module::error("notification server not running");
+                };
+                if let Err(e) = NotificationServer::action_invoked(&emitter, id, key).await {
+                    return module::error(e.to_string());
+                }
+                if close(&self.state, id) {
+                    self.close(&[id], CLOSED_DISMISSED).await;
+                }
+                ok()
+            }
+            "clear" => {
+                self.state.notifications.update(|store| {
+                    store.history.clear();
+                    self.file.save(store);
+                });
+                ok()
+            }
+            other => module::error(format!("unknown notifications command: {}", other)),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let connection = match self.connect().await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Cannot own {} (is dunst running?): {}", BUS_NAME, e);
+                return;
+            }
+        };
+        info!("Serving {} on the session bus", BUS_NAME);
+        *self.connection.lock().await = Some(connection);
+
+        let mut interval = interval(Duration::from_millis(250));
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            let due = self.state.notifications.read(|store| {
+                store.active.iter().any(|n| n.expires_at.is_some_and(|at| at <= now))
+            });
+            if !due {
+                continue;
+            }
+            let mut expired = Vec::new();
+            self.state.notifications.update(|store| expired = store.expire(now));
+            if !expired.is_empty() {
+                self.close(&expired, CLOSED_EXPIRED).await;
+            }
+        }
+    }
+
+    /// Drop the connection so the bus name is released
+    async fn stop(&self) {
+        self.connection.lock().await.take();
+    }
+}
+
+/// Remove a popup from the shared store, returning whether it was shown
+fn close(state: &AppState, id: u32) -> bool {
+    let mut closed = false;
+    state.notifications.update(|store| closed = store.close(id));
+    closed
+}
+
+/// Announce a popup going away on the event bus
+fn closed_event(events: &EventBus, id: u32, reason: u32) {
+    let reason = match reason {
+        CLOSED_EXPIRED => "expired",
+        CLOSED_DISMISSED => "dismissed",
+        _ => "closed",
+    };
+    events.emit("notification-closed", json!({"id": id, "reason": reason}));
+}
+
+/// Actions arrive as a flat list of key, label pairs
+fn parse_actions(actions: &[String]) -> Vec<Action> {
+    actions
+        .chunks_exact(2)
+        .map(|pair| Action {
+            key: pair[0].clone(),
+            label: pair[1].clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(urgency: u8) -> Notification {
+        Notification {
+            id: 0,
+            app_name: "test".to_string(),
+            icon: String::new(),
+            summary: "Hello".to_string(),
+            body: String::new(),
+            urgency,
+            actions: vec![],
+            timeout: -1,
+            timestamp: 0,
+            expires_at: Some(Instant::now()),
+        }
+    }
+
+    #[test]
+    fn test_parse_actions() {
+        let actions = parse_actions(&[
+            "default".to_string(),
+            "Open".to_string(),
+            "dangling".to_string(),
+        ]);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0].key, "default");
+        assert_eq!(actions[0].label, "Open");
+    }
+
+    #[test]
+    fn test_store_replace_and_expire() {
+        let mut store = NotificationStore::default();
+        let first = store.add(notification(URGENCY_NORMAL), 0, 10);
+        let replaced = store.add(notification(URGENCY_NORMAL), first, 10);
+        assert_eq!(first, replaced);
+        assert_eq!(store.history.len(), 1);
+        assert_eq!(store.active.len(), 1);
+
+        assert_eq!(store.expire(Instant::now()), vec![first]);
+        assert!(store.active.is_empty());
+    }
+
+    #[test]
+    fn test_store_dnd() {
+        let mut store = NotificationStore {
+            dnd: true,
+            ..Default::default()
+        };
+        store.add(notification(URGENCY_NORMAL), 0, 10);
+        store.add(notification(URGENCY_CRITICAL), 0, 10);
+        assert_eq!(store.history.len(), 2);
+        assert_eq!(store.active.len(), 1);
+        assert_eq!(store.active[0].urgency, URGENCY_CRITICAL);
+    }
+}
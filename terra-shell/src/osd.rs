@@ -0,0 +1,242 @@
+//! On-screen display events
+//!
+//! Emits a debounced `osd` event whenever the audio, brightness or LED
+//! domains of [`AppState`] change, whatever caused it (keybinds, hardware
+//! keys, IPC).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::Module;
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct OsdSettings {
+    /// Milliseconds changes are coalesced for
+    debounce: u64,
+}
+
+impl Default for OsdSettings {
+    fn default() -> Self {
+        Self { debounce: 100 }
+    }
+}
+
+/// What an OSD shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OsdKind {
+    Volume,
+    Mic,
+    Brightness,
+    KbdBacklight,
+    CapsLock,
+}
+
+impl OsdKind {
+    const ALL: [OsdKind; 5] = [
+        OsdKind::Volume,
+        OsdKind::Mic,
+        OsdKind::Brightness,
+        OsdKind::KbdBacklight,
+        OsdKind::CapsLock,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            OsdKind::Volume => "volume",
+            OsdKind::Mic => "mic",
+            OsdKind::Brightness => "brightness",
+            OsdKind::KbdBacklight => "kbd-backlight",
+            OsdKind::CapsLock => "caps-lock",
+        }
+    }
+
+    /// Current value, `None` until the source module has read it
+    fn read(self, s: &AppState) -> Option<OsdValue> {
+        let value = match self {
+            OsdKind::Volume if s.audio.version().seq > 0 => s.audio.read(|a| OsdValue {
+                value: a.volume,
+                muted: a.muted,
+            }),
+            OsdKind::Mic if s.audio.version().seq > 0 => s.audio.read(|a| OsdValue {
+                value: a.mic.volume,
+                muted: a.mic.muted,
+            }),
+            OsdKind::Brightness if s.brightness.version().seq > 0 => OsdValue {
+                value: s.brightness.get(),
+                muted: false,
+            },
+            // Percent, like the screen brightness
+            OsdKind::KbdBacklight if s.leds.version().seq > 0 => {
+                let leds = s.leds.get();
+                if leds.kbd_backlight_max == 0 {
+                    return None;
+                }
+                OsdValue {
+                    value: (leds.kbd_backlight * 100 / leds.kbd_backlight_max).min(100) as u8,
+                    muted: false,
+                }
+            }
+            // 1 when on
+            OsdKind::CapsLock if s.leds.version().seq > 0 => OsdValue {
+                value: u8::from(s.leds.read(|l| l.caps_lock)),
+                muted: false,
+            },
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OsdValue {
+    value: u8,
+    muted: bool,
+}
+
+/// Emits `osd` events on volume, mic, brightness and keyboard changes
+pub struct OsdModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    settings: OsdSettings,
+    last: Mutex<HashMap<OsdKind, OsdValue>>,
+}
+
+impl OsdModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        Self {
+            state,
+            events,
+            settings: config.section("osd"),
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Compare against the last sent values and emit what changed. The
+    /// first value of each kind is sent with `"user": false`, so the UI
+    /// can skip popping up an OSD at startup.
+    async fn check(&self) {
+        let mut last = self.last.lock().await;
+
+        for kind in OsdKind::ALL {
+            let Some(current) = kind.read(&self.state) else {
+                continue;
+            };
+            let user = match last.insert(kind, current) {
+                None => false,
+                Some(previous) if previous != current => true,
+                Some(_) => continue,
+            };
+            self.events.emit(
+                "osd",
+                json!({
+                    "kind": kind.name(),
+                    "value": current.value,
+                    "muted": current.muted,
+                    "user": user
+                }),
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Module for OsdModule {
+    fn name(&self) -> &'static str {
+        "osd"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["osd"]
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({})
+    }
+
+    /// `osd` lists the last values sent per kind
+    async fn handle(&self, _command: &str, _args: &[&str]) -> Value {
+        let last = self.last.lock().await;
+        let values: serde_json::Map<String, Value> = OsdKind::ALL
+            .into_iter()
+            .filter_map(|kind| {
+                let v = last.get(&kind)?;
+                Some((kind.name().to_string(), json!({"value": v.value, "muted": v.muted})))
+            })
+            .collect();
+        json!({"type": "osd", "debounce": self.settings.debounce, "values": values})
+    }
+
+    async fn run(self: Arc<Self>) {
+        // A restarted module rediscovers values rather than replaying them
+        self.last.lock().await.clear();
+
+        let debounce = Duration::from_millis(self.settings.debounce);
+        let mut audio = self.state.audio.subscribe();
+        let mut brightness = self.state.brightness.subscribe();
+        let mut leds = self.state.leds.subscribe();
+        self.check().await;
+
+        loop {
+            let changed = tokio::select! {
+                r = audio.changed() => r,
+                r = brightness.changed() => r,
+                r = leds.changed() => r,
+            };
+            if changed.is_err() {
+                break;
+            }
+            // Let the rest of a burst arrive, then send its latest values
+            sleep(debounce).await;
+            audio.mark_unchanged();
+            brightness.mark_unchanged();
+            leds.mark_unchanged();
+            self.check().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_osd_events() {
+        let state = Arc::new(AppState::default());
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+        let osd = OsdModule::new(state.clone(), events, &Config::default());
+
+        // Nothing until the audio module has read the volume
+        osd.check().await;
+        assert!(rx.try_recv().is_err());
+
+        state.audio.update(|a| a.volume = 40);
+        osd.check().await;
+        let volume = rx.try_recv().unwrap().payload;
+        assert_eq!(volume["kind"], "volume");
+        assert_eq!(volume["value"], 40);
+        assert_eq!(volume["user"], false);
+        assert_eq!(rx.try_recv().unwrap().payload["kind"], "mic");
+
+        // Unchanged values are not repeated, changes are coalesced
+        osd.check().await;
+        assert!(rx.try_recv().is_err());
+        state.audio.update(|a| a.volume = 45);
+        state.audio.update(|a| a.volume = 50);
+        osd.check().await;
+        let volume = rx.try_recv().unwrap().payload;
+        assert_eq!(volume["value"], 50);
+        assert_eq!(volume["user"], true);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,406 @@
+//! Idle inhibition and power profiles
+//!
+//! `caffeine` holds a logind idle inhibitor (and an `org.freedesktop.ScreenSaver`
+//! one when the session provides it) so hypridle doesn't lock. Power
+//! profiles are read and switched through power-profiles-daemon.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::{json, Value as Json};
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+use tracing::{debug, info, warn};
+use zbus::fdo::PropertiesProxy;
+use zbus::names::InterfaceName;
+use zbus::zvariant::{OwnedFd, OwnedValue, Value};
+use zbus::Connection;
+
+use crate::dbus::prop_string;
+use crate::module::{self, Module};
+use crate::state::Version;
+use crate::AppState;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+
+const SCREENSAVER: &str = "org.freedesktop.ScreenSaver";
+const SCREENSAVER_PATH: &str = "/org/freedesktop/ScreenSaver";
+
+/// power-profiles-daemon names: current first, legacy second
+const PROFILE_SERVICES: [(&str, &str); 2] = [
+    ("org.freedesktop.UPower.PowerProfiles", "/org/freedesktop/UPower/PowerProfiles"),
+    ("net.hadess.PowerProfiles", "/net/hadess/PowerProfiles"),
+];
+
+/// Power domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PowerState {
+    pub caffeine: bool,
+    pub profile: String,
+    pub profiles: Vec<String>,
+    pub inhibitors: Vec<Inhibitor>,
+}
+
+/// A logind inhibitor lock
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Inhibitor {
+    /// Colon separated list, e.g. `idle:sleep`
+    pub what: String,
+    pub who: String,
+    pub why: String,
+    /// `block` or `delay`
+    pub mode: String,
+    pub pid: u32,
+}
+
+/// Inhibitors held while caffeine is on
+#[derive(Default)]
+struct Caffeine {
+    /// Released when dropped
+    logind: Option<OwnedFd>,
+    screensaver: Option<u32>,
+}
+
+/// Caffeine toggle and power profile switching
+pub struct PowerModule {
+    state: Arc<AppState>,
+    caffeine: Mutex<Caffeine>,
+    system: Mutex<Option<Connection>>,
+    session: Mutex<Option<Connection>>,
+}
+
+impl PowerModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self {
+            state,
+            caffeine: Mutex::new(Caffeine::default()),
+            system: Mutex::new(None),
+            session: Mutex::new(None),
+        }
+    }
+
+    async fn system(&self) -> Option<Connection> {
+        let mut system = self.system.lock().await;
+        if system.is_none() {
+            *system = Connection::system().await.ok();
+        }
+        system.clone()
+    }
+
+    async fn session(&self) -> Option<Connection> {
+        let mut session = self.session.lock().await;
+        if session.is_none() {
+            *session = Connection::session().await.ok();
+        }
+        session.clone()
+    }
+
+    /// Take or release the idle inhibitors
+    async fn set_caffeine(&self, enabled: bool) -> Result<(), String> {
+        let mut caffeine = self.caffeine.lock().await;
+        if !enabled {
+            caffeine.logind.take();
+            if let (Some(cookie), Some(session)) = (caffeine.screensaver.take(), self.session().await) {
+                let _ = session
+                    .call_method(Some(SCREENSAVER), SCREENSAVER_PATH, Some(SCREENSAVER), "UnInhibit", &(cookie,))
+                    .await;
+            }
+            self.state.power.update(|s| s.caffeine = false);
+            info!("Caffeine off");
+            return Ok(());
+        }
+
+        if caffeine.logind.is_none() {
+            if let Some(system) = self.system().await {
+                let reply = system
+                    .call_method(
+                        Some(LOGIND),
+                        LOGIND_PATH,
+                        Some(LOGIND_MANAGER),
+                        "Inhibit",
+                        &("idle", "terra-shell", "Caffeine mode", "block"),
+                    )
+                    .await;
+                match reply.and_then(|r| r.body().deserialize::<OwnedFd>()) {
+                    Ok(fd) => caffeine.logind = Some(fd),
+                    Err(e) => warn!("logind idle inhibit failed: {}", e),
+                }
+            }
+        }
+
+        if caffeine.screensaver.is_none() {
+            if let Some(session) = self.session().await {
+                let reply = session
+                    .call_method(
+                        Some(SCREENSAVER),
+                        SCREENSAVER_PATH,
+                        Some(SCREENSAVER),
+                        "Inhibit",
+                        &("terra-shell", "Caffeine mode"),
+                    )
+                    .await;
+                if let Ok(cookie) = reply.and_then(|r| r.body().deserialize::<u32>()) {
+                    caffeine.screensaver = Some(cookie);
+                }
+            }
+        }
+
+        if caffeine.logind.is_none() && caffeine.screensaver.is_none() {
+            return Err("no idle inhibitor available".to_string());
+        }
+        self.state.power.update(|s| s.caffeine = true);
+        info!("Caffeine on");
+        Ok(())
+    }
+
+    /// Current logind inhibitors
+    async fn list_inhibitors(&self) -> Vec<Inhibitor> {
+        let Some(system) = self.system().await else {
+            return vec![];
+        };
+        let reply = system
+            .call_method(Some(LOGIND), LOGIND_PATH, Some(LOGIND_MANAGER), "ListInhibitors", &())
+            .await;
+        let list: Vec<(String, String, String, String, u32, u32)> =
+            match reply.and_then(|r| r.body().deserialize()) {
+                Ok(list) => list,
+                Err(e) => {
+                    debug!("ListInhibitors failed: {}", e);
+                    return vec![];
+                }
+            };
+        list.into_iter()
+            .map(|(what, who, why, mode, _uid, pid)| Inhibitor {
+                what,
+                who,
+                why,
+                mode,
+                pid,
+            })
+            .collect()
+    }
+
+    /// Properties proxy of the first power-profiles-daemon name that answers
+    async fn profiles_proxy(&self) -> Option<(PropertiesProxy<'static>, &'static str)> {
+        let system = self.system().await?;
+        for (name, path) in PROFILE_SERVICES {
+            let Ok(builder) = PropertiesProxy::builder(&system)
+                .destination(name)
+                .and_then(|b| b.path(path))
+            else {
+                continue;
+            };
+            let Ok(proxy) = builder.build().await else {
+                continue;
+            };
+            if proxy
+                .get(InterfaceName::from_static_str_unchecked(name), "ActiveProfile")
+                .await
+                .is_ok()
+            {
+                return Some((proxy, name));
+            }
+        }
+        None
+    }
+
+    /// Active profile and available profiles
+    async fn read_profiles(&self) -> Option<(String, Vec<String>)> {
+        let (proxy, interface) = self.profiles_proxy().await?;
+        let props = proxy
+            .get_all(InterfaceName::from_static_str_unchecked(interface))
+            .await
+            .ok()?;
+        Some(parse_profiles(&props))
+    }
+
+    async fn set_profile(&self, profile: &str) -> Result<(), String> {
+        let (proxy, interface) = self
+            .profiles_proxy()
+            .await
+            .ok_or_else(|| "power-profiles-daemon not available".to_string())?;
+        proxy
+            .set(
+                InterfaceName::from_static_str_unchecked(interface),
+                "ActiveProfile",
+                Value::from(profile),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        self.state.power.update(|s| s.profile = profile.to_string());
+        info!("Power profile: {}", profile);
+        Ok(())
+    }
+
+    async fn refresh(&self) {
+        let inhibitors = self.list_inhibitors().await;
+        let profiles = self.read_profiles().await;
+
+        self.state.power.update(|s| {
+            s.inhibitors = inhibitors;
+            if let Some((active, available)) = profiles {
+                if s.profile != active {
+                    debug!("Power profile changed: {}", active);
+                }
+                s.profile = active;
+                s.profiles = available;
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Module for PowerModule {
+    fn name(&self) -> &'static str {
+        "power"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["caffeine", "inhibitors", "power-profile"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.power.version())
+    }
+
+    async fn snapshot(&self) -> Json {
+        let s = self.state.power.get();
+        json!({
+            "power": {
+                "caffeine": s.caffeine,
+                "profile": s.profile,
+                "profiles": s.profiles,
+                "inhibitors": s.inhibitors.len()
+            }
+        })
+    }
+
+    /// `caffeine [on|off|toggle]`, `inhibitors`, `power-profile [<name>|next]`
+    async fn handle(&self, command: &str, args: &[&str]) -> Json {
+        match command {
+            "caffeine" => {
+                let current = self.state.power.read(|s| s.caffeine);
+                let enabled = match args.first().copied() {
+                    None => return json!({"type": "caffeine", "enabled": current}),
+                    Some("on") => true,
+                    Some("off") => false,
+                    Some("toggle") => !current,
+                    Some(other) => return module::error(format!("unknown caffeine mode: {}", other)),
+                };
+                match self.set_caffeine(enabled).await {
+                    Ok(()) => json!({"type": "caffeine", "enabled": enabled}),
+                    Err(e) => module::error(e),
+                }
+            }
+            "inhibitors" => {
+                self.refresh().await;
+                let s = self.state.power.get();
+                json!({"type": "inhibitors", "caffeine": s.caffeine, "list": s.inhibitors})
+            }
+            _ => {
+                let (current, available) = self.state.power.read(|s| (s.profile.clone(), s.profiles.clone()));
+                let target = match args.first().copied() {
+                    None => {
+                        return json!({
+                            "type": "power-profile",
+                            "active": current,
+                            "profiles": available
+                        })
+                    }
+                    Some("next") => next_profile(&current, &available),
+                    Some(name) if available.iter().any(|p| p == name) => Some(name.to_string()),
+                    Some(name) => return module::error(format!("unknown power profile: {}", name)),
+                };
+                let Some(target) = target else {
+                    return module::error("no power profiles available");
+                };
+                match self.set_profile(&target).await {
+                    Ok(()) => json!({"type": "power-profile", "active": target, "profiles": available}),
+                    Err(e) => module::error(e),
+                }
+            }
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(5));
+
+        loop {
+            interval.tick().await;
+            self.refresh().await;
+        }
+    }
+
+    /// Release inhibitors so a stopped module can't keep the screen awake
+    async fn stop(&self) {
+        let _ = self.set_caffeine(false).await;
+    }
+}
+
+/// Read `ActiveProfile` and the names in `Profiles`
+fn parse_profiles(props: &HashMap<String, OwnedValue>) -> (String, Vec<String>) {
+    let active = prop_string(props, "ActiveProfile");
+    let available = props
+        .get("Profiles")
+        .and_then(|v| v.try_clone().ok())
+        .and_then(|v| Vec::<HashMap<String, OwnedValue>>::try_from(v).ok())
+        .unwrap_or_default()
+        .iter()
+        .map(|profile| prop_string(profile, "Profile"))
+        .filter(|name| !name.is_empty())
+        .collect();
+    (active, available)
+}
+
+/// Profile after `current`, wrapping around
+fn next_profile(current: &str, available: &[String]) -> Option<String> {
+    let index = available.iter().position(|p| p == current);
+    let next = index.map_or(0, |i| (i + 1) % available.len());
+    available.get(next).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_profile() {
+        let profiles = vec![
+            "power-saver".to_string(),
+            "balanced".to_string(),
+            "performance".to_string(),
+        ];
+        assert_eq!(next_profile("balanced", &profiles).as_deref(), Some("performance"));
+        assert_eq!(next_profile("performance", &profiles).as_deref(), Some("power-saver"));
+        assert_eq!(next_profile("", &profiles).as_deref(), Some("power-saver"));
+        assert_eq!(next_profile("balanced", &[]), None);
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let profile = |name: &str| {
+            HashMap::from([(
+                "Profile".to_string(),
+                OwnedValue::try_from(Value::from(name)).unwrap(),
+            )])
+        };
+        let profiles = vec![profile("power-saver"), profile("balanced")];
+        let props = HashMap::from([
+            (
+                "ActiveProfile".to_string(),
+                OwnedValue::try_from(Value::from("balanced")).unwrap(),
+            ),
+            (
+                "Profiles".to_string(),
+                OwnedValue::try_from(Value::from(profiles)).unwrap(),
+            ),
+        ]);
+        let (active, available) = parse_profiles(&props);
+        assert_eq!(active, "balanced");
+        assert_eq!(available, vec!["power-saver", "balanced"]);
+    }
+}
@@ -0,0 +1,543 @@
+//! Privacy indicators
+//!
+//! Reports whether the camera, microphone or screen are being captured and
+//! by which apps, for the bar's privacy dots. Three sources are combined:
+//!
+//! - processes holding a `/dev/video*` device open, found through
+//!   `/proc/*/fd` (only our own user's processes are visible, which covers
+//!   desktop apps). PipeWire itself keeps cameras open while idle, so its
+//!   daemons only count while a running stream reads from a camera
+//! - PipeWire capture streams, from the graph `pw-dump --monitor` keeps
+//!   us up to date on: audio streams fed by a source are microphone use,
+//!   video streams are screencasts when fed by the desktop portal and
+//!   camera use otherwise
+//! - Hyprland's `screencast` event, which also covers sharing that does
+//!   not go through PipeWire
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::Module;
+use crate::state::Version;
+use crate::AppState;
+
+/// PipeWire daemons open cameras on behalf of their clients
+const PIPEWIRE_PROCESSES: [&str; 2] = ["pipewire", "wireplumber"];
+
+/// `device.api` of PipeWire camera sources
+const CAMERA_APIS: [&str; 2] = ["v4l2", "libcamera"];
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct PrivacySettings {
+    /// Milliseconds between checks
+    interval: u64,
+    /// Apps never reported, e.g. level meters
+    ignore: Vec<String>,
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        Self {
+            interval: 2000,
+            ignore: vec!["pavucontrol".to_string(), "cava".to_string()],
+        }
+    }
+}
+
+/// Use of one kind of device
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DeviceUse {
+    pub active: bool,
+    /// Apps using it, may be empty while active when the user is unknown
+    pub apps: Vec<String>,
+}
+
+impl DeviceUse {
+    fn new(apps: BTreeSet<String>, active: bool) -> Self {
+        Self {
+            active: active || !apps.is_empty(),
+            apps: apps.into_iter().collect(),
+        }
+    }
+}
+
+/// Privacy domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PrivacyState {
+    pub camera: DeviceUse,
+    pub microphone: DeviceUse,
+    pub screencast: DeviceUse,
+}
+
+/// Capture streams found in the PipeWire graph
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Streams {
+    microphone: BTreeSet<String>,
+    camera: BTreeSet<String>,
+    screencast: BTreeSet<String>,
+    /// The portal is producing frames, even if no consumer was found
+    screencast_active: bool,
+    /// A running stream is linked to a camera source
+    camera_active: bool,
+}
+
+/// A running `pw-dump --monitor`
+struct PwDump {
+    /// Killed when dropped
+    _child: Child,
+    /// Arrays of added or changed objects, the first one is the whole graph
+    updates: mpsc::UnboundedReceiver<Value>,
+}
+
+impl PwDump {
+    fn spawn() -> Option<Self> {
+        let mut child = Command::new("pw-dump")
+            .arg("--monitor")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .ok()?;
+        let stdout = child.stdout.take()?.into_owned_fd().ok()?;
+        let (tx, updates) = mpsc::unbounded_channel();
+        // The output is a stream of pretty-printed JSON documents, which
+        // serde_json can only split from a blocking reader
+        tokio::task::spawn_blocking(move || {
+            let reader = BufReader::new(File::from(stdout));
+            for value in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+                let value = match value {
+                    Ok(value) => value,
+                    Err(e) => {
+                        if !e.is_eof() {
+                            warn!("Unreadable pw-dump output: {}", e);
+                        }
+                        break;
+                    }
+                };
+                if tx.send(value).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Self {
+            _child: child,
+            updates,
+        })
+    }
+}
+
+/// Next update of a running `pw-dump`, `None` once it exited
+async fn next_update(pw_dump: &mut Option<PwDump>) -> Option<Value> {
+    match pw_dump {
+        Some(pw_dump) => pw_dump.updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Apply one `pw-dump` update to the graph: objects are replaced whole and
+/// removed ones come with a null `info`
+fn apply_update(graph: &mut HashMap<u64, Value>, update: Value) {
+    let Value::Array(objects) = update else {
+        return;
+    };
+    for object in objects {
+        let Some(id) = object["id"].as_u64() else {
+            continue;
+        };
+        if object.get("info") == Some(&Value::Null) {
+            graph.remove(&id);
+        } else {
+            graph.insert(id, object);
+        }
+    }
+}
+
+/// Camera, microphone and screen capture monitor
+pub struct PrivacyModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    settings: PrivacySettings,
+    proc: PathBuf,
+}
+
+impl PrivacyModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        Self {
+            state,
+            events,
+            settings: config.section("privacy"),
+            proc: PathBuf::from("/proc"),
+        }
+    }
+
+    /// Combine the PipeWire streams with the other sources and publish
+    async fn refresh(&self, streams: Streams) {
+        // Walks every process's file descriptors
+        let proc = self.proc.clone();
+        let holders = tokio::task::spawn_blocking(move || video_users(&proc))
+            .await
+            .unwrap_or_default();
+        let hyprland_screencast = self.state.hyprland.read(|s| s.screencast);
+
+        let reported = |app: &String| {
+            !self
+                .settings
+                .ignore
+                .iter()
+                .any(|i| i.eq_ignore_ascii_case(app))
+        };
+        let mut camera: BTreeSet<String> = holders
+            .into_iter()
+            .filter(|name| !PIPEWIRE_PROCESSES.contains(&name.as_str()))
+            .collect();
+        let camera_active = !camera.is_empty() || streams.camera_active;
+        camera.extend(streams.camera);
+
+        let state = PrivacyState {
+            camera: DeviceUse::new(camera.into_iter().filter(reported).collect(), camera_active),
+            microphone: DeviceUse::new(
+                streams.microphone.into_iter().filter(reported).collect(),
+                false,
+            ),
+            screencast: DeviceUse::new(
+                streams.screencast.into_iter().filter(reported).collect(),
+                streams.screencast_active || hyprland_screencast,
+            ),
+        };
+        if self.state.privacy.set(state.clone()) {
+            debug!("Privacy: {:?}", state);
+            self.events.emit("privacy", json!(state));
+        }
+    }
+}
+
+#[async_trait]
+impl Module for PrivacyModule {
+    fn name(&self) -> &'static str {
+        "privacy"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["privacy"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.privacy.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"privacy": self.state.privacy.get()})
+    }
+
+    async fn handle(&self, _command: &str, _args: &[&str]) -> Value {
+        let s = self.state.privacy.get();
+        json!({
+            "type": "privacy",
+            "camera": s.camera,
+            "microphone": s.microphone,
+            "screencast": s.screencast
+        })
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_millis(self.settings.interval.max(100)));
+        let mut hyprland = self.state.hyprland.subscribe();
+        let mut screencast = false;
+        let mut pw_dump = None;
+        let mut fresh = false;
+        let mut graph = HashMap::new();
+        let mut streams = Streams::default();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    // Started again after PipeWire restarts
+                    if pw_dump.is_none() {
+                        pw_dump = PwDump::spawn();
+                        fresh = true;
+                    }
+                }
+                update = next_update(&mut pw_dump) => {
+                    let Some(update) = update else {
+                        pw_dump = None;
+                        continue;
+                    };
+                    // A new process starts with the whole graph
+                    if std::mem::take(&mut fresh) {
+                        graph.clear();
+                    }
+                    apply_update(&mut graph, update);
+                    streams = find_streams(graph.values());
+                }
+                // Hyprland screencast changes are passed on right away
+                Ok(()) = hyprland.changed() => {
+                    if hyprland.borrow_and_update().value.screencast == screencast {
+                        continue;
+                    }
+                }
+            }
+            screencast = self.state.hyprland.read(|s| s.screencast);
+            self.refresh(streams.clone()).await;
+        }
+    }
+}
+
+/// Names of processes with a video device open
+fn video_users(proc: &Path) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    let Ok(entries) = fs::read_dir(proc) else {
+        return names;
+    };
+    for entry in entries.flatten() {
+        let pid_dir = entry.path();
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+        // Unreadable for other users' processes
+        let Ok(fds) = fs::read_dir(pid_dir.join("fd")) else {
+            continue;
+        };
+        let uses_video = fds.flatten().any(|fd| {
+            fs::read_link(fd.path())
+                .is_ok_and(|target| target.to_string_lossy().starts_with("/dev/video"))
+        });
+        if uses_video {
+            if let Ok(comm) = fs::read_to_string(pid_dir.join("comm")) {
+                names.insert(comm.trim().to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Find capture streams among `pw-dump` objects
+fn find_streams<'a>(objects: impl IntoIterator<Item = &'a Value>) -> Streams {
+    let mut streams = Streams::default();
+
+    struct Node<'a> {
+        class: &'a str,
+        running: bool,
+        name: String,
+        props: &'a Value,
+    }
+    let mut nodes = HashMap::new();
+    let mut links = Vec::new();
+    for object in objects {
+        let info = &object["info"];
+        match object["type"].as_str() {
+            Some("PipeWire:Interface:Node") => {
+                let Some(id) = object["id"].as_u64() else {
+                    continue;
+                };
+                let props = &info["props"];
+                let name = [
+                    "application.name",
+                    "application.process.binary",
+                    "node.name",
+                ]
+                .iter()
+                .find_map(|key| props[key].as_str())
+                .unwrap_or_default()
+                .to_string();
+                nodes.insert(
+                    id,
+                    Node {
+                        class: props["media.class"].as_str().unwrap_or_default(),
+                        running: info["state"] == "running",
+                        name,
+                        props,
+                    },
+                );
+            }
+            Some("PipeWire:Interface:Link") => {
+                if let (Some(output), Some(input)) = (
+                    info["output-node-id"].as_u64(),
+                    info["input-node-id"].as_u64(),
+                ) {
+                    links.push((output, input));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let is_portal = |node: &Node| {
+        node.class == "Video/Source"
+            && [
+                node.name.as_str(),
+                node.props["node.name"].as_str().unwrap_or_default(),
+            ]
+            .iter()
+            .any(|n| n.contains("xdg-desktop-portal"))
+    };
+    for node in nodes.values() {
+        if is_portal(node) && node.running {
+            streams.screencast_active = true;
+        }
+    }
+
+    for (output, input) in links {
+        let (Some(source), Some(stream)) = (nodes.get(&output), nodes.get(&input)) else {
+            continue;
+        };
+        if !stream.running || stream.props["stream.monitor"] == true {
+            continue;
+        }
+        let name = stream.name.clone();
+        match stream.class {
+            // Sink monitors are desktop audio, not the microphone
+            "Stream/Input/Audio" if source.class.starts_with("Audio/Source") => {
+                streams.microphone.insert(name);
+            }
+            "Stream/Input/Video" if is_portal(source) => {
+                streams.screencast.insert(name);
+            }
+            "Stream/Input/Video" => {
+                let api = source.props["device.api"].as_str().unwrap_or_default();
+                streams.camera_active |= CAMERA_APIS.contains(&api);
+                streams.camera.insert(name);
+            }
+            _ => {}
+        }
+    }
+    streams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: u64, class: &str, state: &str, props: Value) -> Value {
+        let mut props = props;
+        props["media.class"] = json!(class);
+        json!({"id": id, "type": "PipeWire:Interface:Node", "info": {"state": state, "props": props}})
+    }
+
+    fn link(output: u64, input: u64) -> Value {
+        json!({"id": 1000 + input, "type": "PipeWire:Interface:Link",
+               "info": {"output-node-id": output, "input-node-id": input, "state": "active"}})
+    }
+
+    #[test]
+    fn test_find_streams() {
+        let dump = json!([
+            node(40, "Audio/Source", "running", json!({"node.name": "alsa_input.pci"})),
+            node(41, "Audio/Sink", "running", json!({"node.name": "alsa_output.pci"})),
+            node(42, "Video/Source", "running", json!({"node.name": "xdg-desktop-portal-hyprland"})),
+            node(43, "Video/Source", "running",
+                 json!({"node.name": "v4l2_input.usb", "device.api": "v4l2"})),
+            node(50, "Stream/Input/Audio", "running", json!({"application.name": "Firefox"})),
+            node(51, "Stream/Input/Audio", "running", json!({"application.name": "OBS"})),
+            node(52, "Stream/Input/Audio", "idle", json!({"application.name": "Discord"})),
+            node(53, "Stream/Input/Audio", "running",
+                 json!({"application.name": "Volume Meter", "stream.monitor": true})),
+            node(54, "Stream/Input/Video", "running", json!({"application.name": "Chromium"})),
+            node(55, "Stream/Input/Video", "running", json!({"application.process.binary": "zoom"})),
+            {"id": 1, "type": "PipeWire:Interface:Core", "info": {}},
+            link(40, 50),
+            link(41, 51),
+            link(40, 52),
+            link(40, 53),
+            link(42, 54),
+            link(43, 55),
+        ]);
+        let streams = find_streams(dump.as_array().unwrap());
+        assert_eq!(
+            streams.microphone.into_iter().collect::<Vec<_>>(),
+            vec!["Firefox"]
+        );
+        assert_eq!(
+            streams.screencast.into_iter().collect::<Vec<_>>(),
+            vec!["Chromium"]
+        );
+        assert_eq!(streams.camera.into_iter().collect::<Vec<_>>(), vec!["zoom"]);
+        assert!(streams.screencast_active);
+        assert!(streams.camera_active);
+
+        // An idle camera stream doesn't count
+        let idle = json!([
+            node(
+                43,
+                "Video/Source",
+                "suspended",
+                json!({"device.api": "v4l2"})
+            ),
+            node(
+                55,
+                "Stream/Input/Video",
+                "idle",
+                json!({"application.name": "zoom"})
+            ),
+            link(43, 55),
+        ]);
+        assert!(!find_streams(idle.as_array().unwrap()).camera_active);
+    }
+
+    #[test]
+    fn test_apply_update() {
+        let mut graph = HashMap::new();
+        apply_update(
+            &mut graph,
+            json!([
+                node(40, "Audio/Source", "running", json!({})),
+                node(50, "Stream/Input/Audio", "running", json!({"application.name": "Firefox"})),
+                link(40, 50),
+                {"id": 30, "type": "PipeWire:Interface:Metadata", "metadata": []},
+            ]),
+        );
+        assert_eq!(find_streams(graph.values()).microphone.len(), 1);
+
+        // The stream pauses, then goes away with its link
+        apply_update(
+            &mut graph,
+            json!([node(50, "Stream/Input/Audio", "idle", json!({}))]),
+        );
+        assert!(find_streams(graph.values()).microphone.is_empty());
+        apply_update(
+            &mut graph,
+            json!([{"id": 50, "info": null}, {"id": 1050, "info": null}]),
+        );
+        assert_eq!(graph.len(), 2);
+        assert!(graph.contains_key(&30));
+    }
+
+    #[test]
+    fn test_video_users() {
+        let proc = std::env::temp_dir().join(format!("terra-privacy-{}", std::process::id()));
+        for (pid, comm, target) in [
+            ("100", "firefox", "/dev/video0"),
+            ("101", "kitty", "/dev/pts/1"),
+            ("102", "wireplumber", "/dev/video2"),
+        ] {
+            let fd = proc.join(pid).join("fd");
+            fs::create_dir_all(&fd).unwrap();
+            std::os::unix::fs::symlink(target, fd.join("3")).unwrap();
+            fs::write(proc.join(pid).join("comm"), format!("{}\n", comm)).unwrap();
+        }
+        fs::create_dir_all(proc.join("self")).unwrap();
+
+        let users: Vec<String> = video_users(&proc).into_iter().collect();
+        assert_eq!(users, vec!["firefox", "wireplumber"]);
+
+        fs::remove_dir_all(&proc).unwrap();
+    }
+}
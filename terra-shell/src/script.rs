@@ -0,0 +1,256 @@
+//! External script module
+//!
+//! Runs user-configured commands and exposes their latest output under a
+//! named key. Commands either run on an interval (stdout is one value) or
+//! stay alive and emit one value per line. Output that parses as JSON is
+//! passed through as-is, anything else is exposed as a string.
+
+use std::process::Stdio;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::task::JoinSet;
+use tokio::time::{interval, timeout, Duration};
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::module::{self, current_timestamp, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// Delay before restarting a long-lived script that exited
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// How a script produces output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptMode {
+    /// Run every `interval` seconds, whole stdout is the value
+    #[default]
+    Interval,
+    /// Long-lived process, every stdout line is a new value
+    Watch,
+}
+
+/// A configured external command
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptConfig {
+    /// Key the output is stored under
+    pub name: String,
+    /// Command line, run through `sh -c`
+    pub command: String,
+    #[serde(default)]
+    pub mode: ScriptMode,
+    /// Seconds between runs in interval mode
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ScriptSettings {
+    scripts: Vec<ScriptConfig>,
+}
+
+/// Latest output of a script
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ScriptOutput {
+    pub value: Value,
+    /// Unix timestamp of the last update
+    pub updated: u64,
+    /// Last failure, cleared on the next successful run
+    pub error: Option<String>,
+}
+
+/// Custom data sources backed by external commands
+pub struct ScriptModule {
+    state: Arc<AppState>,
+    scripts: Vec<ScriptConfig>,
+}
+
+impl ScriptModule {
+    pub fn new(state: Arc<AppState>, config: &Config) -> Self {
+        let settings: ScriptSettings = config.section("custom");
+        Self {
+            state,
+            scripts: settings.scripts,
+        }
+    }
+
+    fn set_value(&self, name: &str, value: Value) {
+        self.state.custom.update(|outputs| {
+            outputs.insert(
+                name.to_string(),
+                ScriptOutput {
+                    value,
+                    updated: current_timestamp(),
+                    error: None,
+                },
+            );
+        });
+    }
+
+    fn set_error(&self, name: &str, error: String) {
+        warn!("Script {}: {}", name, error);
+        self.state.custom.update(|outputs| {
+            outputs.entry(name.to_string()).or_default().error = Some(error);
+        });
+    }
+
+    /// Run an interval script forever
+    async fn run_interval(&self, script: &ScriptConfig) {
+        let period = Duration::from_secs(script.interval.max(1));
+        let mut interval = interval(period);
+
+        loop {
+            interval.tick().await;
+
+            let output = shell(&script.command).stdout(Stdio::piped()).output();
+            match timeout(period, output).await {
+                Ok(Ok(out)) if out.status.success() => {
+                    let stdout = String::from_utf8_lossy(&out.stdout);
+                    debug!("Script {}: {}", script.name, stdout.trim());
+                    self.set_value(&script.name, parse_output(&stdout));
+                }
+                Ok(Ok(out)) => {
+                    self.set_error(&script.name, format!("exited with {}", out.status));
+                }
+                Ok(Err(e)) => self.set_error(&script.name, e.to_string()),
+                Err(_) => self.set_error(&script.name, "timed out".to_string()),
+            }
+        }
+    }
+
+    /// Keep a long-lived script running, restarting it when it exits
+    async fn run_watch(&self, script: &ScriptConfig) {
+        loop {
+            match shell(&script.command).stdout(Stdio::piped()).spawn() {
+                Ok(mut child) => {
+                    if let Some(stdout) = child.stdout.take() {
+                        let mut lines = BufReader::new(stdout).lines();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if !line.trim().is_empty() {
+                                self.set_value(&script.name, parse_output(&line));
+                            }
+                        }
+                    }
+                    let status = child.wait().await;
+                    self.set_error(&script.name, format!("exited: {:?}", status));
+                }
+                Err(e) => self.set_error(&script.name, e.to_string()),
+            }
+
+            tokio::time::sleep(RESTART_DELAY).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Module for ScriptModule {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["custom"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.custom.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        let outputs = self.state.custom.get();
+        let values: serde_json::Map<String, Value> = outputs
+            .iter()
+            .map(|(name, output)| (name.clone(), output.value.clone()))
+            .collect();
+        json!({"custom": values})
+    }
+
+    /// `custom` lists every script, `custom <name>` returns one
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        let outputs = self.state.custom.get();
+        match args.first() {
+            Some(name) => match outputs.get(*name) {
+                Some(output) => json!({
+                    "type": "custom",
+                    "name": name,
+                    "value": output.value,
+                    "updated": output.updated,
+                    "error": output.error
+                }),
+                None => module::error(format!("unknown script: {}", name)),
+            },
+            None => json!({"type": "custom", "scripts": outputs}),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        // Dropping the set on stop aborts every script task
+        let mut tasks = JoinSet::new();
+        for index in 0..self.scripts.len() {
+            let module = self.clone();
+            tasks.spawn(async move {
+                let script = &module.scripts[index];
+                match script.mode {
+                    ScriptMode::Interval => module.run_interval(script).await,
+                    ScriptMode::Watch => module.run_watch(script).await,
+                }
+            });
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+/// Build a `sh -c` command whose process dies with its task
+fn shell(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    cmd
+}
+
+/// Parse script output as JSON, falling back to a trimmed string
+fn parse_output(output: &str) -> Value {
+    let trimmed = output.trim();
+    serde_json::from_str(trimmed).unwrap_or_else(|_| Value::String(trimmed.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(parse_output("{\"on\": true}\n"), json!({"on": true}));
+        assert_eq!(parse_output("42\n"), json!(42));
+        assert_eq!(parse_output("  wg0 up \n"), json!("wg0 up"));
+    }
+
+    #[test]
+    fn test_script_config() {
+        let config: Config = serde_json::from_str(
+            r#"{"modules": {"custom": {"scripts": [
+                {"name": "vpn", "command": "vpn-status"},
+                {"name": "pomodoro", "command": "pomo watch", "mode": "watch"}
+            ]}}}"#,
+        )
+        .unwrap();
+        let module = ScriptModule::new(Arc::default(), &config);
+        assert_eq!(module.scripts.len(), 2);
+        assert_eq!(module.scripts[0].mode, ScriptMode::Interval);
+        assert_eq!(module.scripts[0].interval, 60);
+        assert_eq!(module.scripts[1].mode, ScriptMode::Watch);
+    }
+}
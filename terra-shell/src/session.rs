@@ -0,0 +1,333 @@
+//! Session actions: lock, suspend, hibernate, reboot, power off, logout
+//!
+//! Everything except `lock` is two-step: `session <action>` returns a
+//! short-lived token and only `session confirm <token>` performs the
+//! action, so a stray click or misrouted message can't power off the
+//! machine. Actions go through logind, logout exits Hyprland.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use async_trait::async_trait;
+use serde_json::{json, Value as Json};
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::Connection;
+
+use crate::hyprland;
+use crate::module::{self, ok, Module};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+const LOGIND_SESSION: &str = "org.freedesktop.login1.Session";
+
+/// How long a confirmation token stays valid
+const TOKEN_TTL: Duration = Duration::from_secs(15);
+
+/// A session action
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Lock,
+    Suspend,
+    Hibernate,
+    Reboot,
+    PowerOff,
+    Logout,
+}
+
+impl Action {
+    const ALL: [Action; 6] = [
+        Action::Lock,
+        Action::Suspend,
+        Action::Hibernate,
+        Action::Reboot,
+        Action::PowerOff,
+        Action::Logout,
+    ];
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Action::Lock => "lock",
+            Action::Suspend => "suspend",
+            Action::Hibernate => "hibernate",
+            Action::Reboot => "reboot",
+            Action::PowerOff => "poweroff",
+            Action::Logout => "logout",
+        }
+    }
+
+    /// logind `Can*` / action method suffix
+    fn logind_method(self) -> Option<&'static str> {
+        match self {
+            Action::Suspend => Some("Suspend"),
+            Action::Hibernate => Some("Hibernate"),
+            Action::Reboot => Some("Reboot"),
+            Action::PowerOff => Some("PowerOff"),
+            Action::Lock | Action::Logout => None,
+        }
+    }
+
+    fn needs_confirmation(self) -> bool {
+        self != Action::Lock
+    }
+}
+
+struct PendingAction {
+    action: Action,
+    expires_at: Instant,
+}
+
+/// Power menu backend
+pub struct SessionModule {
+    pending: Mutex<HashMap<String, PendingAction>>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SessionModule {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn system(&self) -> zbus::Result<Connection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(c) = connection.as_ref() {
+            return Ok(c.clone());
+        }
+        let c = Connection::system().await?;
+        *connection = Some(c.clone());
+        Ok(c)
+    }
+
+    /// logind answer for `Can<Action>`: yes, no, challenge or na
+    async fn can(&self, action: Action) -> String {
+        let Some(method) = action.logind_method() else {
+            // Locking and leaving Hyprland need no privileges
+            return "yes".to_string();
+        };
+        let Ok(system) = self.system().await else {
+            return "na".to_string();
+        };
+        system
+            .call_method(
+                Some(LOGIND),
+                LOGIND_PATH,
+                Some(LOGIND_MANAGER),
+                format!("Can{}", method).as_str(),
+                &(),
+            )
+            .await
+            .and_then(|reply| reply.body().deserialize::<String>())
+            .unwrap_or_else(|_| "na".to_string())
+    }
+
+    async fn perform(&self, action: Action) -> Result<(), String> {
+        info!("Session action: {}", action.name());
+        match action {
+            Action::Logout => {
+                if hyprland::dispatch("exit", "").await {
+                    Ok(())
+                } else {
+                    Err("hyprland exit failed".to_string())
+                }
+            }
+            Action::Lock => {
+                let system = self.system().await.map_err(|e| e.to_string())?;
+                let session: OwnedObjectPath = system
+                    .call_method(Some(LOGIND), LOGIND_PATH, Some(LOGIND_MANAGER), "GetSession", &("auto",))
+                    .await
+                    .and_then(|reply| reply.body().deserialize())
+                    .map_err(|e| e.to_string())?;
+                system
+                    .call_method(Some(LOGIND), &session, Some(LOGIND_SESSION), "Lock", &())
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            _ => {
+                let method = action.logind_method().unwrap_or_default();
+                let system = self.system().await.map_err(|e| e.to_string())?;
+                // interactive = false: never block on a polkit prompt
+                system
+                    .call_method(Some(LOGIND), LOGIND_PATH, Some(LOGIND_MANAGER), method, &(false,))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Issue a confirmation token for an action
+    async fn request(&self, action: Action) -> Json {
+        let token = new_token();
+        let mut pending = self.pending.lock().await;
+        let now = Instant::now();
+        pending.retain(|_, p| p.expires_at > now);
+        pending.insert(
+            token.clone(),
+            PendingAction {
+                action,
+                expires_at: now + TOKEN_TTL,
+            },
+        );
+        json!({
+            "type": "confirm",
+            "action": action.name(),
+            "token": token,
+            "expires_in": TOKEN_TTL.as_secs()
+        })
+    }
+
+    /// Consume a token and perform its action
+    async fn confirm(&self, token: &str) -> Json {
+        let pending = self.pending.lock().await.remove(token);
+        let Some(pending) = pending.filter(|p| p.expires_at > Instant::now()) else {
+            return module::error("invalid or expired token");
+        };
+        match self.perform(pending.action).await {
+            Ok(()) => ok(),
+            Err(e) => module::error(e),
+        }
+    }
+}
+
+impl Default for SessionModule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Module for SessionModule {
+    fn name(&self) -> &'static str {
+        "session"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &[
+            "session",
+            "can-lock",
+            "can-suspend",
+            "can-hibernate",
+            "can-reboot",
+            "can-poweroff",
+            "can-logout",
+        ]
+    }
+
+    async fn snapshot(&self) -> Json {
+        json!({})
+    }
+
+    /// `session` lists permissions, `session <action>` requests a token,
+    /// `session confirm <token>` performs, `session cancel`, `can-<action>`
+    async fn handle(&self, command: &str, args: &[&str]) -> Json {
+        if let Some(name) = command.strip_prefix("can-") {
+            return match Action::parse(name) {
+                Some(action) => json!({
+                    "type": "can",
+                    "action": action.name(),
+                    "result": self.can(action).await
+                }),
+                None => module::error(format!("unknown action: {}", name)),
+            };
+        }
+
+        match args.first().copied() {
+            None => {
+                let mut actions = serde_json::Map::new();
+                for action in Action::ALL {
+                    actions.insert(action.name().to_string(), json!(self.can(action).await));
+                }
+                json!({"type": "session", "actions": actions})
+            }
+            Some("confirm") => match args.get(1) {
+                Some(token) => self.confirm(token).await,
+                None => module::error("usage: session confirm <token>"),
+            },
+            Some("cancel") => {
+                self.pending.lock().await.clear();
+                ok()
+            }
+            Some(name) => match Action::parse(name) {
+                Some(action) if action.needs_confirmation() => self.request(action).await,
+                Some(action) => match self.perform(action).await {
+                    Ok(()) => ok(),
+                    Err(e) => module::error(e),
+                },
+                None => module::error(format!("unknown action: {}", name)),
+            },
+        }
+    }
+
+    async fn stop(&self) {
+        self.pending.lock().await.clear();
+        self.connection.lock().await.take();
+    }
+}
+
+/// Random hex token from the kernel RNG
+fn new_token() -> String {
+    let mut bytes = [0u8; 8];
+    if File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .is_err()
+    {
+        // Still unguessable enough for a UI confirmation step
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        bytes = (nanos ^ u64::from(std::process::id()).rotate_left(32)).to_le_bytes();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_parse() {
+        assert_eq!(Action::parse("poweroff"), Some(Action::PowerOff));
+        assert_eq!(Action::parse("shutdown"), None);
+        assert!(!Action::Lock.needs_confirmation());
+        assert!(Action::Reboot.needs_confirmation());
+    }
+
+    #[tokio::test]
+    async fn test_request_token() {
+        let session = SessionModule::new();
+        let reply = session.handle("session", &["reboot"]).await;
+        assert_eq!(reply["type"], "confirm");
+        assert_eq!(reply["action"], "reboot");
+        let token = reply["token"].as_str().unwrap();
+        assert_eq!(token.len(), 16);
+        assert!(session.pending.lock().await.contains_key(token));
+
+        let bogus = session.handle("session", &["confirm", "deadbeef"]).await;
+        assert_eq!(bogus["error"], "invalid or expired token");
+    }
+
+    #[tokio::test]
+    async fn test_token_expires() {
+        let session = SessionModule::new();
+        let reply = session.handle("session", &["poweroff"]).await;
+        let token = reply["token"].as_str().unwrap().to_string();
+        session.pending.lock().await.get_mut(&token).unwrap().expires_at = Instant::now();
+
+        let reply = session.handle("session", &["confirm", &token]).await;
+        assert_eq!(reply["error"], "invalid or expired token");
+        assert!(session.pending.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+//! Versioned per-domain state
+//!
+//! Each domain (audio, battery, Hyprland, ...) lives in its own
+//! [`Shared`] watch channel, so monitors publishing one domain never wait
+//! on readers of another and readers only copy what they need. Every
+//! change takes the next value of a process-wide sequence number, which
+//! lets clients ask for the domains that changed since a sequence they
+//! have already seen.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::module::current_timestamp_ms;
+
+/// Last sequence number handed out
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Latest sequence number of any domain
+pub fn current_seq() -> u64 {
+    SEQ.load(Ordering::SeqCst)
+}
+
+/// When a domain last changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Version {
+    /// 0 until the first value is published
+    pub seq: u64,
+    /// Unix milliseconds
+    pub updated: u64,
+}
+
+/// A domain value with its version
+#[derive(Debug, Clone, Default)]
+pub struct Versioned<T> {
+    pub version: Version,
+    pub value: T,
+}
+
+/// One domain of shared state
+pub struct Shared<T> {
+    tx: watch::Sender<Versioned<T>>,
+}
+
+impl<T: Clone + PartialEq> Shared<T> {
+    /// Copy of the current value
+    pub fn get(&self) -> T {
+        self.tx.borrow().value.clone()
+    }
+
+    /// Read part of the value without copying all of it
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.tx.borrow().value)
+    }
+
+    pub fn version(&self) -> Version {
+        self.tx.borrow().version
+    }
+
+    /// Modify the value, notifying subscribers if it changed. The first
+    /// update always counts, so an initial value equal to the default is
+    /// still published.
+    pub fn update(&self, f: impl FnOnce(&mut T)) -> bool {
+        self.tx.send_if_modified(|current| {
+            let before = current.value.clone();
+            f(&mut current.value);
+            if current.value == before && current.version.seq != 0 {
+                return false;
+            }
+            current.version = Version {
+                seq: SEQ.fetch_add(1, Ordering::SeqCst) + 1,
+                updated: current_timestamp_ms(),
+            };
+            true
+        })
+    }
+
+    /// Replace the value
+    pub fn set(&self, value: T) -> bool {
+        self.update(|current| *current = value)
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Versioned<T>> {
+        self.tx.subscribe()
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        let (tx, _) = watch::channel(Versioned::default());
+        Self { tx }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.tx.borrow().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versioning() {
+        let shared: Shared<u8> = Shared::default();
+        assert_eq!(shared.version().seq, 0);
+
+        // The first value counts even if it equals the default
+        assert!(shared.set(0));
+        let first = shared.version();
+        assert!(first.seq > 0);
+
+        assert!(!shared.set(0));
+        assert_eq!(shared.version(), first);
+
+        assert!(shared.update(|v| *v += 5));
+        assert_eq!(shared.get(), 5);
+        assert!(shared.version().seq > first.seq);
+        assert!(current_seq() >= shared.version().seq);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let shared: Shared<u8> = Shared::default();
+        let mut rx = shared.subscribe();
+        shared.set(3);
+        rx.changed().await.unwrap();
+        assert_eq!(rx.borrow_and_update().value, 3);
+    }
+}
@@ -0,0 +1,396 @@
+//! System resource monitor
+//!
+//! Reads CPU usage from `/proc/stat`, memory from `/proc/meminfo`,
+//! temperatures from hwmon and thermal zones, and disk usage via `statvfs`.
+
+use std::ffi::CString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::time::{interval, Duration};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::module::{self, Module};
+use crate::state::Version;
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct SystemSettings {
+    /// Seconds between samples
+    interval: u64,
+    /// Mount points to report disk usage for
+    mounts: Vec<String>,
+}
+
+impl Default for SystemSettings {
+    fn default() -> Self {
+        Self {
+            interval: 2,
+            mounts: vec!["/".to_string()],
+        }
+    }
+}
+
+/// Cumulative jiffies of one `/proc/stat` cpu line
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct CpuTimes {
+    idle: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    /// Busy percentage between two samples
+    fn usage_since(&self, prev: &CpuTimes) -> f32 {
+        let total = self.total.saturating_sub(prev.total);
+        let idle = self.idle.saturating_sub(prev.idle);
+        if total == 0 {
+            return 0.0;
+        }
+        round1((total - idle.min(total)) as f32 * 100.0 / total as f32)
+    }
+}
+
+/// CPU usage in percent
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CpuUsage {
+    pub total: f32,
+    pub cores: Vec<f32>,
+}
+
+/// Memory and swap usage in bytes
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MemoryUsage {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+    pub percent: f32,
+    pub swap_total: u64,
+    pub swap_used: u64,
+    pub swap_percent: f32,
+}
+
+/// A temperature sensor reading
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Temperature {
+    /// hwmon chip or thermal zone type
+    pub sensor: String,
+    pub label: String,
+    pub celsius: f32,
+}
+
+/// Filesystem usage of a mount point in bytes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DiskUsage {
+    pub mount: String,
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+    pub percent: f32,
+}
+
+/// Latest system resource sample, the system domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SystemStats {
+    pub cpu: CpuUsage,
+    pub memory: MemoryUsage,
+    pub temperatures: Vec<Temperature>,
+    pub disks: Vec<DiskUsage>,
+}
+
+/// CPU, memory, temperature and disk usage
+pub struct SystemModule {
+    state: Arc<AppState>,
+    settings: SystemSettings,
+}
+
+impl SystemModule {
+    pub fn new(state: Arc<AppState>, config: &Config) -> Self {
+        Self {
+            state,
+            settings: config.section("system"),
+        }
+    }
+}
+
+#[async_trait]
+impl Module for SystemModule {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["system"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.system.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"system": self.state.system.get()})
+    }
+
+    /// `system` returns everything, `system cpu|memory|temperatures|disks` one part
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        let stats = self.state.system.get();
+        match args.first().copied() {
+            None => json!({"type": "system", "stats": stats}),
+            Some("cpu") => json!({"type": "system", "cpu": stats.cpu}),
+            Some("memory") => json!({"type": "system", "memory": stats.memory}),
+            Some("temperatures") => json!({"type": "system", "temperatures": stats.temperatures}),
+            Some("disks") => json!({"type": "system", "disks": stats.disks}),
+            Some(other) => module::error(format!("unknown system query: {}", other)),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(self.settings.interval.max(1)));
+        let mut prev_cpu = read_cpu_times();
+
+        loop {
+            interval.tick().await;
+
+            let cpu_times = read_cpu_times();
+            let cpu = cpu_usage(&prev_cpu, &cpu_times);
+            prev_cpu = cpu_times;
+
+            let memory = fs::read_to_string("/proc/meminfo")
+                .map(|s| parse_meminfo(&s))
+                .unwrap_or_default();
+            let temperatures = read_temperatures();
+            let disks = self
+                .settings
+                .mounts
+                .iter()
+                .filter_map(|mount| disk_usage(mount))
+                .collect();
+
+            debug!("System: cpu {}%, memory {}%", cpu.total, memory.percent);
+            self.state.system.set(SystemStats {
+                cpu,
+                memory,
+                temperatures,
+                disks,
+            });
+        }
+    }
+}
+
+/// Read aggregate and per-core CPU times, aggregate first
+fn read_cpu_times() -> Vec<CpuTimes> {
+    fs::read_to_string("/proc/stat")
+        .map(|s| parse_cpu_times(&s))
+        .unwrap_or_default()
+}
+
+/// Parse `cpu` lines of `/proc/stat`
+fn parse_cpu_times(stat: &str) -> Vec<CpuTimes> {
+    stat.lines()
+        .filter(|line| line.starts_with("cpu"))
+        .filter_map(|line| {
+            let fields: Vec<u64> = line
+                .split_whitespace()
+                .skip(1)
+                .filter_map(|f| f.parse().ok())
+                .collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            // user nice system idle iowait irq softirq steal (guest is already in user)
+            let idle = fields[3] + fields.get(4).copied().unwrap_or(0);
+            let total = fields.iter().take(8).sum();
+            Some(CpuTimes { idle, total })
+        })
+        .collect()
+}
+
+/// Usage between two samples of [`read_cpu_times`]
+fn cpu_usage(prev: &[CpuTimes], current: &[CpuTimes]) -> CpuUsage {
+    let mut usage = current.iter().zip(prev).map(|(c, p)| c.usage_since(p));
+    CpuUsage {
+        total: usage.next().unwrap_or(0.0),
+        cores: usage.collect(),
+    }
+}
+
+/// Parse `/proc/meminfo` (values are in kB)
+fn parse_meminfo(meminfo: &str) -> MemoryUsage {
+    let field = |name: &str| -> u64 {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|rest| rest.split_whitespace().next()?.parse::<u64>().ok())
+            .unwrap_or(0)
+            * 1024
+    };
+
+    let total = field("MemTotal");
+    let available = field("MemAvailable");
+    let swap_total = field("SwapTotal");
+    let swap_free = field("SwapFree");
+    let used = total.saturating_sub(available);
+    let swap_used = swap_total.saturating_sub(swap_free);
+
+    MemoryUsage {
+        total,
+        used,
+        available,
+        percent: percent(used, total),
+        swap_total,
+        swap_used,
+        swap_percent: percent(swap_used, swap_total),
+    }
+}
+
+/// Read all hwmon sensors, falling back to thermal zones
+fn read_temperatures() -> Vec<Temperature> {
+    let mut temps = Vec::new();
+
+    for chip in read_dir_sorted(module::sysfs("class/hwmon")) {
+        let sensor = read_trimmed(&chip.join("name")).unwrap_or_default();
+        for entry in read_dir_sorted(&chip) {
+            let Some(file) = entry.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(index) = file
+                .strip_prefix("temp")
+                .and_then(|f| f.strip_suffix("_input"))
+            else {
+                continue;
+            };
+            let Some(millis) = read_trimmed(&entry).and_then(|t| t.parse::<i64>().ok()) else {
+                continue;
+            };
+            let label = read_trimmed(&chip.join(format!("temp{}_label", index)))
+                .unwrap_or_else(|| format!("temp{}", index));
+            temps.push(Temperature {
+                sensor: sensor.clone(),
+                label,
+                celsius: round1(millis as f32 / 1000.0),
+            });
+        }
+    }
+
+    if temps.is_empty() {
+        for zone in read_dir_sorted(module::sysfs("class/thermal")) {
+            let Some(millis) = read_trimmed(&zone.join("temp")).and_then(|t| t.parse::<i64>().ok())
+            else {
+                continue;
+            };
+            let label = zone
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            temps.push(Temperature {
+                sensor: read_trimmed(&zone.join("type")).unwrap_or_default(),
+                label,
+                celsius: round1(millis as f32 / 1000.0),
+            });
+        }
+    }
+
+    temps
+}
+
+/// Filesystem usage via statvfs
+pub fn disk_usage(mount: &str) -> Option<DiskUsage> {
+    let path = CString::new(mount).ok()?;
+    // SAFETY: statvfs is plain old data, so all zeroes is a valid value
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is a NUL-terminated string that outlives the call and
+    // `stat` is a valid, exclusively borrowed struct for it to fill in
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let block = stat.f_frsize as u64;
+    let total = stat.f_blocks as u64 * block;
+    let free = stat.f_bfree as u64 * block;
+    let available = stat.f_bavail as u64 * block;
+    let used = total.saturating_sub(free);
+
+    Some(DiskUsage {
+        mount: mount.to_string(),
+        total,
+        used,
+        available,
+        // Matches df: used / (used + available to unprivileged users)
+        percent: percent(used, used + available),
+    })
+}
+
+fn read_dir_sorted(dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map(|rd| rd.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn percent(part: u64, whole: u64) -> f32 {
+    if whole == 0 {
+        return 0.0;
+    }
+    round1(part as f32 * 100.0 / whole as f32)
+}
+
+fn round1(value: f32) -> f32 {
+    (value * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_usage() {
+        let prev = parse_cpu_times(
+            "cpu  100 0 100 800 0 0 0 0 0 0\n\
+             cpu0 50 0 50 400 0 0 0 0 0 0\n\
+             cpu1 50 0 50 400 0 0 0 0 0 0\n\
+             intr 12345",
+        );
+        let current = parse_cpu_times(
+            "cpu  150 0 150 900 0 0 0 0 0 0\n\
+             cpu0 100 0 100 400 0 0 0 0 0 0\n\
+             cpu1 50 0 50 500 0 0 0 0 0 0",
+        );
+        assert_eq!(prev.len(), 3);
+
+        let usage = cpu_usage(&prev, &current);
+        assert_eq!(usage.total, 50.0);
+        assert_eq!(usage.cores, vec![100.0, 0.0]);
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let memory = parse_meminfo(
+            "MemTotal:       16000000 kB\n\
+             MemFree:         2000000 kB\n\
+             MemAvailable:    4000000 kB\n\
+             SwapTotal:       8000000 kB\n\
+             SwapFree:        6000000 kB",
+        );
+        assert_eq!(memory.total, 16_000_000 * 1024);
+        assert_eq!(memory.used, 12_000_000 * 1024);
+        assert_eq!(memory.percent, 75.0);
+        assert_eq!(memory.swap_percent, 25.0);
+    }
+
+    #[test]
+    fn test_disk_usage_root() {
+        let disk = disk_usage("/").unwrap();
+        assert!(disk.total >= disk.used);
+        assert!(disk_usage("/nonexistent/mount").is_none());
+    }
+}
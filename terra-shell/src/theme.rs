@@ -0,0 +1,345 @@
+//! Wallpaper and colour scheme
+//!
+//! `wallpaper set` hands the image to swww, regenerates the colour scheme
+//! with pywal or matugen, runs the configured reload hooks and broadcasts
+//! `theme-changed` with the new palette. With pywal the palette is read
+//! from `~/.cache/wal/colors.json`, which is also watched so running `wal`
+//! by hand re-themes clients too; with any other generator that file is
+//! left alone.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::{self, Module};
+use crate::state::Version;
+use crate::AppState;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ThemeSettings {
+    /// `wal`, `matugen` or `none`
+    generator: String,
+    /// Extra arguments for `swww img`
+    swww_args: Vec<String>,
+    /// Extra arguments for the generator
+    generator_args: Vec<String>,
+    /// Shell commands run after the scheme is regenerated
+    hooks: Vec<String>,
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        Self {
+            generator: "wal".to_string(),
+            swww_args: vec!["--transition-type".to_string(), "grow".to_string()],
+            generator_args: Vec::new(),
+            hooks: Vec::new(),
+        }
+    }
+}
+
+/// Theme domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ThemeState {
+    pub palette: Option<Palette>,
+    /// Wallpaper per monitor, `*` when set on all of them
+    pub wallpapers: BTreeMap<String, String>,
+}
+
+/// Current colour scheme
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Palette {
+    pub wallpaper: String,
+    pub background: String,
+    pub foreground: String,
+    pub cursor: String,
+    /// `color0` to `color15` (pywal)
+    pub colors: Vec<String>,
+    /// Named colours (matugen material roles)
+    pub named: BTreeMap<String, String>,
+}
+
+/// Wallpaper setter and palette publisher
+pub struct ThemeModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    settings: ThemeSettings,
+    /// Modification time of colors.json last read
+    seen: Mutex<Option<SystemTime>>,
+}
+
+impl ThemeModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        Self {
+            state,
+            events,
+            settings: config.section("theme"),
+            seen: Mutex::new(None),
+        }
+    }
+
+    /// Store and broadcast a palette
+    async fn publish(&self, palette: Palette) {
+        let event = json!({"palette": palette});
+        self.state.theme.update(|s| s.palette = Some(palette));
+        self.events.emit("theme-changed", event);
+    }
+
+    /// Re-read colors.json if it changed since last time
+    async fn reload_wal(&self, force: bool) {
+        // It would replace matugen's palette with a stale one
+        if self.settings.generator != "wal" {
+            return;
+        }
+        let Some(path) = wal_colors_path() else {
+            return;
+        };
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        {
+            let mut seen = self.seen.lock().await;
+            if !force && *seen == modified {
+                return;
+            }
+            *seen = modified;
+        }
+        let Some(palette) = fs::read_to_string(&path).ok().and_then(|s| parse_wal(&s)) else {
+            return;
+        };
+        if self.state.theme.read(|s| s.palette.as_ref() != Some(&palette)) {
+            info!("Palette changed ({})", palette.wallpaper);
+            self.publish(palette).await;
+        }
+    }
+
+    async fn set_wallpaper(&self, path: &str, monitor: Option<&str>) -> Result<(), String> {
+        let path = fs::canonicalize(path).map_err(|e| format!("{}: {}", path, e))?;
+        let path = path.to_string_lossy().into_owned();
+
+        let mut swww = Command::new("swww");
+        swww.arg("img").args(&self.settings.swww_args);
+        if let Some(monitor) = monitor {
+            swww.args(["--outputs", monitor]);
+        }
+        run(swww.arg(&path)).await?;
+        self.state.theme.update(|s| {
+            if monitor.is_none() {
+                s.wallpapers.clear();
+            }
+            s.wallpapers.insert(monitor.unwrap_or("*").to_string(), path.clone());
+        });
+        info!("Wallpaper set to {}", path);
+
+        match self.settings.generator.as_str() {
+            "wal" => {
+                let mut wal = Command::new("wal");
+                // -n: swww already set the wallpaper
+                wal.args(["-n", "-q", "-i", &path]).args(&self.settings.generator_args);
+                run(&mut wal).await?;
+                self.reload_wal(true).await;
+            }
+            "matugen" => {
+                let output = Command::new("matugen")
+                    .args(["image", &path, "--json", "hex"])
+                    .args(&self.settings.generator_args)
+                    .output()
+                    .await
+                    .map_err(|e| format!("matugen failed: {}", e))?;
+                if !output.status.success() {
+                    return Err(format!("matugen exited with {}", output.status));
+                }
+                let json = String::from_utf8_lossy(&output.stdout);
+                let palette = parse_matugen(&json, &path).ok_or("unreadable matugen output")?;
+                self.publish(palette).await;
+            }
+            _ => {}
+        }
+
+        for hook in &self.settings.hooks {
+            if let Err(e) = run(Command::new("sh").args(["-c", hook])).await {
+                warn!("Theme hook {} failed: {}", hook, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Module for ThemeModule {
+    fn name(&self) -> &'static str {
+        "theme"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["theme", "wallpaper"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.theme.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"theme": self.state.theme.get()})
+    }
+
+    /// `theme [reload]`, `wallpaper [set <path> [monitor]]`
+    async fn handle(&self, command: &str, args: &[&str]) -> Value {
+        if command == "theme" {
+            self.reload_wal(args.first() == Some(&"reload")).await;
+            return json!({"type": "theme", "palette": self.state.theme.read(|s| s.palette.clone())});
+        }
+
+        match args {
+            [] => {
+                json!({"type": "wallpaper", "wallpapers": self.state.theme.read(|s| s.wallpapers.clone())})
+            }
+            ["set", path, rest @ ..] => match self.set_wallpaper(path, rest.first().copied()).await {
+                Ok(()) => {
+                    let s = self.state.theme.get();
+                    json!({"type": "theme", "palette": s.palette, "wallpapers": s.wallpapers})
+                }
+                Err(e) => module::error(e),
+            },
+            _ => module::error("usage: wallpaper [set <path> [monitor]]"),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        if let Ok(output) = Command::new("swww").arg("query").output().await {
+            let wallpapers = parse_swww_query(&String::from_utf8_lossy(&output.stdout));
+            self.state.theme.update(|s| s.wallpapers = wallpapers);
+        }
+
+        let mut interval = interval(Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            self.reload_wal(false).await;
+        }
+    }
+}
+
+/// Run a command, failing on a non-zero exit
+async fn run(command: &mut Command) -> Result<(), String> {
+    let program = command.as_std().get_program().to_string_lossy().into_owned();
+    let status = command
+        .status()
+        .await
+        .map_err(|e| format!("{} failed: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+fn wal_colors_path() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("wal").join("colors.json"))
+}
+
+/// Parse pywal's colors.json
+fn parse_wal(json: &str) -> Option<Palette> {
+    #[derive(Deserialize)]
+    struct Wal {
+        #[serde(default)]
+        wallpaper: String,
+        special: BTreeMap<String, String>,
+        colors: BTreeMap<String, String>,
+    }
+
+    let wal: Wal = serde_json::from_str(json).ok()?;
+    let special = |name: &str| wal.special.get(name).cloned().unwrap_or_default();
+    let colors = (0..16)
+        .map_while(|i| wal.colors.get(&format!("color{}", i)).cloned())
+        .collect();
+    Some(Palette {
+        wallpaper: wal.wallpaper.clone(),
+        background: special("background"),
+        foreground: special("foreground"),
+        cursor: special("cursor"),
+        colors,
+        named: BTreeMap::new(),
+    })
+}
+
+/// Parse `matugen --json hex`, using the dark scheme
+fn parse_matugen(json: &str, wallpaper: &str) -> Option<Palette> {
+    let value: Value = serde_json::from_str(json).ok()?;
+    let named: BTreeMap<String, String> = value["colors"]
+        .as_object()?
+        .iter()
+        .filter_map(|(name, modes)| Some((name.clone(), modes["dark"].as_str()?.to_string())))
+        .collect();
+    let get = |name: &str| named.get(name).cloned().unwrap_or_default();
+    Some(Palette {
+        wallpaper: wallpaper.to_string(),
+        background: get("background"),
+        foreground: get("on_background"),
+        cursor: get("primary"),
+        colors: Vec::new(),
+        named,
+    })
+}
+
+/// Monitor to image from `swww query`
+fn parse_swww_query(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (monitor, rest) = line.trim_start_matches(": ").split_once(':')?;
+            let (_, image) = rest.split_once("image: ")?;
+            Some((monitor.trim().to_string(), image.trim().to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wal() {
+        let json = r##"{
+            "wallpaper": "/home/me/wall.png",
+            "alpha": "100",
+            "special": {"background": "#0f1419", "foreground": "#c5c8c6", "cursor": "#c5c8c6"},
+            "colors": {"color0": "#0f1419", "color1": "#a54242", "color2": "#8c9440"}
+        }"##;
+        let palette = parse_wal(json).unwrap();
+        assert_eq!(palette.wallpaper, "/home/me/wall.png");
+        assert_eq!(palette.background, "#0f1419");
+        assert_eq!(palette.colors, vec!["#0f1419", "#a54242", "#8c9440"]);
+    }
+
+    #[test]
+    fn test_parse_matugen() {
+        let json = r##"{"colors": {
+            "background": {"dark": "#111318", "light": "#f9f9ff"},
+            "on_background": {"dark": "#e2e2e9", "light": "#191c20"},
+            "primary": {"dark": "#aac7ff", "light": "#415f91"}
+        }}"##;
+        let palette = parse_matugen(json, "/w.png").unwrap();
+        assert_eq!(palette.background, "#111318");
+        assert_eq!(palette.foreground, "#e2e2e9");
+        assert_eq!(palette.named["primary"], "#aac7ff");
+    }
+
+    #[test]
+    fn test_parse_swww_query() {
+        let output = "DP-1: 2560x1440, scale: 1, currently displaying: image: /w/a.png\n\
+                      HDMI-A-1: 1920x1080, scale: 1, currently displaying: color: 000000\n";
+        let wallpapers = parse_swww_query(output);
+        assert_eq!(wallpapers.len(), 1);
+        assert_eq!(wallpapers["DP-1"], "/w/a.png");
+    }
+}
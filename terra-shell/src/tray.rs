@@ -0,0 +1,722 @@
+//! System tray (StatusNotifierItem) host
+//!
+//! Owns `org.kde.StatusNotifierWatcher`, registers itself as a host and
+//! tracks every registered item. Icon pixmaps are converted to PNG files
+//! in the cache directory so QML can load them by path, and removed again
+//! once no item shows them. Context menus are
+//! read and clicked through `com.canonical.dbusmenu`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::Serialize;
+use serde_json::{json, Value as Json};
+use tokio::sync::{mpsc, Mutex};
+use tracing::{debug, info, warn};
+use zbus::message::{Header, Type as MessageType};
+use zbus::names::BusName;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{fdo, interface, proxy, Connection, MatchRule, MessageStream};
+
+use crate::dbus::{self, prop, prop_string};
+use crate::module::{self, ok, Module};
+use crate::state::Version;
+use crate::AppState;
+
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &str = "/StatusNotifierWatcher";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const DEFAULT_ITEM_PATH: &str = "/StatusNotifierItem";
+
+/// Icon pixmap as sent over D-Bus: width, height, ARGB32 big-endian data
+type Pixmap = (i32, i32, Vec<u8>);
+
+/// dbusmenu layout node: id, properties, children (variants of nodes)
+type MenuNode = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+#[proxy(interface = "org.kde.StatusNotifierItem", assume_defaults = false)]
+trait StatusNotifierItem {
+    fn activate(&self, x: i32, y: i32) -> zbus::Result<()>;
+
+    fn secondary_activate(&self, x: i32, y: i32) -> zbus::Result<()>;
+
+    fn scroll(&self, delta: i32, orientation: &str) -> zbus::Result<()>;
+}
+
+#[proxy(interface = "com.canonical.dbusmenu", assume_defaults = false)]
+trait DBusMenu {
+    fn get_layout(
+        &self,
+        parent_id: i32,
+        recursion_depth: i32,
+        property_names: &[&str],
+    ) -> zbus::Result<(u32, MenuNode)>;
+
+    fn event(&self, id: i32, event_id: &str, data: &Value<'_>, timestamp: u32) -> zbus::Result<()>;
+
+    fn about_to_show(&self, id: i32) -> zbus::Result<bool>;
+}
+
+/// Tooltip of a tray item
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ToolTip {
+    pub title: String,
+    pub body: String,
+    /// Cached PNG of the tooltip icon pixmap
+    pub icon: Option<String>,
+}
+
+/// A registered tray item, the tray domain of [`AppState`] is a list of these
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrayItem {
+    /// Registration key: bus name followed by object path
+    pub id: String,
+    pub app_id: String,
+    pub title: String,
+    pub category: String,
+    /// Passive, Active or NeedsAttention
+    pub status: String,
+    pub icon_name: String,
+    pub attention_icon_name: String,
+    /// Extra directory to look up `icon_name` in
+    pub icon_theme_path: String,
+    /// Cached PNG of the largest icon pixmap
+    pub icon_pixmap: Option<String>,
+    pub attention_icon_pixmap: Option<String>,
+    pub tooltip: ToolTip,
+    /// Item only supports showing its menu, not activation
+    pub item_is_menu: bool,
+    pub has_menu: bool,
+    #[serde(skip)]
+    bus: String,
+    #[serde(skip)]
+    path: String,
+    /// Unique bus name of the item's connection, to match its signals
+    #[serde(skip)]
+    owner: String,
+    #[serde(skip)]
+    menu: Option<OwnedObjectPath>,
+}
+
+/// Sent from the watcher interface to the host loop
+enum WatcherEvent {
+    Registered { bus: String, path: String },
+}
+
+/// `org.kde.StatusNotifierWatcher` implementation
+struct Watcher {
+    state: Arc<AppState>,
+    events: mpsc::UnboundedSender<WatcherEvent>,
+}
+
+#[interface(name = "org.kde.StatusNotifierWatcher")]
+impl Watcher {
+    async fn register_status_notifier_item(
+        &self,
+        service: &str,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<()> {
+        let sender = header
+            .sender()
+            .map(|s| s.to_string())
+            .ok_or_else(|| fdo::Error::InvalidArgs("missing sender".to_string()))?;
+
+        // Ayatana items register an object path, KDE items a bus name
+        let (bus, path) = if service.starts_with('/') {
+            (sender, service.to_string())
+        } else {
+            (service.to_string(), DEFAULT_ITEM_PATH.to_string())
+        };
+        debug!("Tray item registered: {}{}", bus, path);
+        let _ = self.events.send(WatcherEvent::Registered { bus, path });
+        Ok(())
+    }
+
+    async fn register_status_notifier_host(
+        &self,
+        _service: &str,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        Self::status_notifier_host_registered(&emitter).await?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    async fn registered_status_notifier_items(&self) -> Vec<String> {
+        self.state.tray.read(|items| items.iter().map(|i| i.id.clone()).collect())
+    }
+
+    #[zbus(property)]
+    fn is_status_notifier_host_registered(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn protocol_version(&self) -> i32 {
+        0
+    }
+
+    #[zbus(signal)]
+    async fn status_notifier_item_registered(
+        emitter: &SignalEmitter<'_>,
+        service: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_item_unregistered(
+        emitter: &SignalEmitter<'_>,
+        service: &str,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn status_notifier_host_registered(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+}
+
+/// System tray host module
+pub struct TrayModule {
+    state: Arc<AppState>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl TrayModule {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self {
+            state,
+            connection: Mutex::new(None),
+        }
+    }
+
+    async fn connection(&self) -> Option<Connection> {
+        self.connection.lock().await.clone()
+    }
+
+    /// Find an item by its registration key
+    async fn item(&self, id: &str) -> Option<TrayItem> {
+        self.state.tray.read(|items| items.iter().find(|i| i.id == id).cloned())
+    }
+
+    /// Fetch an item's properties and add or update it
+    async fn refresh(&self, connection: &Connection, bus: &str, path: &str) -> zbus::Result<()> {
+        let owner = if bus.starts_with(':') {
+            bus.to_string()
+        } else {
+            let dbus = fdo::DBusProxy::new(connection).await?;
+            dbus.get_name_owner(BusName::try_from(bus)?).await?.to_string()
+        };
+
+        let props = fdo::PropertiesProxy::builder(connection)
+            .destination(bus)?
+            .path(path)?
+            .build()
+            .await?
+            .get_all(ITEM_INTERFACE.try_into()?)
+            .await?;
+
+        // Encoding pixmaps as PNG is too slow for the runtime threads
+        let (bus, path) = (bus.to_string(), path.to_string());
+        let item = tokio::task::spawn_blocking(move || parse_item(&bus, &path, owner, &props))
+            .await
+            .map_err(|e| zbus::Error::Failure(e.to_string()))?;
+        let changed = self.state.tray.update(|items| {
+            match items.iter_mut().find(|i| i.id == item.id) {
+                Some(existing) => *existing = item,
+                None => {
+                    info!("Tray item added: {} ({})", item.id, item.app_id);
+                    items.push(item);
+                }
+            }
+        });
+        if changed {
+            self.prune_icons().await;
+        }
+        Ok(())
+    }
+
+    /// Delete cached PNGs no current item refers to
+    async fn prune_icons(&self) {
+        let keep: HashSet<String> = self.state.tray.read(|items| {
+            items
+                .iter()
+                .flat_map(|i| [&i.icon_pixmap, &i.attention_icon_pixmap, &i.tooltip.icon])
+                .flatten()
+                .cloned()
+                .collect()
+        });
+        let _ = tokio::task::spawn_blocking(move || prune_cache(&keep)).await;
+    }
+
+    fn emitter(connection: &Connection) -> Option<SignalEmitter<'static>> {
+        SignalEmitter::new(connection, WATCHER_PATH).ok()
+    }
+
+    async fn connect(&self, events: mpsc::UnboundedSender<WatcherEvent>) -> zbus::Result<Connection> {
+        let watcher = Watcher {
+            state: self.state.clone(),
+            events,
+        };
+        let connection = zbus::connection::Builder::session()?
+            .name(WATCHER_NAME)?
+            .serve_at(WATCHER_PATH, watcher)?
+            .build()
+            .await?;
+        let host = format!("org.kde.StatusNotifierHost-{}", std::process::id());
+        connection.request_name(host).await?;
+        Ok(connection)
+    }
+
+    /// Handle a registration from the watcher interface
+    async fn on_registered(&self, connection: &Connection, bus: String, path: String) {
+        let id = format!("{}{}", bus, path);
+        if let Err(e) = self.refresh(connection, &bus, &path).await {
+            warn!("Failed to read tray item {}: {}", id, e);
+            return;
+        }
+        if let Some(emitter) = Self::emitter(connection) {
+            let _ = Watcher::status_notifier_item_registered(&emitter, &id).await;
+        }
+    }
+
+    /// Drop every item owned by a connection that left the bus
+    async fn on_name_lost(&self, connection: &Connection, name: &str) {
+        let mut removed = Vec::new();
+        self.state.tray.update(|items| {
+            removed = items
+                .iter()
+                .filter(|i| i.owner == name || i.bus == name)
+                .map(|i| i.id.clone())
+                .collect();
+            items.retain(|i| i.owner != name && i.bus != name);
+        });
+        if !removed.is_empty() {
+            self.prune_icons().await;
+        }
+        let Some(emitter) = Self::emitter(connection) else {
+            return;
+        };
+        for id in removed {
+            info!("Tray item removed: {}", id);
+            let _ = Watcher::status_notifier_item_unregistered(&emitter, &id).await;
+        }
+    }
+
+    /// Refetch items after a NewIcon/NewTitle/NewStatus/... signal
+    async fn on_item_signal(&self, connection: &Connection, sender: &str, path: &str) {
+        let targets: Vec<(String, String)> = self.state.tray.read(|items| {
+            items
+                .iter()
+                .filter(|i| i.owner == sender && i.path == path)
+                .map(|i| (i.bus.clone(), i.path.clone()))
+                .collect()
+        });
+        for (bus, path) in targets {
+            if let Err(e) = self.refresh(connection, &bus, &path).await {
+                debug!("Failed to refresh tray item {}{}: {}", bus, path, e);
+            }
+        }
+    }
+
+    async fn item_proxy(&self, id: &str) -> Result<StatusNotifierItemProxy<'static>, Json> {
+        let item = self.item(id).await.ok_or_else(|| module::error("unknown tray item"))?;
+        let connection = self.connection().await.ok_or_else(|| module::error("tray not running"))?;
+        let builder = StatusNotifierItemProxy::builder(&connection)
+            .destination(item.bus)
+            .and_then(|b| b.path(item.path))
+            .map_err(|e| module::error(e.to_string()))?;
+        builder.build().await.map_err(|e| module::error(e.to_string()))
+    }
+
+    async fn menu_proxy(&self, id: &str) -> Result<DBusMenuProxy<'static>, Json> {
+        let item = self.item(id).await.ok_or_else(|| module::error("unknown tray item"))?;
+        let menu = item.menu.ok_or_else(|| module::error("tray item has no menu"))?;
+        let connection = self.connection().await.ok_or_else(|| module::error("tray not running"))?;
+        let builder = DBusMenuProxy::builder(&connection)
+            .destination(item.bus)
+            .and_then(|b| b.path(menu))
+            .map_err(|e| module::error(e.to_string()))?;
+        builder.build().await.map_err(|e| module::error(e.to_string()))
+    }
+
+    /// Run an item method and turn the result into a reply
+    async fn call(&self, id: &str, method: TrayCall) -> Json {
+        let proxy = match self.item_proxy(id).await {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        let result = match method {
+            TrayCall::Activate(x, y) => proxy.activate(x, y).await,
+            TrayCall::SecondaryActivate(x, y) => proxy.secondary_activate(x, y).await,
+            TrayCall::Scroll(delta, orientation) => proxy.scroll(delta, orientation).await,
+        };
+        match result {
+            Ok(()) => ok(),
+            Err(e) => module::error(e.to_string()),
+        }
+    }
+}
+
+enum TrayCall {
+    Activate(i32, i32),
+    SecondaryActivate(i32, i32),
+    Scroll(i32, &'static str),
+}
+
+#[async_trait]
+impl Module for TrayModule {
+    fn name(&self) -> &'static str {
+        "tray"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["tray"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.tray.version())
+    }
+
+    async fn snapshot(&self) -> Json {
+        json!({"tray": self.state.tray.get()})
+    }
+
+    /// `tray`, `tray activate|secondary <id> [x y]`, `tray scroll <id> <delta> [horizontal]`,
+    /// `tray menu <id>`, `tray click <id> <menu-item>`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Json {
+        let position = |i: usize| -> (i32, i32) {
+            let x = args.get(i).and_then(|v| v.parse().ok()).unwrap_or(0);
+            let y = args.get(i + 1).and_then(|v| v.parse().ok()).unwrap_or(0);
+            (x, y)
+        };
+
+        match (args.first().copied(), args.get(1).copied()) {
+            (None, _) | (Some("list"), _) => {
+                json!({"type": "tray", "items": self.state.tray.get()})
+            }
+            (Some("activate"), Some(id)) => {
+                let (x, y) = position(2);
+                self.call(id, TrayCall::Activate(x, y)).await
+            }
+            (Some("secondary"), Some(id)) => {
+                let (x, y) = position(2);
+                self.call(id, TrayCall::SecondaryActivate(x, y)).await
+            }
+            (Some("scroll"), Some(id)) => {
+                let Some(delta) = args.get(2).and_then(|d| d.parse().ok()) else {
+                    return module::error("usage: tray scroll <id> <delta> [horizontal]");
+                };
+                let orientation = match args.get(3).copied() {
+                    Some("horizontal") => "horizontal",
+                    _ => "vertical",
+                };
+                self.call(id, TrayCall::Scroll(delta, orientation)).await
+            }
+            (Some("menu"), Some(id)) => {
+                let proxy = match self.menu_proxy(id).await {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                let _ = proxy.about_to_show(0).await;
+                match proxy.get_layout(0, -1, &[]).await {
+                    Ok((revision, root)) => json!({
+                        "type": "tray-menu",
+                        "id": id,
+                        "revision": revision,
+                        "menu": menu_to_json(root)
+                    }),
+                    Err(e) => module::error(e.to_string()),
+                }
+            }
+            (Some("click"), Some(id)) => {
+                let Some(item) = args.get(2).and_then(|i| i.parse::<i32>().ok()) else {
+                    return module::error("usage: tray click <id> <menu-item>");
+                };
+                let proxy = match self.menu_proxy(id).await {
+                    Ok(p) => p,
+                    Err(e) => return e,
+                };
+                match proxy.event(item, "clicked", &Value::I32(0), 0).await {
+                    Ok(()) => ok(),
+                    Err(e) => module::error(e.to_string()),
+                }
+            }
+            _ => module::error("usage: tray [activate|secondary|scroll|menu|click] <id> ..."),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let connection = match self.connect(tx).await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Cannot own {} (another tray running?): {}", WATCHER_NAME, e);
+                return;
+            }
+        };
+        info!("Serving {} on the session bus", WATCHER_NAME);
+        *self.connection.lock().await = Some(connection.clone());
+
+        let mut names = match fdo::DBusProxy::new(&connection).await {
+            Ok(dbus) => match dbus.receive_name_owner_changed().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to watch bus names: {}", e);
+                    return;
+                }
+            },
+            Err(e) => {
+                warn!("Failed to watch bus names: {}", e);
+                return;
+            }
+        };
+
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface(ITEM_INTERFACE)
+            .map(|b| b.build());
+        let mut signals = match rule {
+            Ok(rule) => match MessageStream::for_match_rule(rule, &connection, None).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to watch tray item signals: {}", e);
+                    return;
+                }
+            },
+            Err(e) => {
+                warn!("Invalid match rule: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                Some(event) = rx.recv() => match event {
+                    WatcherEvent::Registered { bus, path } => {
+                        self.on_registered(&connection, bus, path).await;
+                    }
+                },
+                Some(signal) = names.next() => {
+                    let Ok(args) = signal.args() else { continue };
+                    if args.new_owner().is_none() {
+                        self.on_name_lost(&connection, args.name().as_str()).await;
+                    }
+                },
+                Some(Ok(message)) = signals.next() => {
+                    let header = message.header();
+                    if let (Some(sender), Some(path)) = (header.sender(), header.path()) {
+                        self.on_item_signal(&connection, sender.as_str(), path.as_str()).await;
+                    }
+                },
+                else => break,
+            }
+        }
+    }
+
+    /// Drop the connection so the watcher name is released
+    async fn stop(&self) {
+        self.connection.lock().await.take();
+        self.state.tray.update(Vec::clear);
+    }
+}
+
+/// Build a tray item from its `GetAll` properties
+fn parse_item(bus: &str, path: &str, owner: String, props: &HashMap<String, OwnedValue>) -> TrayItem {
+    let pixmaps = |name: &str| -> Vec<Pixmap> {
+        props
+            .get(name)
+            .and_then(|v| v.try_clone().ok())
+            .and_then(|v| Vec::<Pixmap>::try_from(v).ok())
+            .unwrap_or_default()
+    };
+
+    let tooltip = props
+        .get("ToolTip")
+        .and_then(|v| v.try_clone().ok())
+        .and_then(|v| <(String, Vec<Pixmap>, String, String)>::try_from(v).ok())
+        .map(|(_, icons, title, body)| ToolTip {
+            title,
+            body,
+            icon: cache_pixmap(&icons),
+        })
+        .unwrap_or_default();
+
+    let menu = props
+        .get("Menu")
+        .and_then(|v| v.try_clone().ok())
+        .and_then(|v| OwnedObjectPath::try_from(v).ok())
+        .filter(|p| p.as_str() != "/");
+
+    TrayItem {
+        id: format!("{}{}", bus, path),
+        app_id: prop_string(props, "Id"),
+        title: prop_string(props, "Title"),
+        category: prop_string(props, "Category"),
+        status: prop_string(props, "Status"),
+        icon_name: prop_string(props, "IconName"),
+        attention_icon_name: prop_string(props, "AttentionIconName"),
+        icon_theme_path: prop_string(props, "IconThemePath"),
+        icon_pixmap: cache_pixmap(&pixmaps("IconPixmap")),
+        attention_icon_pixmap: cache_pixmap(&pixmaps("AttentionIconPixmap")),
+        tooltip,
+        item_is_menu: prop::<bool>(props, "ItemIsMenu").unwrap_or(false),
+        has_menu: menu.is_some(),
+        bus: bus.to_string(),
+        path: path.to_string(),
+        owner,
+        menu,
+    }
+}
+
+/// Write the largest pixmap as PNG into the cache, returning its path
+fn cache_pixmap(pixmaps: &[Pixmap]) -> Option<String> {
+    let (width, height, data) = pixmaps
+        .iter()
+        .filter(|(w, h, data)| pixmap_len(*w, *h).is_some_and(|len| data.len() >= len))
+        .max_by_key(|(w, h, _)| i64::from(*w) * i64::from(*h))?;
+    let data = &data[..pixmap_len(*width, *height)?];
+
+    let mut hasher = DefaultHasher::new();
+    (width, height, data).hash(&mut hasher);
+    let dir = cache_dir()?;
+    let path: PathBuf = dir.join(format!("{:016x}.png", hasher.finish()));
+    if path.exists() {
+        return Some(path.to_string_lossy().to_string());
+    }
+
+    fs::create_dir_all(&dir).ok()?;
+    let rgba = argb_to_rgba(data);
+    let file = File::create(&path).ok()?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), *width as u32, *height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let written = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgba));
+    if let Err(e) = written {
+        warn!("Failed to write tray icon {:?}: {}", path, e);
+        let _ = fs::remove_file(&path);
+        return None;
+    }
+    Some(path.to_string_lossy().to_string())
+}
+
+/// Remove every PNG in the cache that isn't in `keep`
+fn prune_cache(keep: &HashSet<String>) {
+    let Some(entries) = cache_dir().and_then(|dir| fs::read_dir(dir).ok()) else {
+        return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().is_some_and(|ext| ext == "png")
+            && !keep.contains(path.to_string_lossy().as_ref())
+        {
+            debug!("Removing unused tray icon {:?}", path);
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("terra-shell").join("tray"))
+}
+
+/// Byte length of a `width`x`height` ARGB32 pixmap, `None` if empty or absurd
+fn pixmap_len(width: i32, height: i32) -> Option<usize> {
+    let (width, height) = (usize::try_from(width).ok()?, usize::try_from(height).ok()?);
+    width
+        .checked_mul(height)?
+        .checked_mul(4)
+        .filter(|&len| len > 0)
+}
+
+/// Convert network byte order ARGB32 to RGBA
+fn argb_to_rgba(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(4)
+        .flat_map(|px| [px[1], px[2], px[3], px[0]])
+        .collect()
+}
+
+/// Convert a dbusmenu layout node into JSON, with mnemonics stripped from labels
+fn menu_to_json((id, props, children): MenuNode) -> Json {
+    let mut item = serde_json::Map::new();
+    item.insert("id".to_string(), json!(id));
+    for (key, value) in &props {
+        item.insert(key.clone(), dbus::to_json(value));
+    }
+    if let Some(Json::String(label)) = item.get("label") {
+        let label = strip_mnemonic(label);
+        item.insert("label".to_string(), json!(label));
+    }
+
+    let children: Vec<Json> = children
+        .into_iter()
+        .filter_map(|child| {
+            let value = match Value::from(child) {
+                Value::Value(inner) => *inner,
+                other => other,
+            };
+            MenuNode::try_from(value).ok().map(menu_to_json)
+        })
+        .collect();
+    item.insert("children".to_string(), Json::Array(children));
+    Json::Object(item)
+}
+
+/// Remove `_` mnemonic markers, keeping escaped `__` as `_`
+fn strip_mnemonic(label: &str) -> String {
+    let mut out = String::with_capacity(label.len());
+    let mut chars = label.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            if chars.peek() == Some(&'_') {
+                out.push('_');
+                chars.next();
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixmap_len() {
+        assert_eq!(pixmap_len(2, 3), Some(24));
+        assert_eq!(pixmap_len(0, 3), None);
+        assert_eq!(pixmap_len(-2, -3), None);
+    }
+
+    #[test]
+    fn test_argb_to_rgba() {
+        assert_eq!(argb_to_rgba(&[0xff, 0x10, 0x20, 0x30]), vec![0x10, 0x20, 0x30, 0xff]);
+    }
+
+    #[test]
+    fn test_strip_mnemonic() {
+        assert_eq!(strip_mnemonic("_Quit"), "Quit");
+        assert_eq!(strip_mnemonic("Open__file"), "Open_file");
+    }
+
+    #[test]
+    fn test_menu_to_json() {
+        let mut props = HashMap::new();
+        props.insert("label".to_string(), OwnedValue::try_from(Value::from("_Quit")).unwrap());
+        let child: MenuNode = (2, props, vec![]);
+        let child = OwnedValue::try_from(Value::new(Value::from(child))).unwrap();
+        let root: MenuNode = (0, HashMap::new(), vec![child]);
+
+        let menu = menu_to_json(root);
+        assert_eq!(menu["id"], 0);
+        assert_eq!(menu["children"][0]["id"], 2);
+        assert_eq!(menu["children"][0]["label"], "Quit");
+    }
+}
@@ -0,0 +1,429 @@
+//! Systemd unit monitoring
+//!
+//! Watches the configured user and system units through the systemd
+//! manager on the session and system bus, reporting their load, active
+//! and sub state so the bar can flag failed services. A `unit-failed`
+//! event is emitted when one enters the `failed` state. User units can
+//! be started, stopped and restarted; system units are only watched,
+//! since changing them needs polkit authorization.
+//!
+//! ```json
+//! "units": { "user": ["syncthing", "pipewire.socket"], "system": ["wg-quick@vpn"] }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as Json};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration};
+use tracing::{debug, info, warn};
+use zbus::fdo::PropertiesProxy;
+use zbus::message::Type as MessageType;
+use zbus::names::InterfaceName;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{Connection, MatchRule, MessageStream};
+
+use crate::config::Config;
+use crate::dbus::{prop, prop_string};
+use crate::events::EventBus;
+use crate::module::{self, ok, Module};
+use crate::state::Version;
+use crate::AppState;
+
+const SYSTEMD: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+const SERVICE_INTERFACE: &str = "org.freedesktop.systemd1.Service";
+
+/// Coalesce the property changes of one state transition
+const REFRESH_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct UnitsSettings {
+    /// User units, `.service` is implied without a suffix
+    user: Vec<String>,
+    /// System units
+    system: Vec<String>,
+}
+
+/// Which systemd instance a unit belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    User,
+    System,
+}
+
+/// State of one watched unit
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnitStatus {
+    pub name: String,
+    pub scope: Scope,
+    pub description: String,
+    /// `loaded`, `not-found`, `masked`...
+    pub load_state: String,
+    /// `active`, `inactive`, `failed`, `activating`...
+    pub active_state: String,
+    /// Type specific, e.g. `running`, `exited`, `dead`
+    pub sub_state: String,
+    pub failed: bool,
+    /// Service result, e.g. `success`, `exit-code`, `timeout`
+    pub result: String,
+}
+
+impl UnitStatus {
+    /// Before systemd has answered
+    fn unknown(name: &str, scope: Scope) -> Self {
+        Self {
+            name: name.to_string(),
+            scope,
+            description: String::new(),
+            load_state: String::new(),
+            active_state: "unknown".to_string(),
+            sub_state: String::new(),
+            failed: false,
+            result: String::new(),
+        }
+    }
+}
+
+/// Watched unit status and user unit control
+pub struct UnitsModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    user: Vec<String>,
+    system: Vec<String>,
+    session: Mutex<Option<Connection>>,
+}
+
+impl UnitsModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        let settings: UnitsSettings = config.section("units");
+        let user: Vec<String> = settings.user.iter().map(|u| unit_name(u)).collect();
+        let system: Vec<String> = settings.system.iter().map(|u| unit_name(u)).collect();
+        let units = user
+            .iter()
+            .map(|u| UnitStatus::unknown(u, Scope::User))
+            .chain(system.iter().map(|u| UnitStatus::unknown(u, Scope::System)))
+            .collect();
+        state.units.set(units);
+        Self {
+            state,
+            events,
+            user,
+            system,
+            session: Mutex::new(None),
+        }
+    }
+
+    fn units(&self, scope: Scope) -> &[String] {
+        match scope {
+            Scope::User => &self.user,
+            Scope::System => &self.system,
+        }
+    }
+
+    /// Re-read the units of one scope
+    async fn refresh(
+        &self,
+        connection: &Connection,
+        scope: Scope,
+        paths: &HashMap<String, OwnedObjectPath>,
+    ) {
+        for name in self.units(scope) {
+            let status = match paths.get(name) {
+                Some(path) => match unit_status(connection, name, scope, path).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        debug!("Failed to read {}: {}", name, e);
+                        continue;
+                    }
+                },
+                None => continue,
+            };
+
+            let mut newly_failed = false;
+            self.state.units.update(|units| {
+                if let Some(unit) = units
+                    .iter_mut()
+                    .find(|u| u.name == *name && u.scope == scope)
+                {
+                    newly_failed = status.failed && !unit.failed;
+                    *unit = status.clone();
+                }
+            });
+            if newly_failed {
+                warn!("Unit {} failed ({})", name, status.result);
+                self.events.emit("unit-failed", json!(status));
+            }
+        }
+    }
+
+    /// Follow one systemd instance until its bus goes away
+    async fn watch(&self, scope: Scope) {
+        if self.units(scope).is_empty() {
+            return;
+        }
+        let connection = match scope {
+            Scope::User => Connection::session().await,
+            Scope::System => Connection::system().await,
+        };
+        let connection = match connection {
+            Ok(c) => c,
+            Err(e) => {
+                warn!("Failed to connect to {:?} bus: {}", scope, e);
+                return;
+            }
+        };
+        if scope == Scope::User {
+            *self.session.lock().await = Some(connection.clone());
+        }
+
+        // Without a subscription systemd doesn't send unit property changes
+        if let Err(e) = manager_call::<()>(&connection, "Subscribe", &()).await {
+            warn!("systemd not available on the {:?} bus: {}", scope, e);
+            return;
+        }
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .sender(SYSTEMD)
+            .and_then(|b| b.interface("org.freedesktop.DBus.Properties"))
+            .and_then(|b| b.member("PropertiesChanged"))
+            .map(|b| b.build());
+        let mut signals = match rule {
+            Ok(rule) => match MessageStream::for_match_rule(rule, &connection, None).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Failed to watch systemd signals: {}", e);
+                    return;
+                }
+            },
+            Err(e) => {
+                warn!("Invalid match rule: {}", e);
+                return;
+            }
+        };
+
+        // LoadUnit also finds units that aren't running, unlike GetUnit
+        let mut paths = HashMap::new();
+        for name in self.units(scope) {
+            match manager_call::<OwnedObjectPath>(&connection, "LoadUnit", &(name,)).await {
+                Ok(path) => {
+                    paths.insert(name.clone(), path);
+                }
+                Err(e) => warn!("Unknown unit {}: {}", name, e),
+            }
+        }
+        self.refresh(&connection, scope, &paths).await;
+        info!("Watching {} {:?} units", paths.len(), scope);
+
+        while let Some(signal) = signals.next().await {
+            let Ok(signal) = signal else {
+                continue;
+            };
+            let ours = signal
+                .header()
+                .path()
+                .is_some_and(|path| paths.values().any(|p| p.as_str() == path.as_str()));
+            if !ours {
+                continue;
+            }
+            // Drain the burst, then refresh once
+            sleep(REFRESH_DELAY).await;
+            while let Ok(Some(_)) = tokio::time::timeout(Duration::ZERO, signals.next()).await {}
+            self.refresh(&connection, scope, &paths).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Module for UnitsModule {
+    fn name(&self) -> &'static str {
+        "units"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["units"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.units.version())
+    }
+
+    async fn snapshot(&self) -> Json {
+        json!({"units": self.state.units.get()})
+    }
+
+    /// `units`, `units start|stop|restart <user unit>`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Json {
+        let (action, unit) = match args {
+            [] => return json!({"type": "units", "list": self.state.units.get()}),
+            [action @ ("start" | "stop" | "restart"), unit] => (*action, unit_name(unit)),
+            _ => return module::error("usage: units [start|stop|restart <unit>]"),
+        };
+        if !self.user.contains(&unit) {
+            if self.system.contains(&unit) {
+                return module::error(format!(
+                    "{} is a system unit, only user units can be controlled",
+                    unit
+                ));
+            }
+            return module::error(format!("unknown unit: {}", unit));
+        }
+        let Some(connection) = self.session.lock().await.clone() else {
+            return module::error("systemd not available");
+        };
+
+        let method = match action {
+            "start" => "StartUnit",
+            "stop" => "StopUnit",
+            _ => "RestartUnit",
+        };
+        match manager_call::<OwnedObjectPath>(&connection, method, &(&unit, "replace")).await {
+            Ok(_job) => {
+                info!("{} {}", action, unit);
+                ok()
+            }
+            Err(e) => module::error(e.to_string()),
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        tokio::join!(self.watch(Scope::User), self.watch(Scope::System));
+    }
+
+    async fn stop(&self) {
+        self.session.lock().await.take();
+    }
+}
+
+/// `syncthing` to `syncthing.service`, other suffixes are kept
+fn unit_name(name: &str) -> String {
+    const TYPES: [&str; 11] = [
+        "service",
+        "socket",
+        "target",
+        "timer",
+        "mount",
+        "automount",
+        "path",
+        "slice",
+        "scope",
+        "device",
+        "swap",
+    ];
+    match name.rsplit_once('.') {
+        Some((_, suffix)) if TYPES.contains(&suffix) => name.to_string(),
+        _ => format!("{}.service", name),
+    }
+}
+
+/// Call a systemd manager method and deserialize its reply
+async fn manager_call<R>(
+    connection: &Connection,
+    method: &str,
+    args: &(impl Serialize + zbus::zvariant::DynamicType),
+) -> zbus::Result<R>
+where
+    R: for<'d> zbus::zvariant::DynamicDeserialize<'d>,
+{
+    connection
+        .call_method(
+            Some(SYSTEMD),
+            SYSTEMD_PATH,
+            Some(MANAGER_INTERFACE),
+            method,
+            args,
+        )
+        .await?
+        .body()
+        .deserialize()
+}
+
+/// Read a unit's state, plus the service result for services
+async fn unit_status(
+    connection: &Connection,
+    name: &str,
+    scope: Scope,
+    path: &OwnedObjectPath,
+) -> zbus::Result<UnitStatus> {
+    let proxy = PropertiesProxy::builder(connection)
+        .destination(SYSTEMD)?
+        .path(path.clone())?
+        .build()
+        .await?;
+    let unit = proxy
+        .get_all(InterfaceName::from_static_str_unchecked(UNIT_INTERFACE))
+        .await?;
+    let service = if name.ends_with(".service") {
+        proxy
+            .get_all(InterfaceName::from_static_str_unchecked(SERVICE_INTERFACE))
+            .await
+            .unwrap_or_default()
+    } else {
+        HashMap::new()
+    };
+    Ok(parse_status(name, scope, &unit, &service))
+}
+
+fn parse_status(
+    name: &str,
+    scope: Scope,
+    unit: &HashMap<String, OwnedValue>,
+    service: &HashMap<String, OwnedValue>,
+) -> UnitStatus {
+    let active_state = prop_string(unit, "ActiveState");
+    UnitStatus {
+        name: name.to_string(),
+        scope,
+        description: prop_string(unit, "Description"),
+        load_state: prop_string(unit, "LoadState"),
+        failed: active_state == "failed",
+        active_state,
+        sub_state: prop_string(unit, "SubState"),
+        result: prop::<&str>(service, "Result")
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::props;
+
+    #[test]
+    fn test_unit_name() {
+        assert_eq!(unit_name("syncthing"), "syncthing.service");
+        assert_eq!(unit_name("wg-quick@vpn"), "wg-quick@vpn.service");
+        assert_eq!(unit_name("pipewire.socket"), "pipewire.socket");
+        assert_eq!(unit_name("app.example"), "app.example.service");
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let unit = props(&[
+            ("Description", "Syncthing"),
+            ("LoadState", "loaded"),
+            ("ActiveState", "failed"),
+            ("SubState", "failed"),
+        ]);
+        let service = props(&[("Result", "exit-code")]);
+        let status = parse_status("syncthing.service", Scope::User, &unit, &service);
+        assert!(status.failed);
+        assert_eq!(status.result, "exit-code");
+        assert_eq!(json!(status)["scope"], "user");
+
+        let unit = props(&[("ActiveState", "active"), ("SubState", "listening")]);
+        let status = parse_status("pipewire.socket", Scope::User, &unit, &HashMap::new());
+        assert!(!status.failed);
+        assert_eq!(status.sub_state, "listening");
+        assert_eq!(status.result, "");
+    }
+}
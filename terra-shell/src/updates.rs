@@ -0,0 +1,379 @@
+//! Pending system updates
+//!
+//! Repo updates are found the way `checkupdates` does it: the sync
+//! databases are refreshed into a private copy under the cache dir, next
+//! to a symlink to the real `local` database, so the system's own sync
+//! databases are never touched and no partial upgrade can result. AUR
+//! updates come from the AUR helper's `-Qua`.
+//!
+//! The full check (with the download) runs every `interval` minutes. When
+//! `/var/lib/pacman/local` changes, i.e. after a pacman transaction, the
+//! list is recomputed right away against the existing copy.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::{interval, Duration};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::module::{self, Module};
+use crate::state::Version;
+use crate::AppState;
+
+/// AUR helpers tried in order when none is configured
+const AUR_HELPERS: [&str; 2] = ["paru", "yay"];
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct UpdatesSettings {
+    /// Minutes between syncs
+    interval: u64,
+    /// `paru`, `yay`, `none`, or empty to use whichever is installed
+    aur_helper: String,
+    /// pacman's database directory
+    dbpath: String,
+}
+
+impl Default for UpdatesSettings {
+    fn default() -> Self {
+        Self {
+            interval: 60,
+            aur_helper: String::new(),
+            dbpath: "/var/lib/pacman".to_string(),
+        }
+    }
+}
+
+/// A package with a newer version available
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Update {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+    pub aur: bool,
+}
+
+/// Updates domain of [`AppState`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UpdatesState {
+    pub count: usize,
+    pub packages: Vec<Update>,
+    /// Time of the last completed check
+    pub checked: Option<u64>,
+    pub checking: bool,
+    /// Why the last check failed
+    pub error: Option<String>,
+}
+
+/// Pending updates counter
+pub struct UpdatesModule {
+    state: Arc<AppState>,
+    events: EventBus,
+    settings: UpdatesSettings,
+    /// Serializes checks, which share the database copy
+    lock: Mutex<()>,
+}
+
+impl UpdatesModule {
+    pub fn new(state: Arc<AppState>, events: EventBus, config: &Config) -> Self {
+        Self {
+            state,
+            events,
+            settings: config.section("updates"),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn dbpath(&self) -> &Path {
+        Path::new(&self.settings.dbpath)
+    }
+
+    /// Recompute the update list, syncing the database copy first if `sync`
+    async fn check(&self, sync: bool) {
+        let _guard = self.lock.lock().await;
+        self.state.updates.update(|s| s.checking = true);
+
+        let result = self.pending(sync).await;
+        let changed = self.state.updates.read(|s| match &result {
+            Ok(packages) => &s.packages != packages,
+            Err(_) => false,
+        });
+        self.state.updates.update(|s| {
+            s.checking = false;
+            s.checked = Some(module::current_timestamp());
+            match result {
+                Ok(packages) => {
+                    s.count = packages.len();
+                    s.packages = packages;
+                    s.error = None;
+                }
+                Err(e) => {
+                    warn!("Update check failed: {}", e);
+                    s.error = Some(e);
+                }
+            }
+        });
+
+        if changed {
+            let s = self.state.updates.get();
+            info!("{} updates pending", s.count);
+            self.events
+                .emit("updates", json!({"count": s.count, "packages": s.packages}));
+        }
+    }
+
+    async fn pending(&self, sync: bool) -> Result<Vec<Update>, String> {
+        let db = db_copy()?;
+        prepare_db(self.dbpath(), &db).map_err(|e| format!("{}: {}", db.display(), e))?;
+        if sync {
+            // pacman refuses -Sy for non-root users, even on another dbpath
+            let status = Command::new("fakeroot")
+                .args(["--", "pacman", "-Sy", "--dbpath"])
+                .arg(&db)
+                .args(["--logfile", "/dev/null"])
+                .output()
+                .await
+                .map_err(|e| format!("fakeroot failed: {}", e))?
+                .status;
+            if !status.success() {
+                return Err(format!("pacman -Sy exited with {}", status));
+            }
+        }
+
+        // -Qu exits with 1 when there is nothing to upgrade
+        let output = Command::new("pacman")
+            .args(["-Qu", "--dbpath"])
+            .arg(&db)
+            .output()
+            .await
+            .map_err(|e| format!("pacman failed: {}", e))?;
+        let mut packages = parse_updates(&String::from_utf8_lossy(&output.stdout), false);
+
+        if let Some(helper) = self.aur_helper() {
+            match Command::new(&helper).arg("-Qua").output().await {
+                Ok(output) => packages.extend(parse_updates(
+                    &String::from_utf8_lossy(&output.stdout),
+                    true,
+                )),
+                Err(e) => debug!("{} failed: {}", helper, e),
+            }
+        }
+        Ok(packages)
+    }
+
+    fn aur_helper(&self) -> Option<String> {
+        match self.settings.aur_helper.as_str() {
+            "none" => None,
+            "" => AUR_HELPERS
+                .iter()
+                .find(|helper| in_path(helper))
+                .map(|helper| helper.to_string()),
+            helper => Some(helper.to_string()),
+        }
+    }
+
+    /// Modification time of the local database, if no transaction is running
+    fn local_modified(&self) -> Option<Option<SystemTime>> {
+        if self.dbpath().join("db.lck").exists() {
+            return None;
+        }
+        Some(
+            fs::metadata(self.dbpath().join("local"))
+                .and_then(|m| m.modified())
+                .ok(),
+        )
+    }
+}
+
+#[async_trait]
+impl Module for UpdatesModule {
+    fn name(&self) -> &'static str {
+        "updates"
+    }
+
+    fn commands(&self) -> &'static [&'static str] {
+        &["updates"]
+    }
+
+    fn version(&self) -> Option<Version> {
+        Some(self.state.updates.version())
+    }
+
+    async fn snapshot(&self) -> Value {
+        json!({"updates": self.state.updates.get()})
+    }
+
+    /// `updates [check]`
+    async fn handle(&self, _command: &str, args: &[&str]) -> Value {
+        match args {
+            [] => {}
+            ["check"] => self.check(true).await,
+            _ => return module::error("usage: updates [check]"),
+        }
+        let s = self.state.updates.get();
+        json!({
+            "type": "updates",
+            "count": s.count,
+            "packages": s.packages,
+            "checked": s.checked,
+            "error": s.error
+        })
+    }
+
+    async fn run(self: Arc<Self>) {
+        let mut sync = interval(Duration::from_secs(self.settings.interval.max(1) * 60));
+        let mut watch = interval(Duration::from_secs(2));
+        let mut seen = self.local_modified().flatten();
+        loop {
+            tokio::select! {
+                _ = sync.tick() => self.check(true).await,
+                _ = watch.tick() => {
+                    // Wait for a running transaction to finish
+                    let Some(modified) = self.local_modified() else {
+                        continue;
+                    };
+                    if modified != seen {
+                        seen = modified;
+                        debug!("pacman database changed");
+                        self.check(false).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Where the private database copy lives
+fn db_copy() -> Result<PathBuf, String> {
+    let cache = dirs::cache_dir().ok_or("no cache directory")?;
+    Ok(cache.join("terra-shell").join("checkup-db"))
+}
+
+/// Link the real local database into `db` and seed its sync databases
+/// from the system's, so a sync only downloads what changed since
+fn prepare_db(dbpath: &Path, db: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(db.join("sync"))?;
+    let local = db.join("local");
+    if fs::read_link(&local).ok().as_deref() != Some(&dbpath.join("local")) {
+        let _ = fs::remove_file(&local);
+        std::os::unix::fs::symlink(dbpath.join("local"), &local)?;
+    }
+
+    let Ok(entries) = fs::read_dir(dbpath.join("sync")) else {
+        return Ok(());
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "db") {
+            continue;
+        }
+        let target = db.join("sync").join(entry.file_name());
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let source = modified(&path);
+        if source.is_some() && modified(&target) >= source {
+            continue;
+        }
+        fs::copy(&path, &target)?;
+        // pacman compares mtimes to decide whether to download
+        if let Some(source) = source {
+            File::options()
+                .write(true)
+                .open(&target)?
+                .set_modified(source)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse `name old -> new` lines from `pacman -Qu` or an AUR helper
+fn parse_updates(output: &str, aur: bool) -> Vec<Update> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let (name, old, arrow, new) =
+                (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+            // Held back by IgnorePkg
+            if arrow != "->" || parts.next() == Some("[ignored]") {
+                return None;
+            }
+            Some(Update {
+                name: name.to_string(),
+                old: old.to_string(),
+                new: new.to_string(),
+                aur,
+            })
+        })
+        .collect()
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_updates() {
+        let output = "linux 6.9.1.arch1-1 -> 6.9.2.arch1-1\n\
+                      mesa 1:24.1.0-1 -> 1:24.1.1-1 [ignored]\n\
+                      :: Synchronizing package databases...\n\
+                      \n\
+                      python-foo 1.0-1 -> 1.1-1\n";
+        let updates = parse_updates(output, false);
+        assert_eq!(updates.len(), 2);
+        assert_eq!(
+            updates[0],
+            Update {
+                name: "linux".to_string(),
+                old: "6.9.1.arch1-1".to_string(),
+                new: "6.9.2.arch1-1".to_string(),
+                aur: false,
+            }
+        );
+        assert_eq!(updates[1].name, "python-foo");
+
+        let aur = parse_updates("hyprland-git r5000-1 -> r5010-1\n", true);
+        assert!(aur[0].aur);
+    }
+
+    #[test]
+    fn test_prepare_db() {
+        let root = std::env::temp_dir().join(format!("terra-updates-{}", std::process::id()));
+        let (dbpath, db) = (root.join("pacman"), root.join("copy"));
+        fs::create_dir_all(dbpath.join("local")).unwrap();
+        fs::create_dir_all(dbpath.join("sync")).unwrap();
+        fs::write(dbpath.join("sync/core.db"), "core").unwrap();
+        fs::write(dbpath.join("sync/core.files"), "files").unwrap();
+
+        prepare_db(&dbpath, &db).unwrap();
+        assert_eq!(
+            fs::read_link(db.join("local")).unwrap(),
+            dbpath.join("local")
+        );
+        assert_eq!(fs::read_to_string(db.join("sync/core.db")).unwrap(), "core");
+        assert!(!db.join("sync/core.files").exists());
+
+        // A newer download in the copy is kept
+        fs::write(db.join("sync/core.db"), "newer").unwrap();
+        prepare_db(&dbpath, &db).unwrap();
+        assert_eq!(
+            fs::read_to_string(db.join("sync/core.db")).unwrap(),
+            "newer"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,507 @@
+//! Hermetic harness for driving the real daemon
+//!
+//! Each [`Harness`] builds a throwaway world under the temp dir: fake
+//! Hyprland request and event sockets under `XDG_RUNTIME_DIR`, scripted
+//! `wpctl` / `nmcli` / `playerctl` / `brightnessctl` / `pw-dump` /
+//! `pacman` / `fakeroot` / `paru` as the only programs on `PATH`, a sysfs
+//! tree with a battery and keyboard LEDs, and empty config, data and cache
+//! dirs. The daemon binary is started inside it and talked to over its own
+//! IPC socket.
+//!
+//! The fake tools keep their state in plain files under `tools/`, so a
+//! test can change what a tool reports with [`Harness::set_tool`] and see
+//! what the daemon ran with [`Harness::calls`].
+
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// How long any wait gives the daemon before failing the test
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+const INSTANCE: &str = "terra-test";
+
+/// Poll `f` until it returns `Some`, panicking with `what` on timeout
+pub fn wait_until<T>(what: &str, mut f: impl FnMut() -> Option<T>) -> T {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        if let Some(value) = f() {
+            return value;
+        }
+        if Instant::now() > deadline {
+            panic!("timed out waiting for {}", what);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// A fake Hyprland instance serving `.socket.sock` and `.socket2.sock`
+pub struct FakeHyprland {
+    replies: Arc<Mutex<HashMap<String, String>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    listeners: Arc<Mutex<Vec<UnixStream>>>,
+}
+
+impl FakeHyprland {
+    fn start(dir: &Path) -> Self {
+        fs::create_dir_all(dir).unwrap();
+        let hypr = Self {
+            replies: Arc::default(),
+            requests: Arc::default(),
+            listeners: Arc::default(),
+        };
+
+        // Requests: one command per connection, answered then closed
+        let socket = UnixListener::bind(dir.join(".socket.sock")).unwrap();
+        let (replies, requests) = (hypr.replies.clone(), hypr.requests.clone());
+        thread::spawn(move || {
+            for mut stream in socket.incoming().flatten() {
+                let mut buf = [0u8; 4096];
+                let Ok(n) = stream.read(&mut buf) else {
+                    continue;
+                };
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let reply = match replies.lock().unwrap().get(&request) {
+                    Some(reply) => reply.clone(),
+                    // Dispatchers and keywords just succeed
+                    None if !request.starts_with("j/") => "ok".to_string(),
+                    None => "{}".to_string(),
+                };
+                requests.lock().unwrap().push(request);
+                let _ = stream.write_all(reply.as_bytes());
+            }
+        });
+
+        // Events: every connected reader gets each emitted line
+        let socket2 = UnixListener::bind(dir.join(".socket2.sock")).unwrap();
+        let listeners = hypr.listeners.clone();
+        thread::spawn(move || {
+            for stream in socket2.incoming().flatten() {
+                listeners.lock().unwrap().push(stream);
+            }
+        });
+
+        hypr
+    }
+
+    /// Answer `request` (e.g. `j/activewindow`) with `reply`
+    pub fn reply(&self, request: &str, reply: &str) {
+        self.replies
+            .lock()
+            .unwrap()
+            .insert(request.to_string(), reply.to_string());
+    }
+
+    /// Requests received so far, oldest first
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Wait until the daemon is reading the event socket
+    pub fn wait_for_listener(&self) {
+        wait_until("event socket listener", || {
+            (!self.listeners.lock().unwrap().is_empty()).then_some(())
+        });
+    }
+
+    /// Send an event line such as `workspace>>3`
+    pub fn emit(&self, event: &str) {
+        self.listeners
+            .lock()
+            .unwrap()
+            .retain_mut(|stream| writeln!(stream, "{}", event).is_ok());
+    }
+}
+
+/// A one-command-per-connection socket that logs requests and answers
+/// `ok`, like the ones hyprctl talks to (e.g. hyprsunset's)
+pub struct FakeSocket {
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeSocket {
+    fn start(path: &Path) -> Self {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let listener = UnixListener::bind(path).unwrap();
+        let requests: Arc<Mutex<Vec<String>>> = Arc::default();
+        let log = requests.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 4096];
+                let Ok(n) = stream.read(&mut buf) else {
+                    continue;
+                };
+                log.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&buf[..n]).into_owned());
+                let _ = stream.write_all(b"ok");
+            }
+        });
+        Self { requests }
+    }
+
+    /// Wait until `request` was received
+    pub fn wait_for(&self, request: &str) {
+        wait_until(request, || {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|r| r == request)
+                .then_some(())
+        });
+    }
+}
+
+/// A connection to the daemon's IPC socket
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    /// Events read while waiting for a reply
+    pending: VecDeque<Value>,
+}
+
+impl Client {
+    fn connect(path: &Path) -> Self {
+        let stream = UnixStream::connect(path).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        Self {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn read(&mut self) -> Value {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => panic!("daemon closed the connection"),
+            Ok(_) => serde_json::from_str(&line).unwrap(),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                panic!("timed out waiting for the daemon")
+            }
+            Err(e) => panic!("read failed: {}", e),
+        }
+    }
+
+    /// Send a command and return its reply
+    pub fn request(&mut self, command: &str) -> Value {
+        writeln!(self.writer, "{}", command).unwrap();
+        loop {
+            let message = self.read();
+            if message["type"] != "event" {
+                return message;
+            }
+            self.pending.push_back(message);
+        }
+    }
+
+    /// Next event called `name` whose payload matches `f`, skipping others
+    pub fn event(&mut self, name: &str, f: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None => self.read(),
+            };
+            if message["event"] == name && f(&message) {
+                return message;
+            }
+            if Instant::now() > deadline {
+                panic!("timed out waiting for {} event", name);
+            }
+        }
+    }
+
+    /// Poll `state` until `f` accepts it
+    pub fn wait_for_state(&mut self, what: &str, f: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let state = self.request("state");
+            if f(&state) {
+                return state;
+            }
+            if Instant::now() > deadline {
+                panic!("timed out waiting for {}, last state: {}", what, state);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// The daemon running in its own temp world
+pub struct Harness {
+    pub root: PathBuf,
+    pub hyprland: FakeHyprland,
+    socket: PathBuf,
+    daemon: Child,
+}
+
+impl Harness {
+    /// Build the default world and start the daemon in it
+    pub fn start() -> Self {
+        Self::start_with_config("{}")
+    }
+
+    /// Like [`Harness::start`] with a `config.json`, in which `{root}` is
+    /// replaced by the harness root
+    pub fn start_with_config(config: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "terra-harness-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        for dir in [
+            "bin", "tools", "run", "config", "data", "share", "cache", "home",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        let hyprland = FakeHyprland::start(&root.join("run/hypr").join(INSTANCE));
+        hyprland.reply("j/activeworkspace", r#"{"id": 1, "name": "1"}"#);
+        hyprland.reply(
+            "j/activewindow",
+            r#"{"class": "kitty", "title": "~", "address": "0x1", "mapped": true,
+                "floating": false, "fullscreen": 0}"#,
+        );
+        hyprland.reply(
+            "j/devices",
+            r#"{"keyboards": [{"name": "at-translated-set-2-keyboard", "layout": "us,de",
+                "variant": "", "active_keymap": "English (US)", "main": true}]}"#,
+        );
+        write_tools(&root);
+        write_sysfs(&root);
+        write_file(
+            &root.join("config/terra-shell/config.json"),
+            &config.replace("{root}", root.to_str().unwrap()),
+        );
+
+        let socket = root.join("terra-shell.sock");
+        let daemon = spawn(&root, &socket);
+        Self {
+            root,
+            hyprland,
+            socket,
+            daemon,
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        Client::connect(&self.socket)
+    }
+
+    /// Replace a fake tool's state file (see [`write_tools`])
+    pub fn set_tool(&self, file: &str, contents: &str) {
+        set_tool(&self.root, file, contents);
+    }
+
+    /// Command lines the fake tools were run with, oldest first
+    pub fn calls(&self) -> Vec<String> {
+        fs::read_to_string(self.root.join("tools/calls"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Wait until a fake tool was run with exactly `call`
+    pub fn wait_for_call(&self, call: &str) {
+        wait_until(call, || {
+            self.calls().iter().any(|c| c == call).then_some(())
+        });
+    }
+
+    /// Path inside the fake sysfs tree
+    pub fn sysfs(&self, path: &str) -> PathBuf {
+        self.root.join("sys").join(path)
+    }
+
+    pub fn write_sysfs(&self, path: &str, contents: &str) {
+        write_file(&self.sysfs(path), contents);
+    }
+
+    /// Serve a [`FakeSocket`] in the Hyprland instance dir, e.g.
+    /// `.hyprsunset.sock`
+    pub fn hyprland_socket(&self, name: &str) -> FakeSocket {
+        FakeSocket::start(&self.root.join("run/hypr").join(INSTANCE).join(name))
+    }
+
+    /// Write a file below the root, e.g. `share/applications/app.desktop`
+    pub fn write(&self, path: &str, contents: &str) {
+        write_file(&self.root.join(path), contents);
+    }
+}
+
+fn write_file(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn set_tool(root: &Path, file: &str, contents: &str) {
+    write_file(&root.join("tools").join(file), contents);
+}
+
+/// Start the daemon with everything it looks at pointing into `root`
+fn spawn(root: &Path, socket: &Path) -> Child {
+    let log = fs::File::create(root.join("daemon.log")).unwrap();
+    // No bus at this address, so D-Bus modules fail fast instead of reaching the host
+    let no_bus = format!("unix:path={}", root.join("no-bus").display());
+    let daemon = Command::new(env!("CARGO_BIN_EXE_terra_shell"))
+        .env_clear()
+        .env("PATH", root.join("bin"))
+        .env("HOME", root.join("home"))
+        .env("XDG_RUNTIME_DIR", root.join("run"))
+        .env("XDG_CONFIG_HOME", root.join("config"))
+        .env("XDG_DATA_HOME", root.join("data"))
+        .env("XDG_DATA_DIRS", root.join("share"))
+        .env("XDG_CACHE_HOME", root.join("cache"))
+        .env("HYPRLAND_INSTANCE_SIGNATURE", INSTANCE)
+        .env("TERRA_SHELL_SOCKET", socket)
+        .env("TERRA_SHELL_SYSFS", root.join("sys"))
+        .env("DBUS_SESSION_BUS_ADDRESS", &no_bus)
+        .env("DBUS_SYSTEM_BUS_ADDRESS", &no_bus)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().unwrap())
+        .stderr(log)
+        .spawn()
+        .unwrap();
+
+    wait_until("daemon socket", || UnixStream::connect(socket).ok());
+    daemon
+}
+
+/// A battery and keyboard LEDs
+fn write_sysfs(root: &Path) {
+    let sys = root.join("sys/class");
+    for (path, contents) in [
+        ("power_supply/BAT0/capacity", "87"),
+        ("power_supply/BAT0/status", "Discharging"),
+        ("leds/tpacpi::kbd_backlight/brightness", "1"),
+        ("leds/tpacpi::kbd_backlight/max_brightness", "2"),
+        ("leds/input3::capslock/brightness", "0"),
+        ("leds/input3::capslock/max_brightness", "1"),
+        ("leds/input3::numlock/brightness", "1"),
+        ("leds/input3::numlock/max_brightness", "1"),
+    ] {
+        write_file(&sys.join(path), &format!("{}\n", contents));
+    }
+}
+
+/// Install the fake tools. PATH holds nothing else, so the scripts stick
+/// to shell builtins.
+fn write_tools(root: &Path) {
+    set_tool(root, "sink", "Volume: 0.55\n");
+    set_tool(root, "source", "Volume: 0.30 [MUTED]\n");
+    set_tool(root, "nmcli", "no:Neighbour\nyes:home\n");
+    set_tool(root, "status", "Playing\n");
+    set_tool(root, "metadata", "Song\nArtist\n");
+    set_tool(root, "brightness", "40\n");
+    set_tool(root, "pw-dump", "[]\n");
+    set_tool(root, "repo-updates", "");
+    set_tool(root, "aur-updates", "");
+
+    let tools = root.join("tools");
+    let prelude = format!(
+        r#"#!/bin/sh
+S='{}'
+printf '%s\n' "${{0##*/}} $*" >> "$S/calls"
+out() {{ while IFS= read -r l || [ -n "$l" ]; do printf '%s\n' "$l"; done < "$S/$1"; }}
+"#,
+        tools.display()
+    );
+
+    let wpctl = r#"
+case "$2" in *SOURCE*) f=source ;; *) f=sink ;; esac
+read -r line < "$S/$f"
+case "$1" in
+get-volume) out $f ;;
+set-volume)
+    p=${3%\%}
+    case "$line" in *MUTED*) m=' [MUTED]' ;; *) m= ;; esac
+    printf 'Volume: %d.%02d%s\n' $((p / 100)) $((p % 100)) "$m" > "$S/$f" ;;
+set-mute)
+    case "$line" in
+    *MUTED*) echo "${line% \[MUTED\]}" > "$S/$f" ;;
+    *) echo "$line [MUTED]" > "$S/$f" ;;
+    esac ;;
+esac
+"#;
+    let nmcli = "out nmcli\n";
+    let pw_dump = "out pw-dump\n";
+    let pacman = r#"
+case "$1" in
+-Qu) out repo-updates ;;
+esac
+"#;
+    let fakeroot = "[ \"$1\" = -- ] && shift\n\"$@\"\n";
+    let paru = "out aur-updates\n";
+    let playerctl = r#"
+case "$1" in
+status) out status ;;
+metadata) out metadata ;;
+play-pause)
+    read -r s < "$S/status"
+    if [ "$s" = Playing ]; then echo Paused > "$S/status"; else echo Playing > "$S/status"; fi ;;
+esac
+"#;
+    let brightnessctl = r#"
+read -r b < "$S/brightness"
+case "$1" in
+info) echo "intel_backlight,backlight,$b,$b%,100" ;;
+set)
+    case "$2" in
+    +*) v=${2#+}; b=$((b + ${v%\%})) ;;
+    *-) b=$((b - ${2%\%-})) ;;
+    *) b=${2%\%} ;;
+    esac
+    [ $b -gt 100 ] && b=100
+    [ $b -lt 0 ] && b=0
+    echo $b > "$S/brightness" ;;
+esac
+"#;
+    for (name, body) in [
+        ("wpctl", wpctl),
+        ("nmcli", nmcli),
+        ("playerctl", playerctl),
+        ("brightnessctl", brightnessctl),
+        ("pw-dump", pw_dump),
+        ("pacman", pacman),
+        ("fakeroot", fakeroot),
+        ("paru", paru),
+    ] {
+        let path = root.join("bin").join(name);
+        fs::write(&path, format!("{}{}", prelude, body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // Stopped the way a session manager would, which runs the clean shutdown path
+        // SAFETY: plain kill(2) on our own child's pid
+        unsafe { libc::kill(self.daemon.id() as i32, libc::SIGTERM) };
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while matches!(self.daemon.try_wait(), Ok(None)) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+        if !thread::panicking() {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! End-to-end tests of the daemon against the fake world in `common`
+
+mod common;
+
+use std::fs;
+
+use common::{wait_until, Harness};
+
+#[test]
+fn test_initial_state() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+
+    let state = client.wait_for_state("initial values", |s| {
+        s["battery"]["level"] == 87
+            && s["audio"]["volume"] == 55
+            && s["network"]["connected"] == true
+            && s["media"]["title"] == "Song"
+            && s["brightness"] == 40
+            && s["window"]["class"] == "kitty"
+    });
+
+    // The keys the QML side binds to
+    assert_eq!(state["workspace"], 1);
+    assert_eq!(state["window"]["title"], "~");
+    assert_eq!(state["battery"]["charging"], false);
+    assert_eq!(state["audio"]["muted"], false);
+    assert_eq!(state["audio"]["mic"]["volume"], 30);
+    assert_eq!(state["audio"]["mic"]["muted"], true);
+    assert_eq!(state["network"]["ssid"], "home");
+    assert_eq!(state["media"]["artist"], "Artist");
+    assert_eq!(state["media"]["playing"], true);
+    assert_eq!(state["keyboard"]["layout"], "English (US)");
+    assert_eq!(state["keyboard_backlight"]["max"], 2);
+    assert_eq!(state["locks"]["num"], true);
+}
+
+#[test]
+fn test_hyprland_events() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("active window", |s| s["window"]["class"] == "kitty");
+    harness.hyprland.wait_for_listener();
+
+    harness.hyprland.emit("workspace>>3");
+    harness.hyprland.emit("activewindow>>firefox,Docs");
+    harness
+        .hyprland
+        .emit("activelayout>>at-translated-set-2-keyboard,German");
+    let state = client.wait_for_state("events applied", |s| {
+        s["workspace"] == 3 && s["window"]["title"] == "Docs" && s["keyboard"]["layout"] == "German"
+    });
+    assert_eq!(state["window"]["class"], "firefox");
+
+    let reply = client.request("switch-layout next");
+    assert_eq!(reply["ok"], true, "{}", reply);
+    assert!(harness
+        .hyprland
+        .requests()
+        .contains(&"switchxkblayout at-translated-set-2-keyboard next".to_string()));
+}
+
+#[test]
+fn test_volume_and_osd() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("volume", |s| s["audio"]["volume"] == 55);
+    // Startup values are only announced once the OSD module has seen them
+    wait_until("osd startup values", || {
+        (!client.request("osd")["values"]["volume"].is_null()).then_some(())
+    });
+    client.request("subscribe osd");
+
+    client.request("volume 30");
+    harness.wait_for_call("wpctl set-volume @DEFAULT_AUDIO_SINK@ 30%");
+    let event = client.event("osd", |e| e["kind"] == "volume");
+    assert_eq!(event["value"], 30);
+    assert_eq!(event["user"], true);
+
+    // Changes made behind the daemon's back are picked up too
+    harness.set_tool("sink", "Volume: 0.70 [MUTED]\n");
+    let event = client.event("osd", |e| e["kind"] == "volume" && e["value"] == 70);
+    assert_eq!(event["muted"], true);
+
+    client.request("brightness +5");
+    harness.wait_for_call("brightnessctl set +5%");
+    let event = client.event("osd", |e| e["kind"] == "brightness");
+    assert_eq!(event["value"], 45);
+}
+
+#[test]
+fn test_media_and_network() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("playing", |s| s["media"]["playing"] == true);
+
+    client.request("play-pause");
+    harness.wait_for_call("playerctl play-pause");
+    client.wait_for_state("paused", |s| s["media"]["playing"] == false);
+
+    harness.set_tool("nmcli", "no:home\n");
+    harness.set_tool("metadata", "Other\nBand\n");
+    let state = client.wait_for_state("disconnected", |s| s["network"]["connected"] == false);
+    assert!(state["network"]["ssid"].is_null());
+    assert_eq!(state["media"]["title"], "Other");
+}
+
+#[test]
+fn test_sysfs_leds() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("leds", |s| s["keyboard_backlight"]["level"] == 1);
+
+    let reply = client.request("kbd-backlight 2");
+    assert_eq!(reply["level"], 2, "{}", reply);
+    let level = fs::read_to_string(harness.sysfs("class/leds/tpacpi::kbd_backlight/brightness"));
+    assert_eq!(level.unwrap(), "2");
+
+    harness.write_sysfs("class/leds/input3::capslock/brightness", "1\n");
+    wait_until("caps lock", || {
+        (client.request("locks")["caps"] == true).then_some(())
+    });
+}
+
+#[test]
+fn test_state_since() {
+    // One sample up front, so the system domain stays unchanged afterwards
+    let harness = Harness::start_with_config(r#"{"modules": {"system": {"interval": 3600}}}"#);
+    let mut client = harness.client();
+    let state = client.wait_for_state("audio, battery and system", |s| {
+        s["audio"]["volume"] == 55 && s["battery"]["level"] == 87 && s["system"].is_object()
+    });
+    let seq = state["seq"].as_u64().unwrap();
+    assert!(state["versions"]["audio"]["seq"].as_u64().unwrap() <= seq);
+
+    client.request("volume 20");
+    let changed = client.request(&format!("state since {}", seq));
+    assert_eq!(changed["audio"]["volume"], 20);
+    assert!(changed.get("battery").is_none());
+    assert!(changed.get("system").is_none());
+    assert!(changed.get("tray").is_none());
+    assert!(changed["seq"].as_u64().unwrap() > seq);
+
+    let reply = client.request("state since soon");
+    assert!(reply["error"].is_string(), "{}", reply);
+}
+
+#[test]
+fn test_apps() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    harness.write(
+        "share/applications/org.example.Editor.desktop",
+        "[Desktop Entry]\nType=Application\nName=Editor\nExec=editor %F\n\
+         Actions=new;\n\n[Desktop Action new]\nName=New Window\nExec=editor --new\n",
+    );
+    // The user's copy hides the system one
+    harness.write(
+        "share/applications/other.desktop",
+        "[Desktop Entry]\nType=Application\nName=Other\nExec=other\n",
+    );
+    harness.write(
+        "data/applications/other.desktop",
+        "[Desktop Entry]\nHidden=true\n",
+    );
+
+    assert_eq!(client.request("apps reload")["count"], 1);
+    let reply = client.request("apps search edi");
+    assert_eq!(reply["results"][0]["id"], "org.example.Editor.desktop");
+    assert_eq!(reply["results"][0]["actions"][0]["name"], "New Window");
+
+    let reply = client.request("apps launch org.example.Editor new");
+    assert_eq!(reply["ok"], true, "{}", reply);
+    assert!(harness
+        .hyprland
+        .requests()
+        .contains(&"dispatch exec editor --new".to_string()));
+    // Saved in the background
+    wait_until("launch recorded", || {
+        fs::read_to_string(harness.root.join("data/terra-shell/apps.json"))
+            .ok()
+            .filter(|usage| usage.contains("org.example.Editor.desktop"))
+    });
+
+    assert!(client.request("apps launch other")["error"].is_string());
+    assert!(client.request("apps launch org.example.Editor new extra")["error"].is_string());
+}
+
+#[test]
+fn test_icons() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("active window", |s| s["window"]["class"] == "kitty");
+    harness.write(
+        "share/icons/hicolor/index.theme",
+        "[Icon Theme]\nName=Hicolor\nDirectories=48x48/apps,scalable/apps\n\n\
+         [48x48/apps]\nSize=48\nType=Threshold\n\n\
+         [scalable/apps]\nSize=64\nMinSize=8\nMaxSize=512\nType=Scalable\n",
+    );
+    harness.write(
+        "share/icons/hicolor/scalable/apps/accessories-text-editor.svg",
+        "<svg/>",
+    );
+    harness.write("share/icons/hicolor/48x48/apps/kitty.png", "");
+    harness.write(
+        "share/applications/org.example.Editor.desktop",
+        "[Desktop Entry]\nType=Application\nName=Editor\nExec=editor\n\
+         Icon=accessories-text-editor\nStartupWMClass=editor-main\n",
+    );
+    harness.hyprland.reply(
+        "j/clients",
+        r#"[{"address": "0x2", "class": "editor-main", "title": "notes.txt", "at": [0, 0],
+            "size": [800, 600], "workspace": {"id": 1, "name": "1"}, "monitor": 0,
+            "mapped": true, "hidden": false}]"#,
+    );
+    let editor_icon = harness
+        .root
+        .join("share/icons/hicolor/scalable/apps/accessories-text-editor.svg");
+    let editor_icon = editor_icon.to_str().unwrap();
+
+    client.request("apps reload");
+    let reply = client.request("apps search editor");
+    assert_eq!(reply["results"][0]["icon_path"], editor_icon);
+
+    // Through StartupWMClass, and the class itself as icon name
+    let clients = client.request("clients");
+    assert_eq!(clients["list"][0]["icon"], editor_icon, "{}", clients);
+    let window = client.request("window");
+    assert!(window["icon"]
+        .as_str()
+        .unwrap()
+        .ends_with("48x48/apps/kitty.png"));
+    assert!(client.request("icon missing")["path"].is_null());
+}
+
+#[test]
+fn test_privacy() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("privacy", |s| s["privacy"]["microphone"]["active"] == false);
+    harness.hyprland.wait_for_listener();
+
+    harness.set_tool(
+        "pw-dump",
+        r#"[{"id": 40, "type": "PipeWire:Interface:Node",
+             "info": {"state": "running", "props": {"media.class": "Audio/Source"}}},
+            {"id": 50, "type": "PipeWire:Interface:Node",
+             "info": {"state": "running",
+                      "props": {"media.class": "Stream/Input/Audio", "application.name": "Firefox"}}},
+            {"id": 60, "type": "PipeWire:Interface:Link",
+             "info": {"output-node-id": 40, "input-node-id": 50}}]"#,
+    );
+    // The fake pw-dump exits after one dump, so it is started again on the next check
+    client.wait_for_state("microphone", |s| s["privacy"]["microphone"]["apps"][0] == "Firefox");
+    harness.wait_for_call("pw-dump --monitor");
+    let reply = client.request("privacy");
+    assert_eq!(reply["microphone"]["active"], true, "{}", reply);
+    assert_eq!(reply["camera"]["active"], false);
+
+    harness.hyprland.emit("screencast>>1,0");
+    let state = client.wait_for_state("screencast", |s| {
+        s["privacy"]["screencast"]["active"] == true
+    });
+    assert_eq!(state["privacy"]["microphone"]["active"], true);
+}
+
+#[test]
+fn test_updates() {
+    let harness =
+        Harness::start_with_config(r#"{"modules": {"updates": {"dbpath": "{root}/pacman"}}}"#);
+    let mut client = harness.client();
+    harness.write("pacman/sync/core.db", "core");
+    fs::create_dir_all(harness.root.join("pacman/local/linux-6.9.1-1")).unwrap();
+    client.wait_for_state("first check", |s| !s["updates"]["checked"].is_null());
+
+    harness.set_tool("repo-updates", "linux 6.9.1-1 -> 6.9.2-1\n");
+    harness.set_tool("aur-updates", "hyprland-git r1-1 -> r2-1\n");
+    let reply = client.request("updates check");
+    assert_eq!(reply["count"], 2, "{}", reply);
+    assert_eq!(reply["packages"][0]["new"], "6.9.2-1");
+    assert_eq!(reply["packages"][1]["aur"], true);
+    assert!(reply["error"].is_null());
+
+    // The system database is only read, syncing happens in the copy
+    let db = harness.root.join("cache/terra-shell/checkup-db");
+    let sync = format!(
+        "fakeroot -- pacman -Sy --dbpath {} --logfile /dev/null",
+        db.display()
+    );
+    assert!(harness.calls().contains(&sync));
+    assert_eq!(fs::read_to_string(db.join("sync/core.db")).unwrap(), "core");
+
+    // A pacman transaction recounts without syncing
+    client.request("subscribe updates");
+    harness.set_tool("repo-updates", "");
+    fs::rename(
+        harness.root.join("pacman/local/linux-6.9.1-1"),
+        harness.root.join("pacman/local/linux-6.9.2-1"),
+    )
+    .unwrap();
+    let event = client.event("updates", |e| e["count"] == 1);
+    assert_eq!(event["packages"][0]["name"], "hyprland-git");
+    assert_eq!(harness.calls().iter().filter(|c| **c == sync).count(), 2);
+}
+
+#[test]
+fn test_nightlight() {
+    // Never night by the schedule, and changes apply at once
+    let harness = Harness::start_with_config(
+        r#"{"modules": {"nightlight": {"enabled": true, "start": "00:00", "end": "00:00",
+            "speed": 0}}}"#,
+    );
+    let hyprsunset = harness.hyprland_socket(".hyprsunset.sock");
+    let mut client = harness.client();
+    let state = client.wait_for_state("night light", |s| s["nightlight"]["enabled"] == true);
+    assert_eq!(state["nightlight"]["temperature"], 6500);
+    assert_eq!(state["nightlight"]["night"], false);
+
+    let reply = client.request("nightlight set 3000");
+    assert_eq!(reply["target"], 3000, "{}", reply);
+    hyprsunset.wait_for("temperature 3000");
+    client.wait_for_state("applied", |s| s["nightlight"]["temperature"] == 3000);
+
+    let reply = client.request("nightlight disable 10m");
+    assert!(reply["disabled_until"].is_u64(), "{}", reply);
+    assert_eq!(reply["target"], 6500);
+    hyprsunset.wait_for("identity");
+
+    assert!(client.request("nightlight set hot")["error"].is_string());
+    let reply = client.request("nightlight auto");
+    assert!(reply["manual"].is_null() && reply["disabled_until"].is_null());
+
+    // Above the day temperature is still a temperature, not the identity
+    client.request("nightlight set 8000");
+    hyprsunset.wait_for("temperature 8000");
+}
+
+#[test]
+fn test_submap() {
+    let harness = Harness::start();
+    let mut client = harness.client();
+    client.wait_for_state("active window", |s| s["window"]["class"] == "kitty");
+    harness.hyprland.wait_for_listener();
+    harness.hyprland.reply(
+        "j/binds",
+        r#"[{"modmask": 64, "submap": "", "key": "R", "description": "Resize mode",
+             "dispatcher": "submap", "arg": "resize"},
+            {"modmask": 0, "submap": "resize", "key": "l", "description": "",
+             "dispatcher": "resizeactive", "arg": "10 0"},
+            {"modmask": 0, "submap": "resize", "key": "escape", "description": "",
+             "dispatcher": "submap", "arg": "reset"}]"#,
+    );
+
+    // Binds are re-read when the config is
+    harness.hyprland.emit("configreloaded>>");
+    harness.hyprland.emit("submap>>resize");
+    let state = client.wait_for_state("resize submap", |s| {
+        s["submap"]["name"] == "resize" && s["submap"]["binds"].as_array().unwrap().len() == 2
+    });
+    assert_eq!(state["submap"]["binds"][0]["dispatcher"], "resizeactive");
+
+    let reply = client.request("binds default");
+    assert_eq!(reply["list"][0]["modifiers"][0], "SUPER", "{}", reply);
+    assert_eq!(client.request("binds")["list"].as_array().unwrap().len(), 3);
+
+    harness.hyprland.emit("submap>>");
+    let state = client.wait_for_state("default submap", |s| s["submap"]["name"].is_null());
+    assert!(state["submap"]["binds"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_units_without_systemd() {
+    let harness = Harness::start_with_config(
+        r#"{"modules": {"units": {"user": ["syncthing"], "system": ["wg-quick@vpn"]}}}"#,
+    );
+    let mut client = harness.client();
+    let state = client.wait_for_state("units", |s| s["units"].as_array().is_some());
+    assert_eq!(state["units"][0]["name"], "syncthing.service");
+    assert_eq!(state["units"][1]["scope"], "system");
+    assert_eq!(state["units"][1]["active_state"], "unknown");
+
+    let reply = client.request("units restart wg-quick@vpn");
+    assert!(
+        reply["error"].as_str().unwrap().contains("system unit"),
+        "{}",
+        reply
+    );
+    assert!(client.request("units start sshd")["error"].is_string());
+    // The fake bus address has no systemd behind it
+    assert!(client.request("units start syncthing")["error"].is_string());
+}